@@ -0,0 +1,52 @@
+//! Lookups behind `/sub` and trial creation, which should not grow with the cache.
+//! Run with `cargo bench --bench cache_index`
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use fcore::{
+    Connection, ConnectionStorageApiOperations, Connections, Env, Proto, Subscription,
+    SubscriptionStorageOperations, Subscriptions, Tag,
+};
+
+const CONNS_PER_SUB: usize = 5;
+
+fn populate(subs: usize) -> (Connections<Connection>, Subscriptions<Subscription>) {
+    let env = Env::from("dev");
+    let mut connections = Connections::default();
+    let mut subscriptions = Subscriptions::default();
+
+    for _ in 0..subs {
+        let sub = Subscription::default();
+        for _ in 0..CONNS_PER_SUB {
+            let conn = Connection::new(&env, Some(sub.id), Proto::new_xray(&Tag::Vmess), None);
+            connections.insert(uuid::Uuid::new_v4(), conn);
+        }
+        subscriptions.insert(sub.id, sub);
+    }
+    (connections, subscriptions)
+}
+
+fn lookups(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache_index");
+
+    for subs in [1_000, 10_000, 100_000] {
+        let (connections, subscriptions) = populate(subs);
+        let sub = subscriptions.values().next().cloned().unwrap();
+        let code = sub.refer_code.clone();
+
+        group.bench_with_input(
+            BenchmarkId::new("get_by_subscription_id", subs * CONNS_PER_SUB),
+            &sub.id,
+            |b, id| b.iter(|| connections.get_by_subscription_id(black_box(id))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("find_by_refer_code", subs),
+            &code,
+            |b, code| b.iter(|| subscriptions.find_by_refer_code(black_box(code)).is_some()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookups);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+CREATE TABLE webhook_deliveries (
+    id UUID PRIMARY KEY,
+    endpoint TEXT NOT NULL,
+    event TEXT NOT NULL,
+    dedup_key TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_error TEXT DEFAULT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ DEFAULT NULL
+);
+
+CREATE UNIQUE INDEX webhook_deliveries_endpoint_dedup_key
+ON webhook_deliveries (endpoint, dedup_key);
+
+CREATE INDEX idx_webhook_deliveries_due
+ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
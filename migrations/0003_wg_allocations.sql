@@ -0,0 +1,7 @@
+CREATE TABLE wg_allocations (
+    address INET PRIMARY KEY,
+    conn_id UUID NOT NULL,
+    allocated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_wg_allocations_conn_id ON wg_allocations (conn_id);
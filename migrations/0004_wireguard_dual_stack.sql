@@ -0,0 +1,5 @@
+ALTER TABLE nodes ALTER COLUMN address TYPE TEXT USING host(address);
+ALTER TABLE inbounds ADD COLUMN wg_address_v6 TEXT;
+ALTER TABLE connections ADD COLUMN wg_address_v6 TEXT;
+ALTER TABLE inbounds ADD COLUMN wg_awg JSONB;
+ALTER TABLE connections ADD COLUMN wg_pubkey TEXT;
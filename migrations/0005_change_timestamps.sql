@@ -0,0 +1,30 @@
+-- Change timestamps are bumped on every update, delta sync relies on them
+CREATE OR REPLACE FUNCTION touch_modified_at() RETURNS trigger AS $$
+BEGIN
+    NEW.modified_at = now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION touch_updated_at() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at = now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER connections_touch BEFORE UPDATE ON connections
+FOR EACH ROW EXECUTE FUNCTION touch_modified_at();
+
+CREATE TRIGGER nodes_touch BEFORE UPDATE ON nodes
+FOR EACH ROW EXECUTE FUNCTION touch_modified_at();
+
+CREATE TRIGGER inbounds_touch BEFORE UPDATE ON inbounds
+FOR EACH ROW EXECUTE FUNCTION touch_modified_at();
+
+CREATE TRIGGER subscriptions_touch BEFORE UPDATE ON subscriptions
+FOR EACH ROW EXECUTE FUNCTION touch_updated_at();
+
+CREATE INDEX idx_connections_modified_at ON connections(modified_at);
+CREATE INDEX idx_subscriptions_updated_at ON subscriptions(updated_at);
+CREATE INDEX idx_inbounds_modified_at ON inbounds(modified_at);
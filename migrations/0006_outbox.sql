@@ -0,0 +1,12 @@
+-- Node messages written in the same transaction as the change they describe
+CREATE TABLE outbox (
+    id BIGSERIAL PRIMARY KEY,
+    topic TEXT NOT NULL,
+    payload BYTEA NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT DEFAULT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ DEFAULT NULL
+);
+
+CREATE INDEX idx_outbox_pending ON outbox (id) WHERE delivered_at IS NULL;
@@ -1,19 +1,48 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::net::Ipv4Addr;
 
-use fcore::{Env, IpAddrMask, Result, Settings, Tag};
+use fcore::{Env, Error, IpAddrMask, PrometheusConfig, Result, Settings, Tag};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct ServiceSettings {
     pub service: ServiceConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Only read with the postgres storage backend
+    #[serde(default)]
     pub pg: PostgresConfig,
     pub metrics: MetricsRxConfig,
     pub tasks: TasksConfig,
     pub smtp: SmtpConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
 }
 
 impl Settings for ServiceSettings {
     fn validate(&self) -> Result<()> {
+        match self.storage.backend {
+            StorageBackend::Postgres => {
+                if self.pg.pool_size == 0 {
+                    return Err(Error::Custom("pg.pool_size must be greater than 0".into()));
+                }
+                if let Some(tls) = &self.pg.tls {
+                    if !std::path::Path::new(&tls.ca_cert).exists() {
+                        return Err(Error::Custom(format!(
+                            "pg.tls.ca_cert {} not found",
+                            tls.ca_cert
+                        )));
+                    }
+                }
+            }
+            StorageBackend::File => {
+                if self.storage.path.is_none() {
+                    return Err(Error::Custom(
+                        "storage.path is required for the file backend".into(),
+                    ));
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -50,6 +79,9 @@ pub struct ServiceConfig {
     pub cors_origins: Vec<String>,
     #[serde(default = "default_wg_network")]
     pub wireguard_network: IpAddrMask,
+    /// Per env IPv6 ULA prefix, peers get a /128 next to their /32
+    #[serde(default)]
+    pub wireguard_network_v6: HashMap<Env, IpAddrMask>,
     #[serde(default = "default_log_level")]
     pub log_level: String,
     pub updates_endpoint_zmq: String,
@@ -58,6 +90,41 @@ pub struct ServiceConfig {
     pub trial_limit_days: i64,
     pub trial_limit_bytes: i64,
     pub subscription_title: String,
+    /// Warm-start copy of the cache, reconciled with Postgres in the background
+    #[serde(default)]
+    pub cache_snapshot_path: Option<String>,
+}
+
+fn default_pg_pool_size() -> usize {
+    16
+}
+
+fn default_pg_connect_timeout() -> u64 {
+    5
+}
+
+fn default_pg_idle_timeout() -> u64 {
+    300
+}
+
+fn default_pg_auto_migrate() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Postgres,
+    /// Embedded single file, for one server setups without Postgres
+    File,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    pub path: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Default)]
@@ -67,13 +134,75 @@ pub struct PostgresConfig {
     pub db: String,
     pub username: String,
     pub password: String,
+    #[serde(default = "default_pg_pool_size")]
+    pub pool_size: usize,
+    #[serde(default = "default_pg_connect_timeout")]
+    pub connect_timeout_sec: u64,
+    #[serde(default = "default_pg_idle_timeout")]
+    pub idle_timeout_sec: u64,
+    /// Run a test query before handing out a reused connection
+    #[serde(default)]
+    pub verify_connections: bool,
+    #[serde(default)]
+    pub tls: Option<PgTlsConfig>,
+    /// Apply pending migrations at startup, otherwise refuse to start until `migrate` is run
+    #[serde(default = "default_pg_auto_migrate")]
+    pub auto_migrate: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PgTlsConfig {
+    /// PEM file, the server certificate must chain to it
+    pub ca_cert: String,
+    #[serde(default)]
+    pub accept_invalid_hostnames: bool,
+}
+
+fn default_cache_snapshot_interval() -> u64 {
+    300
+}
+
+fn default_db_full_sync_every() -> u64 {
+    12
+}
+
+fn default_outbox_poll_interval() -> u64 {
+    1000
+}
+
+fn default_outbox_batch_size() -> i64 {
+    100
+}
+
+fn default_outbox_retention() -> i64 {
+    24 * 60 * 60
+}
+
+fn default_node_offline_after() -> u64 {
+    300
 }
 
 #[derive(Clone, Debug, Deserialize, Default)]
 pub struct TasksConfig {
     pub db_sync_interval_sec: u64,
+    /// Every Nth sync reloads everything to catch hard deletes, the rest are deltas
+    #[serde(default = "default_db_full_sync_every")]
+    pub db_full_sync_every: u64,
     pub subscription_restore_interval: u64,
     pub subscription_expire_interval: u64,
+    #[serde(default = "default_cache_snapshot_interval")]
+    pub cache_snapshot_interval_sec: u64,
+    /// Outbox is also dispatched right after each commit
+    #[serde(default = "default_outbox_poll_interval")]
+    pub outbox_poll_interval_ms: u64,
+    #[serde(default = "default_outbox_batch_size")]
+    pub outbox_batch_size: i64,
+    /// How long delivered outbox rows are kept
+    #[serde(default = "default_outbox_retention")]
+    pub outbox_retention_sec: i64,
+    /// Nodes without metrics for this long are marked offline, 0 disables
+    #[serde(default = "default_node_offline_after")]
+    pub node_offline_after_sec: u64,
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
@@ -81,6 +210,8 @@ pub struct MetricsRxConfig {
     pub reciever: String,
     pub max_points: usize,
     pub retention_seconds: i64,
+    #[serde(default)]
+    pub prometheus: Option<PrometheusConfig>,
 }
 
 fn default_company_website() -> String {
@@ -101,3 +232,83 @@ pub struct SmtpConfig {
     #[serde(default = "default_company_website")]
     pub company_website: String,
 }
+
+fn default_webhook_poll_interval() -> u64 {
+    5
+}
+
+fn default_webhook_scan_interval() -> u64 {
+    600
+}
+
+fn default_webhook_expiring_days() -> Vec<i64> {
+    vec![3, 1]
+}
+
+fn default_webhook_max_attempts() -> i32 {
+    12
+}
+
+fn default_webhook_backoff_base() -> u64 {
+    30
+}
+
+fn default_webhook_backoff_max() -> u64 {
+    6 * 60 * 60
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
+}
+
+fn default_webhook_batch_size() -> i64 {
+    50
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhooksConfig {
+    #[serde(default)]
+    pub endpoints: Vec<WebhookEndpoint>,
+    #[serde(default = "default_webhook_poll_interval")]
+    pub poll_interval_sec: u64,
+    #[serde(default = "default_webhook_scan_interval")]
+    pub scan_interval_sec: u64,
+    /// Send subscription_expiring when this many days are left
+    #[serde(default = "default_webhook_expiring_days")]
+    pub expiring_days: Vec<i64>,
+    #[serde(default = "default_webhook_max_attempts")]
+    pub max_attempts: i32,
+    #[serde(default = "default_webhook_backoff_base")]
+    pub backoff_base_sec: u64,
+    #[serde(default = "default_webhook_backoff_max")]
+    pub backoff_max_sec: u64,
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout_sec: u64,
+    #[serde(default = "default_webhook_batch_size")]
+    pub batch_size: i64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: vec![],
+            poll_interval_sec: default_webhook_poll_interval(),
+            scan_interval_sec: default_webhook_scan_interval(),
+            expiring_days: default_webhook_expiring_days(),
+            max_attempts: default_webhook_max_attempts(),
+            backoff_base_sec: default_webhook_backoff_base(),
+            backoff_max_sec: default_webhook_backoff_max(),
+            timeout_sec: default_webhook_timeout(),
+            batch_size: default_webhook_batch_size(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    pub secret: String,
+    /// Event names to deliver, empty means all webhook events
+    #[serde(default)]
+    pub events: Vec<String>,
+}
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use fcore::{Env, NodeStatus, Tag};
+
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    SubscriptionCreated {
+        id: uuid::Uuid,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    SubscriptionUpdated {
+        id: uuid::Uuid,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    SubscriptionExtended {
+        id: uuid::Uuid,
+        days: i64,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    SubscriptionExpired {
+        id: uuid::Uuid,
+    },
+    SubscriptionExpiring {
+        id: uuid::Uuid,
+        days_left: i64,
+        expires_at: DateTime<Utc>,
+    },
+    QuotaExceeded {
+        id: uuid::Uuid,
+        limit_bytes: i64,
+        downlink_bytes: i64,
+    },
+    TrialCreated {
+        subscription_id: uuid::Uuid,
+        referred_by: Option<String>,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    ConnectionCreated {
+        id: uuid::Uuid,
+        subscription_id: Option<uuid::Uuid>,
+        env: Env,
+        proto: Tag,
+    },
+    ConnectionDeleted {
+        id: uuid::Uuid,
+    },
+    ConnectionRestored {
+        id: uuid::Uuid,
+    },
+    NodeRegistered {
+        id: uuid::Uuid,
+        env: Env,
+    },
+    NodeStatusChanged {
+        id: uuid::Uuid,
+        env: Env,
+        status: NodeStatus,
+    },
+    KeyActivated {
+        id: uuid::Uuid,
+        subscription_id: uuid::Uuid,
+        days: i16,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::SubscriptionCreated { .. } => "subscription_created",
+            Event::SubscriptionUpdated { .. } => "subscription_updated",
+            Event::SubscriptionExtended { .. } => "subscription_extended",
+            Event::SubscriptionExpired { .. } => "subscription_expired",
+            Event::SubscriptionExpiring { .. } => "subscription_expiring",
+            Event::QuotaExceeded { .. } => "quota_exceeded",
+            Event::TrialCreated { .. } => "trial_created",
+            Event::ConnectionCreated { .. } => "connection_created",
+            Event::ConnectionDeleted { .. } => "connection_deleted",
+            Event::ConnectionRestored { .. } => "connection_restored",
+            Event::NodeRegistered { .. } => "node_registered",
+            Event::NodeStatusChanged { .. } => "node_status_changed",
+            Event::KeyActivated { .. } => "key_activated",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EventEnvelope {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<EventEnvelope>,
+    seq: Arc<AtomicU64>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self {
+            tx,
+            seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        let envelope = EventEnvelope {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed) + 1,
+            timestamp: Utc::now(),
+            event,
+        };
+        tracing::debug!("Event {} #{}", envelope.event.name(), envelope.seq);
+        // No subscribers is fine
+        let _ = self.tx.send(envelope);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.tx.subscribe()
+    }
+}
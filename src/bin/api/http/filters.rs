@@ -7,7 +7,7 @@ use super::super::sync::MemSync;
 use fcore::{Env, Tag};
 
 use fcore::{
-    Connection, ConnectionApiOperations, ConnectionBaseOperations, IpAddrMask, MetricStorage,
+    Connection, ConnectionApiOperations, ConnectionBaseOperations, MetricStorage,
     NodeStorageOperations, SubscriptionOperations,
 };
 
@@ -45,12 +45,6 @@ pub fn with_param_tags(
     warp::any().map(move || param.clone())
 }
 
-pub fn with_param_ipaddrmask(
-    param: IpAddrMask,
-) -> impl Filter<Extract = (IpAddrMask,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || param.clone())
-}
-
 pub fn with_param_vec_string(
     param: Vec<String>,
 ) -> impl Filter<Extract = (Vec<String>,), Error = std::convert::Infallible> + Clone {
@@ -1,7 +1,6 @@
 use chrono::{DateTime, Utc};
 use rkyv::to_bytes;
-use std::net::{IpAddr, Ipv4Addr};
-
+use std::sync::Arc;
 use tracing::{debug, error};
 
 use fcore::{
@@ -10,9 +9,8 @@ use fcore::{
         {request::ConnType, response::Instance},
     },
     utils, Connection, ConnectionApiOperations, ConnectionBaseOperations,
-    ConnectionStorageApiOperations, InboundConnLink, IpAddrMask, NodeStorageOperations, Proto,
-    Status, Subscription, SubscriptionOperations, SubscriptionStorageOperations, Tag, Topic,
-    WgKeys, WgParam,
+    ConnectionStorageApiOperations, InboundConnLink, MetricStorage, NodeStorageOperations, Proto,
+    Status, Subscription, SubscriptionOperations, SubscriptionStorageOperations, Tag, WgKeys,
 };
 
 use super::super::{
@@ -20,6 +18,7 @@ use super::super::{
     param::ConnQueryParam,
     request::{ConnCreateRequest, ConnectionInfoRequest},
 };
+use super::metrics::{last_handshake, online_nodes};
 
 /// Handler get connection
 // POST /connections/sync
@@ -106,7 +105,6 @@ where
 pub async fn create_connection_handler<N, C, S>(
     conn_req: ConnCreateRequest,
     memory: MemSync<N, C, S>,
-    wg_network: IpAddrMask,
 ) -> Result<impl warp::Reply, warp::Rejection>
 where
     N: NodeStorageOperations + Sync + Send + Clone + 'static,
@@ -125,6 +123,12 @@ where
         return Ok(http::bad_request(&e.to_string()));
     }
 
+    let client_keys = match conn_req.wg_pubkey.as_deref().map(WgKeys::from_pubkey) {
+        Some(Ok(keys)) => Some(keys),
+        Some(Err(e)) => return Ok(http::bad_request(&e.to_string())),
+        None => None,
+    };
+
     let expired_at: Option<DateTime<Utc>> = conn_req
         .days
         .map(|days| Utc::now() + chrono::Duration::days(days.into()));
@@ -155,37 +159,22 @@ where
         }
     }
 
+    drop(mem);
+
+    let conn_id = uuid::Uuid::new_v4();
     let proto = match conn_req.proto {
-        Tag::Wireguard => {
-            let last_ip: Option<Ipv4Addr> = mem
-                .connections
-                .get_last_wg_addr()
-                .and_then(|mask| mask.as_ipv4());
-
-            let next = match last_ip {
-                Some(ip) => IpAddrMask::increment_ipv4(ip),
-                None => wg_network.first_peer_ip(),
-            };
-
-            let next = match next {
-                Some(ip) => ip,
-                None => return Ok(http::internal_error("Failed to allocate IP")),
-            };
-
-            if !wg_network.contains_ipv4(next) {
-                return Ok(http::internal_error("IP out of range"));
+        Tag::Wireguard => match SyncOp::allocate_wg_param(&memory, &conn_id, &conn_req.env).await {
+            Ok(mut param) => {
+                if let Some(keys) = client_keys {
+                    param.keys = keys;
+                }
+                Proto::Wireguard { param }
             }
-
-            Proto::Wireguard {
-                param: WgParam {
-                    keys: WgKeys::default(),
-                    address: IpAddrMask {
-                        address: IpAddr::V4(next),
-                        cidr: 32,
-                    },
-                },
+            Err(e) => {
+                error!("Failed to allocate WG address: {}", e);
+                return Ok(http::internal_error("Failed to allocate IP"));
             }
-        }
+        },
         Tag::Shadowsocks => {
             let password = utils::generate_random_password(15);
             Proto::Shadowsocks { password }
@@ -203,45 +192,25 @@ where
         }
     };
 
-    drop(mem);
-
     let conn: Connection =
         Connection::new(&conn_req.env, conn_req.subscription_id, proto, expired_at);
 
     debug!("New connection to create {}", conn);
-    let conn_id = uuid::Uuid::new_v4();
-    let msg = conn.as_create_message(&conn_id);
 
-    let messages = vec![msg];
-
-    match SyncOp::add_conn(&memory, &conn_id, conn.clone()).await {
-        Ok(Status::Ok(id)) => {
-            let bytes = match rkyv::to_bytes::<_, 1024>(&messages) {
-                Ok(b) => b,
-                Err(e) => {
-                    return Ok(http::internal_error(&format!("Serialization error: {}", e)));
-                }
-            };
-
-            let topic = if let Some(_token) = conn.get_token() {
-                // Hysteria2 uses external auth provided which handles all envs
-                Some(Topic::Auth)
-            } else if conn.get_proto().is_mtproto() {
-                None
-            } else {
-                Some(conn.get_env().into())
-            };
-
-            if let Some(topic) = topic {
-                let _ = memory.publisher.send_binary(&topic, bytes.as_ref()).await;
-            }
-
-            Ok(http::success_response(
-                format!("Connection {} has been created", id),
-                Some(id),
-                Instance::Connection(conn),
-            ))
+    // The create message is committed to the outbox together with the row
+    let status = SyncOp::add_conn(&memory, &conn_id, conn.clone()).await;
+    if !matches!(status, Ok(Status::Ok(_))) {
+        if let Some(wg) = conn.get_wireguard() {
+            let _ = SyncOp::release_wg_address(&memory, &conn_id, &wg.address).await;
         }
+    }
+
+    match status {
+        Ok(Status::Ok(id)) => Ok(http::success_response(
+            format!("Connection {} has been created", id),
+            Some(id),
+            Instance::Connection(conn),
+        )),
 
         Ok(Status::AlreadyExist(id)) => Ok(http::not_modified(&format!(
             "Connection {} already exists",
@@ -364,6 +333,7 @@ where
 pub async fn wireguard_connections_handler<N, C, S>(
     req: ConnectionInfoRequest,
     memory: MemSync<N, C, S>,
+    metrics: Arc<MetricStorage>,
 ) -> Result<Box<dyn warp::Reply + Send>, warp::Rejection>
 where
     N: NodeStorageOperations + Sync + Send + Clone + 'static,
@@ -412,6 +382,8 @@ where
                 continue;
             }
 
+            let online_nodes = online_nodes(&metrics, &conn_id);
+
             if let Some(nodes) = mem.nodes.get_by_env(&conn.get_env()) {
                 for node in nodes {
                     if let Some(inbound) = node.inbounds.get(&Tag::Wireguard) {
@@ -424,11 +396,31 @@ where
                             &node.address,
                             &node.label,
                         ) {
+                            // Amnezia app import key, only for AWG inbounds
+                            let amnezia = inbound
+                                .wg
+                                .as_ref()
+                                .and_then(|wg| wg.awg.as_ref())
+                                .and_then(|_| {
+                                    inbound
+                                        .amnezia(
+                                            &conn_id,
+                                            &c,
+                                            &node.hostname,
+                                            &node.address,
+                                            &node.label,
+                                        )
+                                        .ok()
+                                });
+
                             result.push(serde_json::json!({
                                 "conn_id": conn_id,
                                 "label": node.label,
                                 "env": node.env,
-                                "config": link
+                                "config": link,
+                                "amnezia": amnezia,
+                                "online": online_nodes.contains(&node.uuid),
+                                "last_handshake": last_handshake(&metrics, &conn_id, &node.uuid)
                             }));
                         }
                     }
@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+use tokio::sync::broadcast::error::RecvError;
+use warp::sse::Event as SseEvent;
+
+use fcore::{
+    Connection, ConnectionApiOperations, ConnectionBaseOperations, NodeStorageOperations,
+    SubscriptionOperations,
+};
+
+use super::super::{super::sync::MemSync, param::EventsQueryParams};
+
+/// Server-sent events stream of domain events
+// GET /events?types=connection_created,connection_deleted
+pub async fn events_handler<N, C, S>(
+    params: EventsQueryParams,
+    memory: MemSync<N, C, S>,
+) -> Result<impl warp::Reply, warp::Rejection>
+where
+    N: NodeStorageOperations + Sync + Send + Clone + 'static,
+    C: ConnectionApiOperations
+        + ConnectionBaseOperations
+        + Sync
+        + Send
+        + Clone
+        + 'static
+        + From<Connection>
+        + PartialEq,
+    S: SubscriptionOperations + Send + Sync + Clone + 'static,
+{
+    let types: Option<HashSet<String>> = params.types.map(|types| {
+        types
+            .split(',')
+            .map(|t| t.trim().to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect()
+    });
+
+    let rx = memory.events.subscribe();
+
+    let stream = futures::stream::unfold((rx, types), |(mut rx, types)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(envelope) => {
+                    let name = envelope.event.name();
+                    if types.as_ref().is_some_and(|t| !t.contains(name)) {
+                        continue;
+                    }
+                    let event = SseEvent::default()
+                        .id(envelope.seq.to_string())
+                        .event(name)
+                        .json_data(&envelope);
+                    return Some((event, (rx, types)));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("SSE client lagged, {} events skipped", skipped);
+                    let event = SseEvent::default()
+                        .event("lagged")
+                        .data(skipped.to_string());
+                    return Some((Ok(event), (rx, types)));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
@@ -7,7 +7,10 @@ use fcore::{
 };
 
 use super::super::{
-    super::sync::{tasks::SyncOp, MemSync},
+    super::{
+        events::Event,
+        sync::{tasks::SyncOp, MemSync},
+    },
     param::KeyQueryParams,
     request::{ActivateKeyReq, KeyReq},
 };
@@ -141,6 +144,12 @@ where
                 )));
             }
 
+            memory.events.emit(Event::KeyActivated {
+                id: key.id,
+                subscription_id: req.subscription_id,
+                days: key.days,
+            });
+
             Ok(http::success_response(
                 format!("Key {} activated", key.id),
                 Some(key.id),
@@ -1,10 +1,44 @@
 use chrono::Utc;
 use futures::{SinkExt, StreamExt};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 
 use fcore::MetricStorage;
 
+/// `user.online` samples older than this are stale, the node stopped reporting
+const ONLINE_FRESHNESS_MS: i64 = 5 * 60 * 1000;
+
+/// Xray tags user series with `conn_id`, WireGuard with `user_id`
+const USER_TAG_KEYS: [&str; 2] = ["conn_id", "user_id"];
+
+/// Nodes where the connection is online according to the latest `user.online` sample
+pub fn online_nodes(storage: &MetricStorage, conn_id: &uuid::Uuid) -> HashSet<uuid::Uuid> {
+    let min_ts = Utc::now().timestamp_millis() - ONLINE_FRESHNESS_MS;
+    let conn_id = conn_id.to_string();
+    USER_TAG_KEYS
+        .iter()
+        .flat_map(|tag_key| storage.latest_by_tag(tag_key, &conn_id, "user.online"))
+        .filter(|(_, p)| p.timestamp >= min_ts && p.value > 0.0)
+        .map(|(node_id, _)| node_id)
+        .collect()
+}
+
+pub fn is_online(storage: &MetricStorage, conn_id: &uuid::Uuid) -> bool {
+    !online_nodes(storage, conn_id).is_empty()
+}
+
+/// Unix seconds of the last WireGuard handshake of the connection on the node
+pub fn last_handshake(
+    storage: &MetricStorage,
+    conn_id: &uuid::Uuid,
+    node_id: &uuid::Uuid,
+) -> Option<i64> {
+    storage
+        .latest_by_tag("user_id", &conn_id.to_string(), "user.last_handshake")
+        .get(node_id)
+        .map(|p| p.value as i64)
+}
+
 pub async fn handle_ws_client(
     socket: warp::ws::WebSocket,
     node_id: uuid::Uuid,
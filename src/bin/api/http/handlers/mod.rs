@@ -1,4 +1,5 @@
 pub mod connection;
+pub mod events;
 pub mod key;
 pub mod metrics;
 pub mod node;
@@ -2,15 +2,16 @@ use std::sync::Arc;
 use warp::http::StatusCode;
 
 use fcore::{
-    http::{IdResponse, ResponseMessage},
-    Connection, ConnectionApiOperations, ConnectionBaseOperations, MetricStorage, NodeMetricInfo,
-    NodeResponse, NodeStatus, NodeStorageOperations, Status, Subscription, SubscriptionOperations,
+    http::{helpers as http, IdResponse, ResponseMessage},
+    Command, Connection, ConnectionApiOperations, ConnectionBaseOperations, MetricStorage,
+    NodeMetricInfo, NodeResponse, NodeStatus, NodeStorageOperations, Status, Subscription,
+    SubscriptionOperations, Topic,
 };
 
 use super::super::{
     super::sync::{tasks::SyncOp, MemSync},
     param::{NodeIdParam, NodesQueryParams},
-    request::NodeRequest,
+    request::{NodeCommandRequest, NodeRequest},
 };
 
 // Register node handler
@@ -221,3 +222,55 @@ where
         ))
     }
 }
+
+/// Send command to a node or to all nodes of env
+// POST /node/command
+pub async fn post_node_command_handler<N, C, S>(
+    req: NodeCommandRequest,
+    memory: MemSync<N, C, S>,
+) -> Result<impl warp::Reply, warp::Rejection>
+where
+    N: NodeStorageOperations + Sync + Send + Clone + 'static,
+    C: ConnectionApiOperations
+        + ConnectionBaseOperations
+        + Sync
+        + Send
+        + Clone
+        + 'static
+        + From<Connection>
+        + PartialEq,
+    Connection: From<C>,
+    S: SubscriptionOperations + Send + Sync + Clone + 'static + PartialEq + From<Subscription>,
+{
+    let topic = match (req.node_id, req.env) {
+        (Some(node_id), None) => {
+            let mem = memory.memory.read().await;
+            if mem.nodes.get_by_id(&node_id).is_none() {
+                return Ok(http::not_found("Node not found"));
+            }
+            Topic::Command(node_id)
+        }
+        (None, Some(env)) => Topic::EnvCommand(env),
+        _ => return Ok(http::bad_request("Either node_id or env must be specified")),
+    };
+
+    let cmd = Command::new(req.command);
+
+    match SyncOp::send_command(&memory, &topic, &cmd).await {
+        Ok(()) => {
+            let response = ResponseMessage::<Option<IdResponse>> {
+                status: StatusCode::OK.as_u16(),
+                message: format!("Command {} sent to {}", cmd.kind, topic),
+                response: Some(IdResponse { id: cmd.id }),
+            };
+            Ok(warp::reply::with_status(
+                warp::reply::json(&response),
+                StatusCode::OK,
+            ))
+        }
+        Err(e) => {
+            tracing::error!("Failed to send command {}: {}", cmd.id, e);
+            Ok(http::internal_error("Failed to send command"))
+        }
+    }
+}
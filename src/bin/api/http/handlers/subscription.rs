@@ -1,7 +1,6 @@
 use base64::Engine;
 use chrono::{DateTime, Utc};
 use std::collections::HashSet;
-use std::net::Ipv4Addr;
 use warp::http::{Response, StatusCode};
 
 use fcore::http::{
@@ -13,7 +12,7 @@ use fcore::http::{
 use fcore::{
     utils::get_uuid_last_octet_simple, Connection, ConnectionApiOperations,
     ConnectionBaseOperations, ConnectionStorageApiOperations, Env, Inbound, InboundClashConfig,
-    InboundConnLink, MetricStorage, NodeStorageOperations, Status, Subscription,
+    InboundConnLink, MetricStorage, NodeAddress, NodeStorageOperations, Status, Subscription,
     SubscriptionOperations, SubscriptionStorageOperations, Tag,
 };
 
@@ -22,6 +21,7 @@ use super::super::{
     param::SubIdQueryParam,
     request::{FormatReq, Subscription as SubReq, SubscriptionInfoRequest},
 };
+use super::metrics::is_online;
 
 /// Handler creates subscription
 // POST /subscription
@@ -166,7 +166,7 @@ where
 pub async fn get_subscription_info_json<N, C, S>(
     subscription_id: uuid::Uuid,
     memory: MemSync<N, C, S>,
-    _metrics: std::sync::Arc<MetricStorage>,
+    metrics: std::sync::Arc<MetricStorage>,
 ) -> Result<Box<dyn warp::Reply + Send>, warp::Rejection>
 where
     N: NodeStorageOperations + Sync + Send + Clone + 'static,
@@ -270,6 +270,13 @@ where
 
     let limit_bytes = sub.limit_bytes().unwrap_or(0);
 
+    let online = connections.is_some_and(|conns| {
+        conns
+            .iter()
+            .filter(|(_, conn)| !conn.get_deleted())
+            .any(|(conn_id, _)| is_online(&metrics, conn_id))
+    });
+
     let sub_resp = SubscriptionResponse {
         id: sub.id(),
         expires: sub.expires_at().unwrap_or_default(),
@@ -279,6 +286,7 @@ where
         locations,
         downlink,
         limit_bytes,
+        online,
     };
 
     Ok(Box::new(warp::reply::json(&sub_resp)))
@@ -327,7 +335,7 @@ where
     };
 
     let conns = mem.connections.get_by_subscription_id(&req.id);
-    let mut inbounds_list: Vec<(Inbound, uuid::Uuid, Connection, String, Ipv4Addr, String)> =
+    let mut inbounds_list: Vec<(Inbound, uuid::Uuid, Connection, String, NodeAddress, String)> =
         vec![];
 
     let tags = req.proto.tags();
@@ -352,7 +360,7 @@ where
                             conn_id,
                             conn_converted,
                             node.hostname.clone(),
-                            node.address,
+                            node.address.clone(),
                             node.label.clone(),
                         ))
                     }
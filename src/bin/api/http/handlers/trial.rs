@@ -1,22 +1,21 @@
 use chrono::{DateTime, Utc};
-use std::net::{IpAddr, Ipv4Addr};
 
 use fcore::{
     http::helpers as http, http::response::Instance, utils, utils::get_uuid_last_octet_simple,
-    Connection, ConnectionApiOperations, ConnectionBaseOperations, ConnectionStorageApiOperations,
-    Env, IpAddrMask, NodeStorageOperations, Proto, Status, Subscription, SubscriptionOperations,
-    SubscriptionStorageOperations, Tag, Topic, WgKeys, WgParam,
+    Connection, ConnectionApiOperations, ConnectionBaseOperations, Env, NodeStorageOperations,
+    Proto, Status, Subscription, SubscriptionOperations, SubscriptionStorageOperations, Tag,
 };
 
 use super::super::super::email::EmailStore;
+use super::super::super::events::Event;
 use super::super::super::sync::{tasks::SyncOp, MemSync};
 use super::super::request;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn post_trial_handler<N, C, S>(
     req: request::Trial,
     memory: MemSync<N, C, S>,
     store: EmailStore,
-    wireguard_network: IpAddrMask,
     system_refer_codes: Vec<String>,
     envs: Vec<Env>,
     protos: Vec<Tag>,
@@ -102,39 +101,15 @@ where
 
     for env in envs {
         for p in &protos {
+            let conn_id = uuid::Uuid::new_v4();
             let proto = match p {
-                Tag::Wireguard => {
-                    let mem = memory.memory.read().await;
-
-                    let last_ip: Option<Ipv4Addr> = mem
-                        .connections
-                        .get_last_wg_addr()
-                        .and_then(|mask| mask.as_ipv4());
-
-                    let next = match last_ip {
-                        Some(ip) => IpAddrMask::increment_ipv4(ip),
-                        None => wireguard_network.first_peer_ip(),
-                    };
-
-                    let next = match next {
-                        Some(ip) => ip,
-                        None => return Ok(http::internal_error("Failed to allocate IP")),
-                    };
-
-                    if !wireguard_network.contains_ipv4(next) {
-                        return Ok(http::internal_error("IP out of range"));
-                    }
-
-                    Proto::Wireguard {
-                        param: WgParam {
-                            keys: WgKeys::default(),
-                            address: IpAddrMask {
-                                address: IpAddr::V4(next),
-                                cidr: 32,
-                            },
-                        },
+                Tag::Wireguard => match SyncOp::allocate_wg_param(&memory, &conn_id, &env).await {
+                    Ok(param) => Proto::Wireguard { param },
+                    Err(e) => {
+                        tracing::error!("Failed to allocate WG address: {}", e);
+                        return Ok(http::internal_error("Failed to allocate IP"));
                     }
-                }
+                },
                 Tag::Shadowsocks => {
                     let password = utils::generate_random_password(15);
                     Proto::Shadowsocks { password }
@@ -154,36 +129,25 @@ where
             };
 
             let conn: Connection = Connection::new(&env, Some(new_sub_id), proto, None);
-            let conn_id = uuid::Uuid::new_v4();
-            let msg = conn.as_create_message(&conn_id);
-            let messages = vec![msg];
 
             match SyncOp::add_conn(&memory, &conn_id, conn.clone()).await {
-                Ok(Status::Ok(_)) => {
-                    let bytes = match rkyv::to_bytes::<_, 1024>(&messages) {
-                        Ok(b) => b,
-                        Err(e) => {
-                            return Ok(http::internal_error(&format!("Serialization error: {}", e)))
-                        }
-                    };
-
-                    let topic = if conn.get_token().is_some() {
-                        Some(Topic::Auth)
-                    } else if conn.get_proto().is_mtproto() {
-                        None
-                    } else {
-                        Some(conn.get_env().into())
-                    };
-
-                    if let Some(topic) = topic {
-                        let _ = memory.publisher.send_binary(&topic, bytes.as_ref()).await;
+                Ok(Status::Ok(_)) => {}
+                _ => {
+                    if let Some(wg) = conn.get_wireguard() {
+                        let _ = SyncOp::release_wg_address(&memory, &conn_id, &wg.address).await;
                     }
+                    continue;
                 }
-                _ => continue,
             }
         }
     }
 
+    memory.events.emit(Event::TrialCreated {
+        subscription_id: sub.id,
+        referred_by: sub.referred_by.clone(),
+        expires_at,
+    });
+
     if let Some(email) = req.user {
         let _ = store.save_trial_hmac(&email, &sub.id, &now, &ref_by).await;
     }
@@ -17,6 +17,11 @@ pub struct NodeIdParam {
     pub id: uuid::Uuid,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct EventsQueryParams {
+    pub types: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ConnQueryParam {
     pub id: uuid::Uuid,
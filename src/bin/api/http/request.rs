@@ -1,11 +1,12 @@
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
 
 use std::collections::HashSet;
 
-use fcore::{Env, Error, Inbound, Node, NodeStatus, NodeType, Tag};
+use fcore::{
+    CommandKind, Env, Error, Inbound, Node, NodeAddress, NodeStatus, NodeType, Tag, WgKeys,
+};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum TagReq {
@@ -59,11 +60,18 @@ pub struct Subscription {
     pub limit_bytes: Option<i64>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NodeCommandRequest {
+    pub node_id: Option<uuid::Uuid>,
+    pub env: Option<Env>,
+    pub command: CommandKind,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NodeRequest {
     pub env: Env,
     pub hostname: String,
-    pub address: Ipv4Addr,
+    pub address: NodeAddress,
     pub inbounds: HashMap<Tag, Inbound>,
     pub uuid: uuid::Uuid,
     pub label: String,
@@ -87,7 +95,7 @@ impl NodeRequest {
             uuid: self.uuid,
             env: self.env.clone(),
             hostname: self.hostname.clone(),
-            address: self.address,
+            address: self.address.clone(),
             inbounds: self.inbounds.clone(),
             status: NodeStatus::Online,
             created_at: now,
@@ -108,10 +116,21 @@ pub struct ConnCreateRequest {
     pub subscription_id: Option<uuid::Uuid>,
     pub proto: Tag,
     pub days: Option<u16>,
+    /// Client generated WireGuard public key, the private key never reaches the API
+    #[serde(default)]
+    pub wg_pubkey: Option<String>,
 }
 
 impl ConnCreateRequest {
     pub fn validate(&self) -> Result<(), Error> {
+        if let Some(pubkey) = &self.wg_pubkey {
+            if self.proto != Tag::Wireguard {
+                return Err(Error::Custom(
+                    "wg_pubkey is allowed only for Wireguard".into(),
+                ));
+            }
+            WgKeys::from_pubkey(pubkey)?;
+        }
         Ok(())
     }
 }
@@ -12,7 +12,8 @@ use super::{
     super::{config::ServiceConfig, service::Service},
     filters::*,
     handlers::{
-        connection::*, healthcheck_handler, key::*, metrics::*, node::*, subscription::*, trial::*,
+        connection::*, events::*, healthcheck_handler, key::*, metrics::*, node::*,
+        subscription::*, trial::*,
     },
     param::*,
     rejection,
@@ -84,6 +85,15 @@ where
             .and(with_sync(self.sync.clone()))
             .and_then(post_node_handler);
 
+        let post_node_command_route = warp::post()
+            .and(warp::path("node"))
+            .and(warp::path("command"))
+            .and(warp::path::end())
+            .and(auth.clone())
+            .and(warp::body::json::<NodeCommandRequest>())
+            .and(with_sync(self.sync.clone()))
+            .and_then(post_node_command_handler);
+
         let get_node_route = warp::get()
             .and(warp::path("node"))
             .and(warp::path::end())
@@ -139,6 +149,7 @@ where
             .and(warp::get())
             .and(warp::query::<ConnectionInfoRequest>())
             .and(with_sync(self.sync.clone()))
+            .and(with_metrics(self.metrics.clone()))
             .and_then(wireguard_connections_handler);
 
         let get_mtproto_connections_info_route = warp::path!("info" / "connections" / "mtproto")
@@ -161,7 +172,6 @@ where
             .and(auth.clone())
             .and(warp::body::json())
             .and(with_sync(self.sync.clone()))
-            .and(with_param_ipaddrmask(params.wireguard_network.clone()))
             .and_then(create_connection_handler);
 
         let delete_connection_route = warp::delete()
@@ -206,7 +216,6 @@ where
             .and(warp::body::json())
             .and(with_sync(self.sync.clone()))
             .and(with_email_store(self.email_store.clone()))
-            .and(with_param_ipaddrmask(params.wireguard_network))
             .and(with_param_vec_string(params.system_refer_codes))
             .and(with_param_envs(params.enabled_envs))
             .and(with_param_tags(params.enabled_tags))
@@ -215,6 +224,15 @@ where
             .and(with_i64(params.trial_limit_bytes))
             .and_then(post_trial_handler);
 
+        // Events
+        let get_events_route = warp::get()
+            .and(warp::path("events"))
+            .and(warp::path::end())
+            .and(auth.clone())
+            .and(warp::query::<EventsQueryParams>())
+            .and(with_sync(self.sync.clone()))
+            .and_then(events_handler);
+
         use uuid::Uuid;
         let ws_all_metrics_route = warp::path!("metrics" / "all" / Uuid / u64 / "ws")
             .and(warp::ws())
@@ -253,6 +271,7 @@ where
             .or(get_nodes_route)
             .or(get_node_route)
             .or(post_node_register_route)
+            .or(post_node_command_route)
             // Connection
             .or(post_connection_route)
             .or(post_connections_sync_route)
@@ -266,6 +285,8 @@ where
             .or(post_activate_key_route)
             //Trial
             .or(post_trial_route)
+            // Events
+            .or(get_events_route)
             // Metrics
             .or(ws_all_metrics_route)
             .or(ws_aggregate_route)
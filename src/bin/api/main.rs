@@ -3,30 +3,36 @@ use tokio::sync::RwLock;
 use tokio::time::Duration;
 
 use fcore::{
-    utils::level_from_settings, utils::measure_time, MetricStorage, Publisher, Result, Settings,
-    Subscriber, Topic, BANNER, VERSION,
+    serve_prometheus, utils::level_from_settings, utils::measure_time, Error, IpPool,
+    MetricStorage, Publisher, Result, Settings, Subscriber, Topic, BANNER, VERSION,
 };
 
 use tracing::{debug, error, info};
 
 use crate::{
-    config::ServiceSettings,
+    config::{ServiceSettings, StorageBackend},
     http::routes::Http,
     metrics::MetricWorker,
     postgres::pg::PgContext,
     service::{Cache, Service, State},
+    storage::{file::FileStorage, Db},
     sync::MemSync,
     tasks::Tasks,
+    webhooks::Webhooks,
 };
 
 mod config;
 mod email;
+mod events;
 mod http;
 mod metrics;
 mod postgres;
 mod service;
+mod snapshot;
+mod storage;
 mod sync;
 mod tasks;
+mod webhooks;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -39,6 +45,7 @@ async fn main() -> Result<()> {
     let config_path = &std::env::args()
         .nth(1)
         .expect("required config path as an argument");
+    let command: Vec<String> = std::env::args().skip(2).collect();
     println!("Config file {:?}", config_path);
 
     let settings = ServiceSettings::from_file(config_path);
@@ -50,17 +57,61 @@ async fn main() -> Result<()> {
         .with_env_filter(level_from_settings(&settings.service.log_level))
         .init();
 
-    let db = match PgContext::init(&settings.pg).await {
-        Ok(db) => db,
-        Err(err) => {
-            error!("Failed to init DB: {}", err);
-            return Err(err);
+    let db: Db = match settings.storage.backend {
+        StorageBackend::Postgres => {
+            let db = match PgContext::init(&settings.pg).await {
+                Ok(db) => db,
+                Err(err) => {
+                    error!("Failed to init DB: {}", err);
+                    return Err(err);
+                }
+            };
+
+            let migrator = db.migrator();
+            if command.first().map(String::as_str) == Some("migrate") {
+                return migrator.run_cli(&command[1..]).await;
+            }
+            if settings.pg.auto_migrate {
+                let applied = migrator.migrate().await?;
+                if applied > 0 {
+                    info!("Applied {} DB migrations", applied);
+                }
+            }
+            let schema = migrator.status().await?;
+            if !schema.pending.is_empty() {
+                error!(
+                    "DB schema is at {} with pending migrations {:?}, run `api <config> migrate`",
+                    schema.current, schema.pending
+                );
+                return Err(Error::Custom("DB schema is out of date".into()));
+            }
+            Arc::new(db)
+        }
+        StorageBackend::File => {
+            if command.first().map(String::as_str) == Some("migrate") {
+                return Err(Error::Custom(
+                    "Migrations only apply to the postgres storage backend".into(),
+                ));
+            }
+            let path = settings.storage.path.clone().unwrap_or_default();
+            info!("Using file storage {}", path);
+            Arc::new(FileStorage::open(&path).await?)
         }
     };
 
     let mem: Arc<RwLock<State>> = Arc::new(RwLock::new(Cache::new()));
     let publisher: Publisher = Publisher::new(&settings.service.updates_endpoint_zmq).await?;
-    let mem_sync = MemSync::new(mem.clone(), db.clone(), publisher);
+    let wg_pool = IpPool::new(settings.service.wireguard_network.clone())?;
+    for prefix in settings.service.wireguard_network_v6.values() {
+        IpPool::check_ipv6_prefix(prefix)?;
+    }
+    let mem_sync = MemSync::new(
+        mem.clone(),
+        db.clone(),
+        publisher,
+        wg_pool,
+        settings.service.wireguard_network_v6.clone(),
+    );
     let metric_storage = Arc::new(MetricStorage::new(
         settings.metrics.max_points,
         settings.metrics.retention_seconds,
@@ -75,7 +126,37 @@ async fn main() -> Result<()> {
         email_store.clone(),
     ));
 
-    measure_time(api_service.get_state_from_db(), "Init PostgreSQL DB").await?;
+    if measure_time(api_service.warm_start(), "Load cache snapshot").await? {
+        // Serve from the snapshot right away, changes since it are applied in the background
+        let api_service = api_service.clone();
+        tokio::spawn(async move {
+            if let Err(e) = measure_time(api_service.sync_db_changes(), "Reconcile DB").await {
+                error!("Reconcile with DB after warm start failed: {:?}", e);
+            }
+        });
+    } else {
+        measure_time(api_service.get_state_from_db(), "Init PostgreSQL DB").await?;
+    }
+
+    tokio::spawn({
+        let api_service = api_service.clone();
+        let job_interval = settings.tasks.cache_snapshot_interval_sec;
+        async move { api_service.periodic_cache_snapshot(job_interval).await }
+    });
+
+    tokio::spawn({
+        let api_service = api_service.clone();
+        let tasks = settings.tasks.clone();
+        async move {
+            api_service
+                .dispatch_outbox(
+                    tasks.outbox_poll_interval_ms,
+                    tasks.outbox_batch_size,
+                    tasks.outbox_retention_sec,
+                )
+                .await
+        }
+    });
 
     let api_service_clone = api_service.clone();
     tokio::spawn(async move {
@@ -93,6 +174,14 @@ async fn main() -> Result<()> {
 
     info!("Metrics system initialized via MetricWorker");
 
+    if let Some(config) = settings.metrics.prometheus.clone() {
+        let storage = api_service.metrics.clone();
+        let max_user_series = config.max_user_series;
+        tokio::spawn(serve_prometheus(config, move || {
+            storage.render_prometheus(max_user_series)
+        }));
+    }
+
     let metrics_storage = api_service.metrics.clone();
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
@@ -144,6 +233,40 @@ async fn main() -> Result<()> {
         }
     });
 
+    if settings.tasks.node_offline_after_sec > 0 {
+        tokio::spawn({
+            let api_service = api_service.clone();
+            let timeout_sec = settings.tasks.node_offline_after_sec;
+            info!("watch_node_liveness task started");
+
+            async move { api_service.watch_node_liveness(timeout_sec).await }
+        });
+    }
+
+    if !settings.webhooks.endpoints.is_empty() {
+        info!(
+            "Webhooks enabled for {} endpoints",
+            settings.webhooks.endpoints.len()
+        );
+
+        tokio::spawn({
+            let api_service = api_service.clone();
+            async move { api_service.run_webhook_listener().await }
+        });
+
+        tokio::spawn({
+            let api_service = api_service.clone();
+            let job_interval = settings.webhooks.scan_interval_sec;
+            async move { api_service.scan_webhook_conditions(job_interval).await }
+        });
+
+        tokio::spawn({
+            let api_service = api_service.clone();
+            let job_interval = settings.webhooks.poll_interval_sec;
+            async move { api_service.deliver_webhooks(job_interval).await }
+        });
+    }
+
     let api_service = api_service.clone();
     let service_settings = settings.service.clone();
     let service_handle = tokio::spawn(async move {
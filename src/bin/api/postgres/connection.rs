@@ -1,125 +1,39 @@
 use chrono::DateTime;
 use chrono::Utc;
-use serde::Deserialize;
-use serde::Serialize;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-use fcore::{
-    Connection, ConnectionBaseOperations, Error, IpAddrMask, Proto, Result, Tag, WgKeys, WgParam,
-};
-
-use super::pg::PgClientManager;
-
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub struct ConnRow {
-    pub conn_id: uuid::Uuid,
-    pub password: Option<String>,
-    pub env: String,
-    pub created_at: DateTime<Utc>,
-    pub modified_at: DateTime<Utc>,
-    pub expires_at: Option<DateTime<Utc>>,
-    pub subscription_id: Option<uuid::Uuid>,
-    pub wg: Option<WgParam>,
-    pub proto: Tag,
-    pub token: Option<uuid::Uuid>,
-    is_deleted: bool,
-}
-
-impl From<(uuid::Uuid, Connection)> for ConnRow {
-    fn from((conn_id, conn): (uuid::Uuid, Connection)) -> Self {
-        ConnRow {
-            conn_id,
-            password: conn.get_password(),
-            env: conn.env.to_string(),
-            created_at: conn.created_at,
-            modified_at: conn.modified_at,
-            expires_at: conn.expires_at,
-            subscription_id: conn.subscription_id,
-            wg: conn.get_wireguard().cloned(),
-            proto: conn.get_proto().proto(),
-            token: conn.get_token(),
-            is_deleted: conn.is_deleted,
-        }
-    }
-}
-
-impl TryFrom<ConnRow> for Connection {
-    type Error = Error;
-
-    fn try_from(row: ConnRow) -> Result<Self> {
-        let proto = match row.proto {
-            Tag::Wireguard => {
-                let wg = row
-                    .wg
-                    .ok_or_else(|| Error::Custom("Missing Wireguard param".into()))?;
-
-                Proto::new_wg(&wg)
-            }
-
-            Tag::Shadowsocks => {
-                let password = row
-                    .password
-                    .ok_or_else(|| Error::Custom("Missing Shadowsocks password".into()))?;
-
-                Proto::new_ss(&password)
-            }
-
-            Tag::Hysteria2 => {
-                let token = row
-                    .token
-                    .ok_or_else(|| Error::Custom("Missing Hysteria2 token".into()))?;
-                Proto::new_hysteria2(&token)
-            }
-
-            tag => Proto::new_xray(&tag),
-        };
-
-        Ok(Self {
-            env: row.env.into(),
-            proto,
-            subscription_id: row.subscription_id,
-            created_at: row.created_at,
-            modified_at: row.modified_at,
-            expires_at: row.expires_at,
-            is_deleted: row.is_deleted,
-        })
-    }
-}
+use deadpool_postgres::Transaction;
+
+use fcore::{Error, IpAddrMask, Result, Tag, WgKeys, WgParam};
+
+use super::super::storage::{ConnRepo, ConnRow, OutboxMessage};
+use super::outbox;
+use super::pg::PgPool;
+
+const CONNS_QUERY: &str = "
+    SELECT
+        id,
+        password,
+        token,
+        env,
+        created_at,
+        modified_at,
+        expires_at,
+        subscription_id,
+        proto,
+        wg_privkey,
+        wg_pubkey,
+        wg_address,
+        wg_address_v6,
+        is_deleted
+    FROM connections
+";
 
 pub struct PgConn {
-    pub manager: Arc<Mutex<PgClientManager>>,
+    pub pool: PgPool,
 }
 
 impl PgConn {
-    pub fn new(manager: Arc<Mutex<PgClientManager>>) -> Self {
-        Self { manager }
-    }
-
-    pub async fn all(&self) -> Result<Vec<ConnRow>> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
-
-        let query = "
-        SELECT
-            id,
-            password,
-            token,
-            env,
-            created_at,
-            modified_at,
-            expires_at,
-            subscription_id,
-            proto,
-            wg_privkey,
-            wg_address,
-            is_deleted
-        FROM connections
-    ";
-
-        let rows = client.query(query, &[]).await?;
-        let conns = self.map_rows_to_conns(rows);
-        Ok(conns)
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
     }
 
     fn map_rows_to_conns(&self, rows: Vec<tokio_postgres::Row>) -> Vec<ConnRow> {
@@ -136,13 +50,26 @@ impl PgConn {
                 let proto: Tag = row.get("proto");
                 let wg_privkey: Option<String> = row.get("wg_privkey");
                 let wg_address: Option<String> = row.get("wg_address");
+                let wg_address_v6: Option<IpAddrMask> = row
+                    .get::<_, Option<String>>("wg_address_v6")
+                    .and_then(|a| a.parse().ok());
                 let is_deleted: bool = row.get("is_deleted");
 
-                let wg = match (wg_privkey, wg_address) {
-                    (Some(privkey), Some(address)) => {
+                let wg_pubkey: Option<String> = row.get("wg_pubkey");
+
+                // Rows without pubkey predate client generated keys
+                let keys = match (wg_privkey, wg_pubkey) {
+                    (Some(privkey), _) => WgKeys::from_privkey(&privkey).ok(),
+                    (None, Some(pubkey)) => WgKeys::from_pubkey(&pubkey).ok(),
+                    _ => None,
+                };
+
+                let wg = match (keys, wg_address) {
+                    (Some(keys), Some(address)) => {
                         address.parse::<IpAddrMask>().ok().map(|ip_mask| WgParam {
-                            keys: WgKeys { privkey },
+                            keys,
                             address: ip_mask,
+                            address_v6: wg_address_v6,
                         })
                     }
                     _ => None,
@@ -165,31 +92,93 @@ impl PgConn {
             .collect()
     }
 
-    pub async fn delete(&self, conn_id: &uuid::Uuid) -> Result<()> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    pub(crate) async fn restore_in(
+        tx: &Transaction<'_>,
+        conn_id: &uuid::Uuid,
+        wg: Option<&WgParam>,
+    ) -> Result<()> {
+        if let Some(wg) = wg {
+            let query = "
+                UPDATE connections
+                SET wg_address = $2, wg_address_v6 = $3
+                WHERE id = $1
+            ";
+
+            tx.execute(
+                &tx.prepare_cached(query).await?,
+                &[
+                    conn_id,
+                    &wg.address.to_string(),
+                    &wg.address_v6.as_ref().map(|a| a.to_string()),
+                ],
+            )
+            .await?;
+        }
 
-        let query = "UPDATE connections SET is_deleted = true WHERE id = $1";
+        let query = "UPDATE connections SET is_deleted = false, modified_at = NOW() WHERE id = $1";
 
-        client.execute(query, &[conn_id]).await?;
+        tx.execute(&tx.prepare_cached(query).await?, &[conn_id])
+            .await?;
 
         Ok(())
     }
+}
+
+#[async_trait::async_trait]
+impl ConnRepo for PgConn {
+    async fn all(&self) -> Result<Vec<ConnRow>> {
+        let client = self.pool.get_client().await?;
+
+        let rows = client
+            .query(&client.prepare_cached(CONNS_QUERY).await?, &[])
+            .await?;
+        let conns = self.map_rows_to_conns(rows);
+        Ok(conns)
+    }
 
-    pub async fn restore(&self, conn_id: &uuid::Uuid) -> Result<()> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    async fn updated_since(&self, since: DateTime<Utc>) -> Result<Vec<ConnRow>> {
+        let client = self.pool.get_client().await?;
+
+        let query = format!("{} WHERE modified_at > $1", CONNS_QUERY);
+        let rows = client
+            .query(&client.prepare_cached(&query).await?, &[&since])
+            .await?;
+        Ok(self.map_rows_to_conns(rows))
+    }
 
-        let query = "UPDATE connections SET is_deleted = false WHERE id = $1";
+    async fn delete(&self, conn_id: &uuid::Uuid, outbox: &[OutboxMessage]) -> Result<()> {
+        let mut client = self.pool.get_client().await?;
+        let tx = client.transaction().await?;
 
-        client.execute(query, &[conn_id]).await?;
+        let query = "UPDATE connections SET is_deleted = true, modified_at = NOW() WHERE id = $1";
 
+        tx.execute(&tx.prepare_cached(query).await?, &[conn_id])
+            .await?;
+        outbox::enqueue(&tx, outbox).await?;
+
+        tx.commit().await?;
         Ok(())
     }
 
-    pub async fn insert(&self, conn: ConnRow) -> Result<()> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    async fn restore(
+        &self,
+        conn_id: &uuid::Uuid,
+        wg: Option<&WgParam>,
+        outbox: &[OutboxMessage],
+    ) -> Result<()> {
+        let mut client = self.pool.get_client().await?;
+        let tx = client.transaction().await?;
+
+        Self::restore_in(&tx, conn_id, wg).await?;
+        outbox::enqueue(&tx, outbox).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn insert(&self, conn: ConnRow, outbox: &[OutboxMessage]) -> Result<()> {
+        let mut client = self.pool.get_client().await?;
+        let tx = client.transaction().await?;
 
         let query = "
         INSERT INTO connections (
@@ -203,18 +192,20 @@ impl PgConn {
             proto,
             is_deleted,
             wg_privkey,
+            wg_pubkey,
             wg_address,
+            wg_address_v6,
             token
         )
         VALUES (
             $1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
-            $11, $12
+            $11, $12, $13, $14
         )
     ";
 
-        let result = client
+        let result = tx
             .execute(
-                query,
+                &tx.prepare_cached(query).await?,
                 &[
                     &conn.conn_id,
                     &conn.password,
@@ -225,15 +216,24 @@ impl PgConn {
                     &conn.subscription_id,
                     &conn.proto,
                     &conn.is_deleted,
-                    &conn.wg.as_ref().map(|w| &w.keys.privkey),
+                    &conn.wg.as_ref().and_then(|w| w.keys.privkey.as_ref()),
+                    &conn.wg.as_ref().map(|w| &w.keys.pubkey),
                     &conn.wg.as_ref().map(|w| w.address.to_string()),
+                    &conn
+                        .wg
+                        .as_ref()
+                        .and_then(|w| w.address_v6.as_ref().map(|a| a.to_string())),
                     &conn.token,
                 ],
             )
             .await;
 
         match result {
-            Ok(_) => Ok(()),
+            Ok(_) => {
+                outbox::enqueue(&tx, outbox).await?;
+                tx.commit().await?;
+                Ok(())
+            }
             Err(e) => {
                 if let Some(code) = e.code() {
                     if code == &tokio_postgres::error::SqlState::UNIQUE_VIOLATION {
@@ -0,0 +1,69 @@
+use std::net::IpAddr;
+
+use super::super::storage::IpPoolRepo;
+use super::pg::PgPool;
+
+use fcore::Result;
+
+pub struct PgIpPool {
+    pub pool: PgPool,
+}
+
+impl PgIpPool {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl IpPoolRepo for PgIpPool {
+    async fn all(&self) -> Result<Vec<(IpAddr, uuid::Uuid)>> {
+        let client = self.pool.get_client().await?;
+
+        let rows = client
+            .query(
+                &client
+                    .prepare_cached("SELECT address, conn_id FROM wg_allocations")
+                    .await?,
+                &[],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("address"), row.get("conn_id")))
+            .collect())
+    }
+
+    async fn insert(&self, address: &IpAddr, conn_id: &uuid::Uuid) -> Result<bool> {
+        let client = self.pool.get_client().await?;
+
+        let query = "
+            INSERT INTO wg_allocations (address, conn_id)
+            VALUES ($1, $2)
+            ON CONFLICT (address) DO UPDATE SET conn_id = EXCLUDED.conn_id
+            WHERE wg_allocations.conn_id = EXCLUDED.conn_id
+        ";
+
+        let affected = client
+            .execute(&client.prepare_cached(query).await?, &[address, conn_id])
+            .await?;
+        Ok(affected > 0)
+    }
+
+    async fn delete(&self, address: &IpAddr, conn_id: &uuid::Uuid) -> Result<()> {
+        let client = self.pool.get_client().await?;
+
+        client
+            .execute(
+                &client
+                    .prepare_cached(
+                        "DELETE FROM wg_allocations WHERE address = $1 AND conn_id = $2",
+                    )
+                    .await?,
+                &[address, conn_id],
+            )
+            .await?;
+        Ok(())
+    }
+}
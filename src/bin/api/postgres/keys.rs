@@ -1,24 +1,25 @@
 use chrono::DateTime;
 use chrono::Utc;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
-use super::pg::PgClientManager;
+use super::super::storage::KeyRepo;
+use super::pg::PgPool;
 
 use fcore::{Key, Result};
 
 pub struct PgKey {
-    pub manager: Arc<Mutex<PgClientManager>>,
+    pub pool: PgPool,
 }
 
 impl PgKey {
-    pub fn new(manager: Arc<Mutex<PgClientManager>>) -> Self {
-        Self { manager }
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
     }
+}
 
-    pub async fn get(&self, code: &str) -> Option<Key> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await.ok()?;
+#[async_trait::async_trait]
+impl KeyRepo for PgKey {
+    async fn get(&self, code: &str) -> Option<Key> {
+        let client = self.pool.get_client().await.ok()?;
 
         let query = "
             SELECT id, code, activated, created_at, modified_at, subscription_id, days, distributor
@@ -26,14 +27,14 @@ impl PgKey {
             WHERE code = $1
         ";
 
-        let rows = client.query(query, &[&code]).await.ok()?;
+        let stmt = client.prepare_cached(query).await.ok()?;
+        let rows = client.query(&stmt, &[&code]).await.ok()?;
 
         rows.first().cloned().map(|r| r.into())
     }
 
-    pub async fn insert(&self, key: &Key) -> Result<()> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    async fn insert(&self, key: &Key) -> Result<()> {
+        let client = self.pool.get_client().await?;
 
         let query = "
                INSERT INTO keys (id, code, activated, created_at, modified_at, subscription_id, days, distributor)
@@ -42,7 +43,7 @@ impl PgKey {
 
         client
             .execute(
-                query,
+                &client.prepare_cached(query).await?,
                 &[
                     &key.id,
                     &key.code,
@@ -59,21 +60,23 @@ impl PgKey {
         Ok(())
     }
 
-    pub async fn activate(&self, key: &Key) -> Result<()> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    async fn activate(&self, key: &Key) -> Result<()> {
+        let mut client = self.pool.get_client().await?;
 
         let tx = client.transaction().await?;
 
         let modified_at: DateTime<Utc> = Utc::now();
         tx.execute(
-            "
+            &tx.prepare_cached(
+                "
             UPDATE keys
             SET activated = true,
                 subscription_id = $1,
                 modified_at = $2
             WHERE id = $3
             ",
+            )
+            .await?,
             &[&key.subscription_id, &modified_at, &key.id],
         )
         .await?;
@@ -0,0 +1,280 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use tracing::{info, warn};
+
+use fcore::{Error, Result};
+
+use super::pg::PgPool;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.sql.as_bytes()))
+    }
+}
+
+/// Ordered by version, applied ones must never be edited
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline",
+        sql: include_str!("../../../../migrations/0001_baseline.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "webhook_deliveries",
+        sql: include_str!("../../../../migrations/0002_webhook_deliveries.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "wg_allocations",
+        sql: include_str!("../../../../migrations/0003_wg_allocations.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "wireguard_dual_stack",
+        sql: include_str!("../../../../migrations/0004_wireguard_dual_stack.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "change_timestamps",
+        sql: include_str!("../../../../migrations/0005_change_timestamps.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "outbox",
+        sql: include_str!("../../../../migrations/0006_outbox.sql"),
+    },
+];
+
+/// Serializes migrations across API instances
+const MIGRATION_LOCK: i64 = 0x6663_6f72_6500;
+
+const CREATE_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        checksum TEXT NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    )
+";
+
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub current: i32,
+    pub latest: i32,
+    pub pending: Vec<i32>,
+}
+
+pub struct PgMigrator {
+    pub pool: PgPool,
+}
+
+impl PgMigrator {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Applied versions with their checksums
+    async fn applied(&self) -> Result<BTreeMap<i32, String>> {
+        let client = self.pool.get_client().await?;
+        client.batch_execute(CREATE_TABLE).await?;
+
+        let rows = client
+            .query("SELECT version, checksum FROM schema_migrations", &[])
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("version"), row.get("checksum")))
+            .collect())
+    }
+
+    /// Errors if the database was migrated by a newer build or was
+    /// created by hand without a migration history
+    pub async fn status(&self) -> Result<MigrationStatus> {
+        let applied = self.applied().await?;
+        let current = applied.keys().max().copied().unwrap_or(0);
+        let latest = latest_version();
+
+        if current > latest {
+            return Err(Error::Custom(format!(
+                "Database schema version {} is newer than supported {}, upgrade the api binary",
+                current, latest
+            )));
+        }
+
+        if applied.is_empty() && self.has_legacy_schema().await? {
+            return Err(Error::Custom(
+                "Database has tables but no migration history, \
+                 mark the matching version with `migrate baseline <version>`"
+                    .into(),
+            ));
+        }
+
+        for migration in MIGRATIONS {
+            if let Some(checksum) = applied.get(&migration.version) {
+                if *checksum != migration.checksum() {
+                    warn!(
+                        "Migration {} ({}) changed after it was applied",
+                        migration.version, migration.name
+                    );
+                }
+            }
+        }
+
+        let pending = MIGRATIONS
+            .iter()
+            .filter(|m| !applied.contains_key(&m.version))
+            .map(|m| m.version)
+            .collect();
+
+        Ok(MigrationStatus {
+            current,
+            latest,
+            pending,
+        })
+    }
+
+    async fn has_legacy_schema(&self) -> Result<bool> {
+        let client = self.pool.get_client().await?;
+        let row = client
+            .query_one(
+                "SELECT to_regclass('public.connections') IS NOT NULL AS exists",
+                &[],
+            )
+            .await?;
+        Ok(row.get("exists"))
+    }
+
+    /// Applies pending migrations, each in its own transaction.
+    /// Returns how many were applied
+    pub async fn migrate(&self) -> Result<usize> {
+        let pending = self.status().await?.pending;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let mut client = self.pool.get_client().await?;
+        client
+            .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK])
+            .await?;
+
+        let result = async {
+            let mut count = 0;
+            for migration in MIGRATIONS.iter().filter(|m| pending.contains(&m.version)) {
+                let tx = client.transaction().await?;
+
+                // Another instance may have applied it while we waited for the lock
+                let done = tx
+                    .query_opt(
+                        "SELECT 1 FROM schema_migrations WHERE version = $1",
+                        &[&migration.version],
+                    )
+                    .await?
+                    .is_some();
+                if done {
+                    continue;
+                }
+
+                info!(
+                    "Applying migration {} ({})",
+                    migration.version, migration.name
+                );
+                tx.batch_execute(migration.sql).await.map_err(|e| {
+                    Error::Custom(format!(
+                        "Migration {} ({}) failed: {}",
+                        migration.version, migration.name, e
+                    ))
+                })?;
+                tx.execute(
+                    "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+                    &[&migration.version, &migration.name, &migration.checksum()],
+                )
+                .await?;
+                tx.commit().await?;
+                count += 1;
+            }
+            Ok::<usize, Error>(count)
+        }
+        .await;
+
+        client
+            .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK])
+            .await?;
+
+        result
+    }
+
+    /// Records migrations up to `version` as applied without running them,
+    /// for databases created from the old hand-written schema
+    pub async fn baseline(&self, version: i32) -> Result<()> {
+        if !MIGRATIONS.iter().any(|m| m.version == version) {
+            return Err(Error::Custom(format!(
+                "Unknown migration version {}",
+                version
+            )));
+        }
+
+        self.applied().await?;
+        let client = self.pool.get_client().await?;
+        for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+            client
+                .execute(
+                    "INSERT INTO schema_migrations (version, name, checksum)
+                     VALUES ($1, $2, $3) ON CONFLICT (version) DO NOTHING",
+                    &[&migration.version, &migration.name, &migration.checksum()],
+                )
+                .await?;
+        }
+        info!("Schema marked as migrated up to version {}", version);
+        Ok(())
+    }
+
+    /// `migrate`, `migrate status` or `migrate baseline <version>`
+    pub async fn run_cli(&self, args: &[String]) -> Result<()> {
+        match args
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [] => {
+                let applied = self.migrate().await?;
+                println!(
+                    "Applied {} migrations, schema is at {}",
+                    applied,
+                    latest_version()
+                );
+            }
+            ["status"] => {
+                let status = self.status().await?;
+                println!(
+                    "Schema version {} of {}, pending: {:?}",
+                    status.current, status.latest, status.pending
+                );
+            }
+            ["baseline", version] => {
+                let version = version
+                    .parse()
+                    .map_err(|_| Error::Custom(format!("Invalid version {}", version)))?;
+                self.baseline(version).await?;
+            }
+            _ => {
+                return Err(Error::Custom(
+                    "Usage: api <config> migrate [status | baseline <version>]".into(),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,9 @@
 pub(crate) mod connection;
+pub(crate) mod ip_pool;
 pub(crate) mod keys;
+pub(crate) mod migrate;
 pub(crate) mod node;
+pub(crate) mod outbox;
 pub(crate) mod pg;
 pub(crate) mod subscription;
+pub(crate) mod webhook;
@@ -2,34 +2,148 @@ use chrono::DateTime;
 use chrono::Utc;
 use std::collections::HashMap;
 use std::net::IpAddr;
-use std::net::Ipv4Addr;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
 use tracing::{debug, error, warn};
 
 use fcore::{
-    H2Settings, Inbound, IpAddrMask, Node, NodeStatus, NodeType, Result, WgKeys, WireguardSettings,
+    H2Settings, Inbound, IpAddrMask, Node, NodeAddress, NodeStatus, NodeType, Result, WgKeys,
+    WireguardSettings,
 };
 
-use super::pg::PgClientManager;
+use super::super::storage::NodeRepo;
+use super::pg::PgPool;
+
+const NODES_QUERY: &str = "
+    SELECT
+        n.id AS node_id, n.uuid, n.env, n.hostname, n.address, n.status,
+        n.created_at, n.modified_at, n.label, n.interface,
+        n.cores, n.max_bandwidth_bps, n.country, n.node_type,
+        i.id AS inbound_id, i.tag, i.port, i.stream_settings, i.uplink, i.downlink,
+        i.conn_count, i.wg_privkey, i.wg_interface, i.wg_address, i.wg_address_v6,
+        i.wg_awg, i.dns, i.h2, i.mtproto_secret
+    FROM nodes n
+    LEFT JOIN inbounds i ON n.id = i.node_id
+";
 
 pub struct PgNode {
-    pub manager: Arc<Mutex<PgClientManager>>,
+    pub pool: PgPool,
 }
 
 impl PgNode {
-    pub fn new(manager: Arc<Mutex<PgClientManager>>) -> Self {
-        Self { manager }
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
     }
 
-    pub async fn upsert(&self, node_id: uuid::Uuid, node: Node) -> Result<()> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    fn map_rows_to_nodes(rows: Vec<tokio_postgres::Row>) -> Vec<Node> {
+        let mut nodes_map: HashMap<uuid::Uuid, Node> = HashMap::new();
+
+        for row in rows {
+            let node_id: uuid::Uuid = row.get("node_id");
+            let uuid: uuid::Uuid = row.get("uuid");
+            let env: String = row.get("env");
+            let hostname: String = row.get("hostname");
+            let address: String = row.get("address");
+            let status: NodeStatus = row.get("status");
+            let created_at: DateTime<Utc> = row.get("created_at");
+            let modified_at: DateTime<Utc> = row.get("modified_at");
+            let label: String = row.get("label");
+            let interface: String = row.get("interface");
+            let cores: i32 = row.get("cores");
+            let country: String = row.get("country");
+            let max_bandwidth_bps: i64 = row.get("max_bandwidth_bps");
+            let r#type: NodeType = row.get("node_type");
+
+            let wg_address: Option<IpAddrMask> = row
+                .get::<_, Option<String>>("wg_address")
+                .and_then(|s| s.parse().ok());
+
+            let wg_address_v6: Option<IpAddrMask> = row
+                .get::<_, Option<String>>("wg_address_v6")
+                .and_then(|s| s.parse().ok());
+
+            let dns: Option<Vec<IpAddr>> = row.get("dns");
+            let inbound_id: Option<uuid::Uuid> = row.get("inbound_id");
+
+            let h2: Option<H2Settings> = row
+                .get::<_, Option<serde_json::Value>>("h2")
+                .and_then(|v| serde_json::from_value(v).ok());
+
+            if let Ok(address) = address.parse::<NodeAddress>() {
+                let node_entry = nodes_map.entry(node_id).or_insert_with(|| Node {
+                    uuid,
+                    env: env.into(),
+                    hostname: hostname.clone(),
+                    address,
+                    interface: interface.clone(),
+                    status,
+                    created_at,
+                    modified_at,
+                    label: label.clone(),
+                    inbounds: HashMap::new(),
+                    cores: cores as usize,
+                    max_bandwidth_bps,
+                    country,
+                    r#type,
+                });
+
+                if let Some(_inbound_id) = inbound_id {
+                    let wg = match (
+                        row.get::<_, Option<String>>("wg_privkey"),
+                        row.get::<_, Option<String>>("wg_interface"),
+                        wg_address,
+                        dns,
+                    ) {
+                        (Some(privkey), Some(interface), Some(address), Some(dns)) => {
+                            WgKeys::from_privkey(&privkey)
+                                .ok()
+                                .map(|keys| WireguardSettings {
+                                    keys,
+                                    interface,
+                                    address,
+                                    address_v6: wg_address_v6,
+                                    port: row.get::<_, i32>("port") as u16,
+                                    dns,
+                                    awg: row
+                                        .get::<_, Option<serde_json::Value>>("wg_awg")
+                                        .and_then(|v| serde_json::from_value(v).ok()),
+                                })
+                        }
+                        _ => None,
+                    };
+
+                    let mtproto_secret = row.get::<_, Option<String>>("mtproto_secret");
+
+                    let inbound = Inbound {
+                        tag: row.get("tag"),
+                        port: row.get::<_, i32>("port") as u16,
+                        stream_settings: row
+                            .get::<_, Option<serde_json::Value>>("stream_settings")
+                            .and_then(|v| serde_json::from_value(v).ok()),
+                        uplink: row.get("uplink"),
+                        downlink: row.get("downlink"),
+                        conn_count: row.get("conn_count"),
+                        wg,
+                        h2,
+                        mtproto_secret,
+                    };
+
+                    node_entry.inbounds.insert(inbound.tag, inbound);
+                }
+            }
+        }
+
+        nodes_map.into_values().collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl NodeRepo for PgNode {
+    async fn upsert(&self, node_id: uuid::Uuid, node: Node) -> Result<()> {
+        let mut client = self.pool.get_client().await?;
         let tx = client.transaction().await?;
         let cores = node.cores as i32;
 
-        let address: IpAddr = IpAddr::V4(node.address);
+        let address = node.address.to_string();
 
         let node_query = "
         INSERT INTO nodes (
@@ -53,7 +167,7 @@ impl PgNode {
     ";
 
         tx.execute(
-            node_query,
+            &tx.prepare_cached(node_query).await?,
             &[
                 &node_id,
                 &node.uuid,
@@ -76,12 +190,12 @@ impl PgNode {
         INSERT INTO inbounds (
             id, node_id, tag, port, stream_settings,
             uplink, downlink, conn_count,
-            wg_privkey, wg_interface, wg_address, dns, h2, mtproto_secret
+            wg_privkey, wg_interface, wg_address, wg_address_v6, wg_awg, dns, h2, mtproto_secret
         )
         VALUES (
             $1, $2, $3, $4, $5,
             $6, $7, $8,
-            $9, $10, $11, $12, $13, $14
+            $9, $10, $11, $12, $13, $14, $15, $16
         )
         ON CONFLICT (node_id, tag) DO UPDATE SET
             port = EXCLUDED.port,
@@ -92,37 +206,41 @@ impl PgNode {
             wg_privkey = EXCLUDED.wg_privkey,
             wg_interface = EXCLUDED.wg_interface,
             wg_address = EXCLUDED.wg_address,
+            wg_address_v6 = EXCLUDED.wg_address_v6,
+            wg_awg = EXCLUDED.wg_awg,
             dns = EXCLUDED.dns,
             h2 = EXCLUDED.h2,
             mtproto_secret = EXCLUDED.mtproto_secret
     ";
 
+        let inbound_stmt = tx.prepare_cached(inbound_query).await?;
         for inbound in node.inbounds.values() {
             let inbound_id = uuid::Uuid::new_v4();
             let stream_settings = serde_json::to_value(&inbound.stream_settings)?;
             let h2_settings = serde_json::to_value(&inbound.h2)?;
+            let wg_awg = inbound
+                .wg
+                .as_ref()
+                .and_then(|wg| wg.awg.as_ref())
+                .map(serde_json::to_value)
+                .transpose()?;
 
-            let (wg_privkey, wg_interface, wg_address, dns) = inbound
+            let (wg_privkey, wg_interface, wg_address, wg_address_v6, dns) = inbound
                 .wg
                 .as_ref()
                 .map(|wg| {
                     (
-                        Some(&wg.keys.privkey),
+                        wg.keys.privkey.as_ref(),
                         Some(&wg.interface),
                         Some(wg.address.to_string()),
-                        Some(
-                            wg.dns
-                                .iter()
-                                .cloned()
-                                .map(IpAddr::V4)
-                                .collect::<Vec<IpAddr>>(),
-                        ),
+                        wg.address_v6.as_ref().map(|a| a.to_string()),
+                        Some(wg.dns.clone()),
                     )
                 })
-                .unwrap_or((None, None, None, None));
+                .unwrap_or((None, None, None, None, None));
 
             tx.execute(
-                inbound_query,
+                &inbound_stmt,
                 &[
                     &inbound_id,
                     &node_id,
@@ -135,6 +253,8 @@ impl PgNode {
                     &wg_privkey,
                     &wg_interface,
                     &wg_address,
+                    &wg_address_v6,
+                    &wg_awg,
                     &dns,
                     &h2_settings,
                     &inbound.mtproto_secret,
@@ -147,130 +267,38 @@ impl PgNode {
         Ok(())
     }
 
-    pub async fn all(&self) -> Result<Vec<Node>> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    async fn all(&self) -> Result<Vec<Node>> {
+        let client = self.pool.get_client().await?;
 
         let rows = client
-            .query(
-                "SELECT
-                n.id AS node_id, n.uuid, n.env, n.hostname, n.address, n.status,
-                n.created_at, n.modified_at, n.label, n.interface,
-                n.cores, n.max_bandwidth_bps, n.country, n.node_type, i.id
-
-             AS inbound_id, i.tag, i.port, i.stream_settings, i.uplink, i.downlink,
-                i.conn_count, i.wg_privkey, i.wg_interface, i.wg_address, i.dns, i.h2, i.mtproto_secret
-             FROM nodes n
-             LEFT JOIN inbounds i ON n.id = i.node_id",
-                &[],
-            )
+            .query(&client.prepare_cached(NODES_QUERY).await?, &[])
             .await?;
 
-        let mut nodes_map: HashMap<uuid::Uuid, Node> = HashMap::new();
-
-        for row in rows {
-            let node_id: uuid::Uuid = row.get("node_id");
-            let uuid: uuid::Uuid = row.get("uuid");
-            let env: String = row.get("env");
-            let hostname: String = row.get("hostname");
-            let address: IpAddr = row.get("address");
-            let status: NodeStatus = row.get("status");
-            let created_at: DateTime<Utc> = row.get("created_at");
-            let modified_at: DateTime<Utc> = row.get("modified_at");
-            let label: String = row.get("label");
-            let interface: String = row.get("interface");
-            let cores: i32 = row.get("cores");
-            let country: String = row.get("country");
-            let max_bandwidth_bps: i64 = row.get("max_bandwidth_bps");
-            let r#type: NodeType = row.get("node_type");
-
-            let wg_address: Option<IpAddrMask> = row
-                .get::<_, Option<String>>("wg_address")
-                .and_then(|s| s.parse().ok());
-
-            let dns: Option<Vec<Ipv4Addr>> = row.get::<_, Option<Vec<IpAddr>>>("dns").map(|ips| {
-                ips.into_iter()
-                    .filter_map(|ip| match ip {
-                        IpAddr::V4(v4) => Some(v4),
-                        _ => None,
-                    })
-                    .collect()
-            });
-            let inbound_id: Option<uuid::Uuid> = row.get("inbound_id");
-
-            let h2: Option<H2Settings> = row
-                .get::<_, Option<serde_json::Value>>("h2")
-                .and_then(|v| serde_json::from_value(v).ok());
-
-            if let IpAddr::V4(ipv4) = address {
-                let node_entry = nodes_map.entry(node_id).or_insert_with(|| Node {
-                    uuid,
-                    env: env.into(),
-                    hostname: hostname.clone(),
-                    address: ipv4,
-                    interface: interface.clone(),
-                    status,
-                    created_at,
-                    modified_at,
-                    label: label.clone(),
-                    inbounds: HashMap::new(),
-                    cores: cores as usize,
-                    max_bandwidth_bps,
-                    country,
-                    r#type,
-                });
-
-                if let Some(_inbound_id) = inbound_id {
-                    let wg = match (
-                        row.get::<_, Option<String>>("wg_privkey"),
-                        row.get::<_, Option<String>>("wg_interface"),
-                        wg_address,
-                        dns,
-                    ) {
-                        (Some(privkey), Some(interface), Some(address), Some(dns)) => {
-                            Some(WireguardSettings {
-                                keys: WgKeys { privkey },
-                                interface,
-                                address,
-                                port: row.get::<_, i32>("port") as u16,
-                                dns,
-                            })
-                        }
-                        _ => None,
-                    };
-
-                    let mtproto_secret = row.get::<_, Option<String>>("mtproto_secret");
+        Ok(Self::map_rows_to_nodes(rows))
+    }
 
-                    let inbound = Inbound {
-                        tag: row.get("tag"),
-                        port: row.get::<_, i32>("port") as u16,
-                        stream_settings: row
-                            .get::<_, Option<serde_json::Value>>("stream_settings")
-                            .and_then(|v| serde_json::from_value(v).ok()),
-                        uplink: row.get("uplink"),
-                        downlink: row.get("downlink"),
-                        conn_count: row.get("conn_count"),
-                        wg,
-                        h2,
-                        mtproto_secret,
-                    };
+    async fn updated_since(&self, since: DateTime<Utc>) -> Result<Vec<Node>> {
+        let client = self.pool.get_client().await?;
 
-                    node_entry.inbounds.insert(inbound.tag, inbound);
-                }
-            }
-        }
+        let query = format!(
+            "{} WHERE n.modified_at > $1
+             OR n.id IN (SELECT node_id FROM inbounds WHERE modified_at > $1)",
+            NODES_QUERY
+        );
+        let rows = client
+            .query(&client.prepare_cached(&query).await?, &[&since])
+            .await?;
 
-        Ok(nodes_map.into_values().collect())
+        Ok(Self::map_rows_to_nodes(rows))
     }
 
-    pub async fn update_status(
+    async fn update_status(
         &self,
         uuid: &uuid::Uuid,
         env: &str,
         new_status: NodeStatus,
     ) -> Result<()> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+        let client = self.pool.get_client().await?;
 
         let query = "
             UPDATE nodes
@@ -280,7 +308,10 @@ impl PgNode {
         let modified_at = Utc::now();
 
         let result = client
-            .execute(query, &[&new_status, &modified_at, &uuid, &env])
+            .execute(
+                &client.prepare_cached(query).await?,
+                &[&new_status, &modified_at, &uuid, &env],
+            )
             .await;
 
         match result {
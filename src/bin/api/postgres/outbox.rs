@@ -0,0 +1,122 @@
+use deadpool_postgres::Transaction;
+use std::str::FromStr;
+use tracing::{debug, warn};
+
+use fcore::{Publisher, Result, Topic};
+
+use super::super::storage::{OutboxMessage, OutboxRepo};
+use super::pg::PgPool;
+
+/// Must be called inside the transaction of the domain change
+pub async fn enqueue(tx: &Transaction<'_>, messages: &[OutboxMessage]) -> Result<()> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    let stmt = tx
+        .prepare_cached("INSERT INTO outbox (topic, payload) VALUES ($1, $2)")
+        .await?;
+    for msg in messages {
+        tx.execute(&stmt, &[&msg.topic.as_str(), &msg.payload])
+            .await?;
+    }
+    Ok(())
+}
+
+pub struct PgOutbox {
+    pub pool: PgPool,
+}
+
+impl PgOutbox {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl OutboxRepo for PgOutbox {
+    async fn dispatch(&self, publisher: &Publisher, batch: i64) -> Result<usize> {
+        let mut client = self.pool.get_client().await?;
+        let tx = client.transaction().await?;
+
+        let rows = tx
+            .query(
+                &tx.prepare_cached(
+                    "
+                SELECT id, topic, payload FROM outbox
+                WHERE delivered_at IS NULL
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+                ",
+                )
+                .await?,
+                &[&batch],
+            )
+            .await?;
+
+        let delivered_stmt = tx
+            .prepare_cached(
+                "UPDATE outbox SET delivered_at = NOW(), attempts = attempts + 1 WHERE id = $1",
+            )
+            .await?;
+
+        let mut delivered = 0;
+        for row in rows {
+            let id: i64 = row.get("id");
+            let topic: String = row.get("topic");
+            let payload: Vec<u8> = row.get("payload");
+
+            let result = match Topic::from_str(&topic) {
+                Ok(topic) => publisher
+                    .send_binary(&topic, &payload)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+
+            match result {
+                Ok(()) => {
+                    tx.execute(&delivered_stmt, &[&id]).await?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    warn!("Outbox message {} to {} failed: {}", id, topic, e);
+                    tx.execute(
+                        &tx.prepare_cached(
+                            "UPDATE outbox SET attempts = attempts + 1, last_error = $2 WHERE id = $1",
+                        )
+                        .await?,
+                        &[&id, &e],
+                    )
+                    .await?;
+                    break;
+                }
+            }
+        }
+
+        tx.commit().await?;
+        if delivered > 0 {
+            debug!("Outbox: {} messages delivered", delivered);
+        }
+        Ok(delivered)
+    }
+
+    async fn purge_delivered(&self, retention_sec: i64) -> Result<u64> {
+        let client = self.pool.get_client().await?;
+
+        let query = "
+            DELETE FROM outbox
+            WHERE delivered_at IS NOT NULL
+              AND delivered_at < NOW() - make_interval(secs => $1)
+        ";
+
+        let deleted = client
+            .execute(
+                &client.prepare_cached(query).await?,
+                &[&(retention_sec as f64)],
+            )
+            .await?;
+        Ok(deleted)
+    }
+}
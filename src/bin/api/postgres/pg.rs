@@ -1,102 +1,199 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Client, Manager, ManagerConfig, Pool, RecyclingMethod, Runtime};
+use native_tls::{Certificate, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio_postgres::Client as PgClient;
+use std::time::Duration;
+use tokio_postgres::config::SslMode;
 use tokio_postgres::NoTls;
 
-use tracing::{debug, error, trace, warn};
+use tracing::{debug, trace};
 
 use fcore::{
-    Connection, ConnectionStorageApiOperations, Env, Node, NodeStorageOperations, Result, Status,
-    Subscription, SubscriptionStorageOperations,
+    Connection, ConnectionStorageApiOperations, Env, Error, Node, NodeStorageOperations, Result,
+    Status, Subscription, SubscriptionStorageOperations,
 };
 
 use super::{
-    super::{config::PostgresConfig, service::Cache},
-    connection::{ConnRow, PgConn},
+    super::{
+        config::{PgTlsConfig, PostgresConfig},
+        service::Cache,
+        storage::{
+            ConnRepo, ConnRow, IpPoolRepo, KeyRepo, NodeRepo, OutboxRepo, Storage,
+            SubscriptionRepo, WebhookRepo,
+        },
+    },
+    connection::PgConn,
+    ip_pool::PgIpPool,
     keys::PgKey,
+    migrate::PgMigrator,
     node::PgNode,
+    outbox::PgOutbox,
     subscription::PgSubscription,
+    webhook::PgWebhook,
 };
 
-pub struct PgClientManager {
-    config: PostgresConfig,
-    client: Option<PgClient>,
+/// Pooled connections; each keeps its own prepared statement cache
+#[derive(Clone)]
+pub struct PgPool {
+    pool: Pool,
 }
 
-impl PgClientManager {
-    pub async fn new(config: PostgresConfig) -> Result<Self> {
-        Ok(Self {
-            config,
-            client: None,
-        })
+impl PgPool {
+    pub fn new(config: &PostgresConfig) -> Result<Self> {
+        let mut pg_config = tokio_postgres::Config::new();
+        pg_config
+            .host(&config.host)
+            .port(config.port)
+            .user(&config.username)
+            .password(&config.password)
+            .dbname(&config.db)
+            .connect_timeout(Duration::from_secs(config.connect_timeout_sec));
+
+        let manager_config = ManagerConfig {
+            recycling_method: if config.verify_connections {
+                RecyclingMethod::Verified
+            } else {
+                RecyclingMethod::Fast
+            },
+        };
+
+        let manager = match &config.tls {
+            Some(tls) => {
+                pg_config.ssl_mode(SslMode::Require);
+                Manager::from_config(pg_config, Self::tls_connector(tls)?, manager_config)
+            }
+            None => Manager::from_config(pg_config, NoTls, manager_config),
+        };
+
+        let timeout = Some(Duration::from_secs(config.connect_timeout_sec));
+        let pool = Pool::builder(manager)
+            .max_size(config.pool_size)
+            .wait_timeout(timeout)
+            .create_timeout(timeout)
+            .recycle_timeout(timeout)
+            .runtime(Runtime::Tokio1)
+            .build()
+            .map_err(|e| Error::Custom(format!("Failed to build PG pool: {}", e)))?;
+
+        Ok(Self { pool })
     }
 
-    async fn connect(&mut self) -> Result<()> {
-        let connection_line = format!(
-            "host={} user={} dbname={} password={} port={}",
-            self.config.host,
-            self.config.username,
-            self.config.db,
-            self.config.password,
-            self.config.port
-        );
+    /// Server certificate is verified against `ca_cert` only
+    fn tls_connector(config: &PgTlsConfig) -> Result<MakeTlsConnector> {
+        let pem = std::fs::read(&config.ca_cert)?;
+        let ca = Certificate::from_pem(&pem)
+            .map_err(|e| Error::Custom(format!("Invalid PG CA certificate: {}", e)))?;
 
-        let (client, connection) = tokio_postgres::connect(&connection_line, NoTls).await?;
-
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                error!("Postgres connection dropped: {}", e);
-            }
-        });
+        let connector = TlsConnector::builder()
+            .add_root_certificate(ca)
+            .disable_built_in_roots(true)
+            .danger_accept_invalid_hostnames(config.accept_invalid_hostnames)
+            .build()
+            .map_err(|e| Error::Custom(format!("Failed to build PG TLS connector: {}", e)))?;
 
-        self.client = Some(client);
-        Ok(())
+        Ok(MakeTlsConnector::new(connector))
     }
 
-    pub async fn get_client(&mut self) -> Result<&mut PgClient> {
-        if self.client.is_none() {
-            self.connect().await?;
-        }
+    pub async fn get_client(&self) -> Result<Client> {
+        self.pool
+            .get()
+            .await
+            .map_err(|e| Error::Custom(format!("PG pool: {}", e)))
+    }
 
-        // ping with simple query
-        let client = self.client.as_mut().unwrap();
-        if let Err(e) = client.simple_query("SELECT 1").await {
-            warn!("PG ping failed: {}. Reconnecting...", e);
-            self.connect().await?;
+    /// Drops connections that sat unused longer than `idle_timeout`
+    pub async fn reap_idle(&self, idle_timeout: Duration) {
+        let mut interval = tokio::time::interval(idle_timeout.max(Duration::from_secs(1)) / 2);
+        loop {
+            interval.tick().await;
+            let removed = self
+                .pool
+                .retain(|_, metrics| metrics.last_used() < idle_timeout)
+                .removed
+                .len();
+            if removed > 0 {
+                debug!("Closed {} idle PG connections", removed);
+            }
         }
-
-        Ok(self.client.as_mut().unwrap())
     }
 }
 
-#[derive(Clone)]
 pub struct PgContext {
-    pub manager: Arc<Mutex<PgClientManager>>,
+    pub pool: PgPool,
+    node: PgNode,
+    conn: PgConn,
+    sub: PgSubscription,
+    key: PgKey,
+    ip_pool: PgIpPool,
+    webhook: PgWebhook,
+    outbox: PgOutbox,
 }
 
 impl PgContext {
     pub async fn init(config: &PostgresConfig) -> Result<Self> {
-        let manager = PgClientManager::new(config.clone()).await?;
+        let pool = PgPool::new(config)?;
+
+        // Fail at startup rather than on the first request
+        drop(pool.get_client().await?);
+
+        let idle_timeout = Duration::from_secs(config.idle_timeout_sec);
+        tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.reap_idle(idle_timeout).await }
+        });
+
         Ok(Self {
-            manager: Arc::new(Mutex::new(manager)),
+            node: PgNode::new(pool.clone()),
+            conn: PgConn::new(pool.clone()),
+            sub: PgSubscription::new(pool.clone()),
+            key: PgKey::new(pool.clone()),
+            ip_pool: PgIpPool::new(pool.clone()),
+            webhook: PgWebhook::new(pool.clone()),
+            outbox: PgOutbox::new(pool.clone()),
+            pool,
         })
     }
 
-    pub fn node(&self) -> PgNode {
-        PgNode::new(self.manager.clone())
+    pub fn migrator(&self) -> PgMigrator {
+        PgMigrator::new(self.pool.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for PgContext {
+    fn node(&self) -> &dyn NodeRepo {
+        &self.node
+    }
+
+    fn conn(&self) -> &dyn ConnRepo {
+        &self.conn
+    }
+
+    fn sub(&self) -> &dyn SubscriptionRepo {
+        &self.sub
+    }
+
+    fn key(&self) -> &dyn KeyRepo {
+        &self.key
+    }
+
+    fn ip_pool(&self) -> &dyn IpPoolRepo {
+        &self.ip_pool
     }
 
-    pub fn conn(&self) -> PgConn {
-        PgConn::new(self.manager.clone())
+    fn webhook(&self) -> &dyn WebhookRepo {
+        &self.webhook
     }
 
-    pub fn sub(&self) -> PgSubscription {
-        PgSubscription::new(self.manager.clone())
+    fn outbox(&self) -> &dyn OutboxRepo {
+        &self.outbox
     }
 
-    pub fn key(&self) -> PgKey {
-        PgKey::new(self.manager.clone())
+    async fn now(&self) -> Result<DateTime<Utc>> {
+        let client = self.pool.get_client().await?;
+        let row = client.query_one("SELECT now()", &[]).await?;
+        Ok(row.get(0))
     }
 }
 
@@ -1,27 +1,34 @@
 use chrono::Utc;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
-use fcore::{Result, Subscription};
+use fcore::{Result, Subscription, WgParam};
 
-use super::pg::PgClientManager;
+use super::super::storage::{OutboxMessage, SubscriptionRepo};
+use super::connection::PgConn;
+use super::outbox;
+use super::pg::PgPool;
 
 pub struct PgSubscription {
-    pub manager: Arc<Mutex<PgClientManager>>,
+    pub pool: PgPool,
 }
 
 impl PgSubscription {
-    pub fn new(manager: Arc<Mutex<PgClientManager>>) -> Self {
-        Self { manager }
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
     }
+}
 
-    pub async fn all(&self) -> Result<Vec<Subscription>> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+#[async_trait::async_trait]
+impl SubscriptionRepo for PgSubscription {
+    async fn all(&self) -> Result<Vec<Subscription>> {
+        let client = self.pool.get_client().await?;
 
         let rows = client
             .query(
-                "SELECT * FROM subscriptions WHERE NOT is_deleted ORDER BY created_at DESC",
+                &client
+                    .prepare_cached(
+                        "SELECT * FROM subscriptions WHERE NOT is_deleted ORDER BY created_at DESC",
+                    )
+                    .await?,
                 &[],
             )
             .await?;
@@ -31,20 +38,38 @@ impl PgSubscription {
         Ok(subscriptions)
     }
 
-    pub async fn create(&self, new_sub: &Subscription) -> Result<Subscription> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    async fn updated_since(&self, since: chrono::DateTime<Utc>) -> Result<Vec<Subscription>> {
+        let client = self.pool.get_client().await?;
+
+        let rows = client
+            .query(
+                &client
+                    .prepare_cached("SELECT * FROM subscriptions WHERE updated_at > $1")
+                    .await?,
+                &[&since],
+            )
+            .await?;
+
+        Ok(rows.into_iter().map(Subscription::from).collect())
+    }
+
+    async fn create(&self, new_sub: &Subscription) -> Result<Subscription> {
+        let client = self.pool.get_client().await?;
 
         let ref_code = new_sub.refer_code.clone();
 
         let row = client
             .query_one(
-                r#"
+                &client
+                    .prepare_cached(
+                        r#"
             INSERT INTO subscriptions
             (id, expires_at, referred_by, refer_code)
             VALUES ($1, $2, $3, $4)
             RETURNING *
             "#,
+                    )
+                    .await?,
                 &[
                     &new_sub.id,
                     &new_sub.expires_at,
@@ -57,20 +82,21 @@ impl PgSubscription {
         Ok(Subscription::from(row))
     }
 
-    pub async fn update_subscription(
+    async fn update_subscription(
         &self,
         id: uuid::Uuid,
         expires_at: chrono::DateTime<chrono::Utc>,
         referred_by: Option<&str>,
-        ref_code: &String,
+        ref_code: &str,
     ) -> Result<Subscription> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+        let client = self.pool.get_client().await?;
         let now = chrono::Utc::now();
 
         let row = client
             .query_one(
-                r#"
+                &client
+                    .prepare_cached(
+                        r#"
             UPDATE subscriptions
             SET expires_at  = $1,
                 referred_by = $2,
@@ -79,6 +105,8 @@ impl PgSubscription {
             WHERE id = $5
             RETURNING *
             "#,
+                    )
+                    .await?,
                 &[&expires_at, &referred_by, &now, &ref_code, &id],
             )
             .await?;
@@ -86,15 +114,22 @@ impl PgSubscription {
         Ok(Subscription::from(row))
     }
 
-    pub async fn add_days(&self, sub_id: &uuid::Uuid, days: i64) -> Result<Subscription> {
-        let mut manager = self.manager.lock().await;
-        let client = manager.get_client().await?;
+    async fn add_days(
+        &self,
+        sub_id: &uuid::Uuid,
+        days: i64,
+        restore: &[(uuid::Uuid, Option<WgParam>)],
+        outbox: &[OutboxMessage],
+    ) -> Result<Subscription> {
+        let mut client = self.pool.get_client().await?;
+        let tx = client.transaction().await?;
 
         let now = chrono::Utc::now();
 
-        let row = client
+        let row = tx
             .query_one(
-                "SELECT expires_at FROM subscriptions WHERE id = $1",
+                &tx.prepare_cached("SELECT expires_at FROM subscriptions WHERE id = $1 FOR UPDATE")
+                    .await?,
                 &[sub_id],
             )
             .await?;
@@ -108,19 +143,28 @@ impl PgSubscription {
 
         let new_expires_at = base + chrono::Duration::days(days);
 
-        let updated_row = client
+        let updated_row = tx
             .query_one(
-                r#"
+                &tx.prepare_cached(
+                    r#"
                 UPDATE subscriptions
                 SET expires_at = $1,
                     updated_at = $2
                 WHERE id = $3
                 RETURNING *
                 "#,
+                )
+                .await?,
                 &[&new_expires_at, &now, sub_id],
             )
             .await?;
 
+        for (conn_id, wg) in restore {
+            PgConn::restore_in(&tx, conn_id, wg.as_ref()).await?;
+        }
+        outbox::enqueue(&tx, outbox).await?;
+
+        tx.commit().await?;
         Ok(Subscription::from(updated_row))
     }
 }
@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+
+use super::super::storage::{WebhookDelivery, WebhookRepo};
+use super::pg::PgPool;
+
+use fcore::Result;
+
+impl From<tokio_postgres::Row> for WebhookDelivery {
+    fn from(row: tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            endpoint: row.get("endpoint"),
+            event: row.get("event"),
+            payload: row.get("payload"),
+            attempts: row.get("attempts"),
+        }
+    }
+}
+
+pub struct PgWebhook {
+    pub pool: PgPool,
+}
+
+impl PgWebhook {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookRepo for PgWebhook {
+    async fn enqueue(
+        &self,
+        endpoint: &str,
+        event: &str,
+        dedup_key: &str,
+        payload: &str,
+    ) -> Result<bool> {
+        let client = self.pool.get_client().await?;
+
+        let query = "
+            INSERT INTO webhook_deliveries (id, endpoint, event, dedup_key, payload)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (endpoint, dedup_key) DO NOTHING
+        ";
+
+        let inserted = client
+            .execute(
+                &client.prepare_cached(query).await?,
+                &[
+                    &uuid::Uuid::new_v4(),
+                    &endpoint,
+                    &event,
+                    &dedup_key,
+                    &payload,
+                ],
+            )
+            .await?;
+
+        Ok(inserted > 0)
+    }
+
+    async fn fetch_due(&self, limit: i64, lease_sec: i64) -> Result<Vec<WebhookDelivery>> {
+        let client = self.pool.get_client().await?;
+
+        let query = "
+            UPDATE webhook_deliveries
+            SET next_attempt_at = NOW() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt_at <= NOW()
+                ORDER BY next_attempt_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, endpoint, event, payload, attempts
+        ";
+
+        let rows = client
+            .query(
+                &client.prepare_cached(query).await?,
+                &[&limit, &(lease_sec as f64)],
+            )
+            .await?;
+
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    async fn mark_delivered(&self, id: &uuid::Uuid) -> Result<()> {
+        let client = self.pool.get_client().await?;
+
+        let query = "
+            UPDATE webhook_deliveries
+            SET status = 'delivered',
+                attempts = attempts + 1,
+                delivered_at = NOW(),
+                last_error = NULL
+            WHERE id = $1
+        ";
+
+        client
+            .execute(&client.prepare_cached(query).await?, &[id])
+            .await?;
+        Ok(())
+    }
+
+    async fn mark_failed(
+        &self,
+        id: &uuid::Uuid,
+        error: &str,
+        next_attempt_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let client = self.pool.get_client().await?;
+
+        let query = "
+            UPDATE webhook_deliveries
+            SET attempts = attempts + 1,
+                last_error = $2,
+                status = CASE WHEN $3::timestamptz IS NULL THEN 'failed' ELSE 'pending' END,
+                next_attempt_at = COALESCE($3, next_attempt_at)
+            WHERE id = $1
+        ";
+
+        client
+            .execute(
+                &client.prepare_cached(query).await?,
+                &[id, &error, &next_attempt_at],
+            )
+            .await?;
+        Ok(())
+    }
+}
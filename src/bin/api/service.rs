@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::atomic::AtomicI64;
 use std::sync::Arc;
 
 use fcore::{
@@ -9,7 +10,7 @@ use fcore::{
 
 use super::email::EmailStore;
 
-use super::{config::ServiceSettings, sync::MemSync};
+use super::{config::ServiceSettings, snapshot::CacheSnapshot, sync::MemSync};
 
 pub type State = Cache<HashMap<Env, Vec<Node>>, Connection, Subscription>;
 
@@ -29,6 +30,9 @@ where
     pub settings: ServiceSettings,
     pub metrics: Arc<MetricStorage>,
     pub email_store: EmailStore,
+    pub cache_snapshot: Option<CacheSnapshot>,
+    /// Unix time the cache last matched Postgres, 0 before the first sync
+    pub last_db_sync: AtomicI64,
 }
 
 impl<N, C, S> Service<N, C, S>
@@ -49,16 +53,27 @@ where
         metrics: Arc<MetricStorage>,
         email_store: EmailStore,
     ) -> Self {
+        let cache_snapshot = settings
+            .service
+            .cache_snapshot_path
+            .clone()
+            .map(CacheSnapshot::new);
+
         Self {
             sync,
             settings,
             metrics,
             email_store,
+            cache_snapshot,
+            last_db_sync: AtomicI64::new(0),
         }
     }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(bound(
+    deserialize = "T: Deserialize<'de>, C: Deserialize<'de> + ConnectionBaseOperations, S: Deserialize<'de> + SubscriptionOperations"
+))]
 pub struct Cache<T, C, S>
 where
     T: Send + Sync + Clone + 'static,
@@ -0,0 +1,66 @@
+use std::path::Path;
+use tokio::fs as async_fs;
+
+use fcore::{quarantine_snapshot, write_snapshot_file, Result, SnapshotHeader};
+
+use super::service::State;
+
+/// Bumped whenever the serialized layout of the cache changes
+pub const CACHE_SNAPSHOT_VERSION: u32 = 1;
+
+/// Warm-start copy of the API cache, same header as node snapshots.
+/// The payload is JSON, not rkyv: `Node` and `Subscription` would pull the
+/// whole inbound config tree (stream, Reality, WireGuard, Hysteria2 settings)
+/// into `Archive`, and the file is only read once at startup
+#[derive(Clone)]
+pub struct CacheSnapshot {
+    pub path: String,
+}
+
+impl CacheSnapshot {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+
+    /// `synced_at` is the last time the cache matched Postgres
+    pub async fn save(&self, state: &State, synced_at: i64) -> Result<()> {
+        let payload = serde_json::to_vec(state)?;
+        let header = SnapshotHeader::new(
+            CACHE_SNAPSHOT_VERSION,
+            uuid::Uuid::nil(),
+            synced_at as u64,
+            &payload,
+        );
+        write_snapshot_file(&self.path, &header.seal(&payload)).await
+    }
+
+    /// Cache and its sync timestamp, unusable files are quarantined
+    pub async fn load(&self) -> Result<Option<(State, i64)>> {
+        if !Path::new(&self.path).exists() {
+            return Ok(None);
+        }
+
+        let bytes = async_fs::read(&self.path).await?;
+        match Self::decode(&bytes) {
+            Ok(loaded) => Ok(Some(loaded)),
+            Err(reason) => {
+                quarantine_snapshot(&self.path, &reason).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> std::result::Result<(State, i64), String> {
+        let (header, payload) =
+            SnapshotHeader::open(bytes, uuid::Uuid::nil(), CACHE_SNAPSHOT_VERSION)?
+                .ok_or_else(|| "missing header".to_string())?;
+
+        if header.version != CACHE_SNAPSHOT_VERSION {
+            return Err(format!("no migration from version {}", header.version));
+        }
+
+        let state: State =
+            serde_json::from_slice(payload).map_err(|e| format!("invalid payload: {}", e))?;
+        Ok((state, header.timestamp as i64))
+    }
+}
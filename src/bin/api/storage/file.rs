@@ -0,0 +1,1003 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::fs as async_fs;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use fcore::{
+    append_spill_file, decode_spill_records, encode_spill_records, repair_spill_file,
+    write_snapshot_file, Error, Key, Node, NodeStatus, Publisher, Result, SnapshotHeader,
+    Subscription, Topic, WgParam,
+};
+
+use super::{
+    ConnRepo, ConnRow, IpPoolRepo, KeyRepo, NodeRepo, OutboxMessage, OutboxRepo, Storage,
+    SubscriptionRepo, WebhookDelivery, WebhookRepo,
+};
+
+/// Bumped whenever the layout of the tables changes
+pub const FILE_STORAGE_VERSION: u32 = 2;
+
+/// Log records folded into the snapshot at once
+const COMPACT_EVERY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DeliveryRow {
+    id: uuid::Uuid,
+    endpoint: String,
+    event: String,
+    dedup_key: String,
+    payload: String,
+    status: DeliveryStatus,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OutboxRow {
+    topic: String,
+    #[serde(with = "base64_bytes")]
+    payload: Vec<u8>,
+    attempts: i32,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+/// Payloads are rkyv bytes, JSON would spell them out as an array of numbers
+mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// New state of one row, `None` removes it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Change {
+    Node(uuid::Uuid, Option<Node>),
+    Conn(uuid::Uuid, Option<ConnRow>),
+    Subscription(uuid::Uuid, Option<Subscription>),
+    Key(String, Option<Key>),
+    WgAllocation(IpAddr, Option<uuid::Uuid>),
+    Delivery(uuid::Uuid, Option<DeliveryRow>),
+    Outbox(i64, Option<OutboxRow>),
+}
+
+/// Same tables and constraints as the Postgres schema
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Tables {
+    nodes: HashMap<uuid::Uuid, Node>,
+    connections: HashMap<uuid::Uuid, ConnRow>,
+    subscriptions: HashMap<uuid::Uuid, Subscription>,
+    /// By code, codes are unique
+    keys: HashMap<String, Key>,
+    wg_allocations: HashMap<IpAddr, uuid::Uuid>,
+    webhook_deliveries: HashMap<uuid::Uuid, DeliveryRow>,
+    outbox: BTreeMap<i64, OutboxRow>,
+    outbox_seq: i64,
+}
+
+impl Tables {
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::Node(id, row) => put(&mut self.nodes, id, row),
+            Change::Conn(id, row) => put(&mut self.connections, id, row),
+            Change::Subscription(id, row) => put(&mut self.subscriptions, id, row),
+            Change::Key(code, row) => put(&mut self.keys, code, row),
+            Change::WgAllocation(address, row) => put(&mut self.wg_allocations, address, row),
+            Change::Delivery(id, row) => put(&mut self.webhook_deliveries, id, row),
+            Change::Outbox(id, row) => {
+                self.outbox_seq = self.outbox_seq.max(id);
+                match row {
+                    Some(row) => self.outbox.insert(id, row),
+                    None => self.outbox.remove(&id),
+                };
+            }
+        }
+    }
+
+    /// Called at most once per write, ids follow the committed sequence
+    fn enqueue(&self, messages: &[OutboxMessage], changes: &mut Vec<Change>) {
+        let now = Utc::now();
+        for (id, msg) in (self.outbox_seq + 1..).zip(messages) {
+            changes.push(Change::Outbox(
+                id,
+                Some(OutboxRow {
+                    topic: msg.topic.as_str().to_string(),
+                    payload: msg.payload.clone(),
+                    attempts: 0,
+                    last_error: None,
+                    created_at: now,
+                    delivered_at: None,
+                }),
+            ));
+        }
+    }
+
+    fn restore_conn(&self, conn_id: &uuid::Uuid, wg: Option<&WgParam>, changes: &mut Vec<Change>) {
+        let Some(row) = self.connections.get(conn_id) else {
+            return;
+        };
+        let mut row = row.clone();
+        if let (Some(current), Some(wg)) = (row.wg.as_mut(), wg) {
+            current.address = wg.address.clone();
+            current.address_v6 = wg.address_v6.clone();
+        }
+        row.is_deleted = false;
+        row.modified_at = Utc::now();
+        changes.push(Change::Conn(*conn_id, Some(row)));
+    }
+}
+
+fn put<K: Eq + Hash, V>(table: &mut HashMap<K, V>, key: K, row: Option<V>) {
+    match row {
+        Some(row) => table.insert(key, row),
+        None => table.remove(&key),
+    };
+}
+
+struct Inner {
+    tables: Tables,
+    /// Records in the log since the last compaction
+    logged: usize,
+}
+
+/// Embedded storage for single server deployments without Postgres.
+/// Tables live in memory, every write appends the rows it changed to
+/// `<path>.log` as one record and the log is folded into the checksummed
+/// snapshot at `path` every `COMPACT_EVERY` records
+pub struct FileStorage {
+    path: String,
+    log: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+impl FileStorage {
+    pub async fn open(path: &str) -> Result<Self> {
+        let mut tables = if Path::new(path).exists() {
+            let bytes = async_fs::read(path).await?;
+            Self::decode(&bytes)
+                .map_err(|e| Error::Custom(format!("Storage file {} is unusable: {}", path, e)))?
+        } else {
+            Tables::default()
+        };
+
+        // Records are whole rows, replaying ones already in the snapshot is harmless
+        let log = PathBuf::from(format!("{}.log", path));
+        repair_spill_file(&log).await?;
+        let logged = if log.exists() {
+            let records = decode_spill_records(&async_fs::read(&log).await?);
+            for record in &records {
+                let changes: Vec<Change> = serde_json::from_slice(record)?;
+                for change in changes {
+                    tables.apply(change);
+                }
+            }
+            records.len()
+        } else {
+            0
+        };
+
+        let storage = Self {
+            path: path.to_string(),
+            log,
+            inner: Mutex::new(Inner { tables, logged }),
+        };
+        // Fail at startup rather than on the first write
+        let mut inner = storage.inner.lock().await;
+        storage.compact(&mut inner).await?;
+        debug!(
+            "File storage {} opened: {} connections, {} subscriptions, {} log records replayed",
+            path,
+            inner.tables.connections.len(),
+            inner.tables.subscriptions.len(),
+            logged
+        );
+        drop(inner);
+        Ok(storage)
+    }
+
+    fn decode(bytes: &[u8]) -> std::result::Result<Tables, String> {
+        let (header, payload) =
+            SnapshotHeader::open(bytes, uuid::Uuid::nil(), FILE_STORAGE_VERSION)?
+                .ok_or_else(|| "missing header".to_string())?;
+
+        if header.version != FILE_STORAGE_VERSION {
+            return Err(format!("no migration from version {}", header.version));
+        }
+
+        serde_json::from_slice(payload).map_err(|e| format!("invalid payload: {}", e))
+    }
+
+    /// Writes the snapshot, then drops the log it now contains
+    async fn compact(&self, inner: &mut Inner) -> Result<()> {
+        let payload = serde_json::to_vec(&inner.tables)?;
+        let header = SnapshotHeader::new(
+            FILE_STORAGE_VERSION,
+            uuid::Uuid::nil(),
+            Utc::now().timestamp() as u64,
+            &payload,
+        );
+        write_snapshot_file(&self.path, &header.seal(&payload)).await?;
+
+        if self.log.exists() {
+            async_fs::remove_file(&self.log).await?;
+        }
+        inner.logged = 0;
+        Ok(())
+    }
+
+    async fn read<T>(&self, f: impl FnOnce(&Tables) -> T) -> T {
+        f(&self.inner.lock().await.tables)
+    }
+
+    /// `f` stages the rows it changes, they are applied once logged as one
+    /// record, so a failed step or write leaves nothing behind
+    async fn write<T>(&self, f: impl FnOnce(&Tables, &mut Vec<Change>) -> Result<T>) -> Result<T> {
+        let mut inner = self.inner.lock().await;
+        let mut changes = Vec::new();
+        let out = f(&inner.tables, &mut changes)?;
+        if changes.is_empty() {
+            return Ok(out);
+        }
+
+        let record = serde_json::to_vec(&changes)?;
+        if let Err(e) = append_spill_file(&self.log, &encode_spill_records(&[record])).await {
+            // The rollback can fail too, don't leave a torn record for the next append
+            let _ = repair_spill_file(&self.log).await;
+            return Err(e);
+        }
+        for change in changes {
+            inner.tables.apply(change);
+        }
+
+        inner.logged += 1;
+        if inner.logged >= COMPACT_EVERY {
+            if let Err(e) = self.compact(&mut inner).await {
+                warn!("Storage compaction failed, keeping the log: {}", e);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for FileStorage {
+    fn node(&self) -> &dyn NodeRepo {
+        self
+    }
+
+    fn conn(&self) -> &dyn ConnRepo {
+        self
+    }
+
+    fn sub(&self) -> &dyn SubscriptionRepo {
+        self
+    }
+
+    fn key(&self) -> &dyn KeyRepo {
+        self
+    }
+
+    fn ip_pool(&self) -> &dyn IpPoolRepo {
+        self
+    }
+
+    fn webhook(&self) -> &dyn WebhookRepo {
+        self
+    }
+
+    fn outbox(&self) -> &dyn OutboxRepo {
+        self
+    }
+
+    async fn now(&self) -> Result<DateTime<Utc>> {
+        Ok(Utc::now())
+    }
+}
+
+#[async_trait::async_trait]
+impl NodeRepo for FileStorage {
+    async fn upsert(&self, node_id: uuid::Uuid, mut node: Node) -> Result<()> {
+        self.write(|t, changes| {
+            let taken = t
+                .nodes
+                .iter()
+                .any(|(id, n)| *id != node_id && n.uuid == node.uuid && n.env == node.env);
+            if taken {
+                return Err(Error::Custom(format!(
+                    "Node {} already exists in {}",
+                    node.uuid, node.env
+                )));
+            }
+
+            if let Some(existing) = t.nodes.get(&node_id) {
+                node.created_at = existing.created_at;
+                node.modified_at = Utc::now();
+            }
+            changes.push(Change::Node(node_id, Some(node)));
+            Ok(())
+        })
+        .await
+    }
+
+    async fn all(&self) -> Result<Vec<Node>> {
+        Ok(self.read(|t| t.nodes.values().cloned().collect()).await)
+    }
+
+    async fn updated_since(&self, since: DateTime<Utc>) -> Result<Vec<Node>> {
+        Ok(self
+            .read(|t| {
+                t.nodes
+                    .values()
+                    .filter(|n| n.modified_at > since)
+                    .cloned()
+                    .collect()
+            })
+            .await)
+    }
+
+    async fn update_status(&self, uuid: &uuid::Uuid, env: &str, status: NodeStatus) -> Result<()> {
+        self.write(|t, changes| {
+            match t
+                .nodes
+                .iter()
+                .find(|(_, n)| n.uuid == *uuid && n.env.to_string() == env)
+            {
+                Some((id, node)) => {
+                    let mut node = node.clone();
+                    node.status = status;
+                    node.modified_at = Utc::now();
+                    changes.push(Change::Node(*id, Some(node)));
+                    debug!("Updated node {} status to {}", uuid, status);
+                }
+                None => warn!("No node found with UUID {}", uuid),
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl ConnRepo for FileStorage {
+    async fn all(&self) -> Result<Vec<ConnRow>> {
+        Ok(self
+            .read(|t| t.connections.values().cloned().collect())
+            .await)
+    }
+
+    async fn updated_since(&self, since: DateTime<Utc>) -> Result<Vec<ConnRow>> {
+        Ok(self
+            .read(|t| {
+                t.connections
+                    .values()
+                    .filter(|c| c.modified_at > since)
+                    .cloned()
+                    .collect()
+            })
+            .await)
+    }
+
+    async fn insert(&self, conn: ConnRow, outbox: &[OutboxMessage]) -> Result<()> {
+        self.write(|t, changes| {
+            if t.connections.contains_key(&conn.conn_id) {
+                return Err(Error::Custom(format!(
+                    "Connection {} already exists",
+                    conn.conn_id
+                )));
+            }
+            if let Some(sub_id) = conn.subscription_id {
+                if !t.subscriptions.contains_key(&sub_id) {
+                    return Err(Error::Custom(format!(
+                        "Subscription {} not found for connection {}",
+                        sub_id, conn.conn_id
+                    )));
+                }
+            }
+
+            changes.push(Change::Conn(conn.conn_id, Some(conn)));
+            t.enqueue(outbox, changes);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, conn_id: &uuid::Uuid, outbox: &[OutboxMessage]) -> Result<()> {
+        self.write(|t, changes| {
+            if let Some(row) = t.connections.get(conn_id) {
+                let mut row = row.clone();
+                row.is_deleted = true;
+                row.modified_at = Utc::now();
+                changes.push(Change::Conn(*conn_id, Some(row)));
+            }
+            t.enqueue(outbox, changes);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn restore(
+        &self,
+        conn_id: &uuid::Uuid,
+        wg: Option<&WgParam>,
+        outbox: &[OutboxMessage],
+    ) -> Result<()> {
+        self.write(|t, changes| {
+            t.restore_conn(conn_id, wg, changes);
+            t.enqueue(outbox, changes);
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl SubscriptionRepo for FileStorage {
+    async fn all(&self) -> Result<Vec<Subscription>> {
+        let mut subs: Vec<Subscription> = self
+            .read(|t| {
+                t.subscriptions
+                    .values()
+                    .filter(|s| !s.is_deleted)
+                    .cloned()
+                    .collect()
+            })
+            .await;
+        subs.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+        Ok(subs)
+    }
+
+    async fn updated_since(&self, since: DateTime<Utc>) -> Result<Vec<Subscription>> {
+        Ok(self
+            .read(|t| {
+                t.subscriptions
+                    .values()
+                    .filter(|s| s.updated_at > since)
+                    .cloned()
+                    .collect()
+            })
+            .await)
+    }
+
+    async fn create(&self, new_sub: &Subscription) -> Result<Subscription> {
+        self.write(|t, changes| {
+            if t.subscriptions.contains_key(&new_sub.id) {
+                return Err(Error::Custom(format!(
+                    "Subscription {} already exists",
+                    new_sub.id
+                )));
+            }
+
+            let now = Utc::now();
+            let sub = Subscription {
+                created_at: now,
+                updated_at: now,
+                is_deleted: false,
+                limit_bytes: None,
+                downlink_bytes: None,
+                ..new_sub.clone()
+            };
+            changes.push(Change::Subscription(sub.id, Some(sub.clone())));
+            Ok(sub)
+        })
+        .await
+    }
+
+    async fn update_subscription(
+        &self,
+        id: uuid::Uuid,
+        expires_at: DateTime<Utc>,
+        referred_by: Option<&str>,
+        ref_code: &str,
+    ) -> Result<Subscription> {
+        self.write(|t, changes| {
+            let mut sub = t
+                .subscriptions
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| Error::Custom(format!("Subscription {} not found", id)))?;
+            sub.expires_at = Some(expires_at);
+            sub.referred_by = referred_by.map(str::to_string);
+            sub.refer_code = ref_code.to_string();
+            sub.updated_at = Utc::now();
+            changes.push(Change::Subscription(id, Some(sub.clone())));
+            Ok(sub)
+        })
+        .await
+    }
+
+    async fn add_days(
+        &self,
+        sub_id: &uuid::Uuid,
+        days: i64,
+        restore: &[(uuid::Uuid, Option<WgParam>)],
+        outbox: &[OutboxMessage],
+    ) -> Result<Subscription> {
+        self.write(|t, changes| {
+            let now = Utc::now();
+            let mut sub = t
+                .subscriptions
+                .get(sub_id)
+                .cloned()
+                .ok_or_else(|| Error::Custom(format!("Subscription {} not found", sub_id)))?;
+
+            let base = match sub.expires_at {
+                Some(exp) if exp > now => exp,
+                _ => now,
+            };
+            sub.expires_at = Some(base + chrono::Duration::days(days));
+            sub.updated_at = now;
+            changes.push(Change::Subscription(*sub_id, Some(sub.clone())));
+
+            for (conn_id, wg) in restore {
+                t.restore_conn(conn_id, wg.as_ref(), changes);
+            }
+            t.enqueue(outbox, changes);
+            Ok(sub)
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl KeyRepo for FileStorage {
+    async fn get(&self, code: &str) -> Option<Key> {
+        self.read(|t| t.keys.get(code).cloned()).await
+    }
+
+    async fn insert(&self, key: &Key) -> Result<()> {
+        self.write(|t, changes| {
+            if t.keys.contains_key(&key.code) || t.keys.values().any(|k| k.id == key.id) {
+                return Err(Error::Custom(format!("Key {} already exists", key.id)));
+            }
+            changes.push(Change::Key(key.code.clone(), Some(key.clone())));
+            Ok(())
+        })
+        .await
+    }
+
+    async fn activate(&self, key: &Key) -> Result<()> {
+        self.write(|t, changes| {
+            if let Some(stored) = t.keys.values().find(|k| k.id == key.id) {
+                let mut stored = stored.clone();
+                stored.activated = true;
+                stored.subscription_id = key.subscription_id;
+                stored.modified_at = Utc::now();
+                changes.push(Change::Key(stored.code.clone(), Some(stored)));
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl IpPoolRepo for FileStorage {
+    async fn all(&self) -> Result<Vec<(IpAddr, uuid::Uuid)>> {
+        Ok(self
+            .read(|t| t.wg_allocations.iter().map(|(a, c)| (*a, *c)).collect())
+            .await)
+    }
+
+    async fn insert(&self, address: &IpAddr, conn_id: &uuid::Uuid) -> Result<bool> {
+        self.write(|t, changes| match t.wg_allocations.get(address) {
+            Some(holder) => Ok(holder == conn_id),
+            None => {
+                changes.push(Change::WgAllocation(*address, Some(*conn_id)));
+                Ok(true)
+            }
+        })
+        .await
+    }
+
+    async fn delete(&self, address: &IpAddr, conn_id: &uuid::Uuid) -> Result<()> {
+        self.write(|t, changes| {
+            if t.wg_allocations.get(address) == Some(conn_id) {
+                changes.push(Change::WgAllocation(*address, None));
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookRepo for FileStorage {
+    async fn enqueue(
+        &self,
+        endpoint: &str,
+        event: &str,
+        dedup_key: &str,
+        payload: &str,
+    ) -> Result<bool> {
+        self.write(|t, changes| {
+            let duplicate = t
+                .webhook_deliveries
+                .values()
+                .any(|d| d.endpoint == endpoint && d.dedup_key == dedup_key);
+            if duplicate {
+                return Ok(false);
+            }
+
+            let now = Utc::now();
+            let id = uuid::Uuid::new_v4();
+            changes.push(Change::Delivery(
+                id,
+                Some(DeliveryRow {
+                    id,
+                    endpoint: endpoint.to_string(),
+                    event: event.to_string(),
+                    dedup_key: dedup_key.to_string(),
+                    payload: payload.to_string(),
+                    status: DeliveryStatus::Pending,
+                    attempts: 0,
+                    next_attempt_at: now,
+                    last_error: None,
+                    created_at: now,
+                    delivered_at: None,
+                }),
+            ));
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn fetch_due(&self, limit: i64, lease_sec: i64) -> Result<Vec<WebhookDelivery>> {
+        let now = Utc::now();
+        self.write(|t, changes| {
+            let mut due: Vec<&DeliveryRow> = t
+                .webhook_deliveries
+                .values()
+                .filter(|d| d.status == DeliveryStatus::Pending && d.next_attempt_at <= now)
+                .collect();
+            due.sort_by_key(|d| d.next_attempt_at);
+
+            let mut leased = Vec::new();
+            for d in due.into_iter().take(limit.max(0) as usize) {
+                let mut row = d.clone();
+                row.next_attempt_at = now + chrono::Duration::seconds(lease_sec);
+                leased.push(WebhookDelivery {
+                    id: row.id,
+                    endpoint: row.endpoint.clone(),
+                    event: row.event.clone(),
+                    payload: row.payload.clone(),
+                    attempts: row.attempts,
+                });
+                changes.push(Change::Delivery(row.id, Some(row)));
+            }
+            Ok(leased)
+        })
+        .await
+    }
+
+    async fn mark_delivered(&self, id: &uuid::Uuid) -> Result<()> {
+        self.write(|t, changes| {
+            if let Some(d) = t.webhook_deliveries.get(id) {
+                let mut d = d.clone();
+                d.status = DeliveryStatus::Delivered;
+                d.attempts += 1;
+                d.delivered_at = Some(Utc::now());
+                d.last_error = None;
+                changes.push(Change::Delivery(*id, Some(d)));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn mark_failed(
+        &self,
+        id: &uuid::Uuid,
+        error: &str,
+        next_attempt_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        self.write(|t, changes| {
+            if let Some(d) = t.webhook_deliveries.get(id) {
+                let mut d = d.clone();
+                d.attempts += 1;
+                d.last_error = Some(error.to_string());
+                match next_attempt_at {
+                    Some(at) => {
+                        d.status = DeliveryStatus::Pending;
+                        d.next_attempt_at = at;
+                    }
+                    None => d.status = DeliveryStatus::Failed,
+                }
+                changes.push(Change::Delivery(*id, Some(d)));
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl OutboxRepo for FileStorage {
+    async fn dispatch(&self, publisher: &Publisher, batch: i64) -> Result<usize> {
+        let pending: Vec<(i64, String, Vec<u8>)> = self
+            .read(|t| {
+                t.outbox
+                    .iter()
+                    .filter(|(_, row)| row.delivered_at.is_none())
+                    .take(batch.max(0) as usize)
+                    .map(|(id, row)| (*id, row.topic.clone(), row.payload.clone()))
+                    .collect()
+            })
+            .await;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let mut delivered = Vec::new();
+        let mut failed = None;
+        for (id, topic, payload) in pending {
+            let result = match Topic::from_str(&topic) {
+                Ok(topic) => publisher
+                    .send_binary(&topic, &payload)
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match result {
+                Ok(()) => delivered.push(id),
+                Err(e) => {
+                    warn!("Outbox message {} to {} failed: {}", id, topic, e);
+                    failed = Some((id, e));
+                    break;
+                }
+            }
+        }
+
+        let count = delivered.len();
+        self.write(|t, changes| {
+            let now = Utc::now();
+            for id in delivered {
+                if let Some(row) = t.outbox.get(&id) {
+                    let mut row = row.clone();
+                    row.attempts += 1;
+                    row.delivered_at = Some(now);
+                    changes.push(Change::Outbox(id, Some(row)));
+                }
+            }
+            if let Some((id, e)) = failed {
+                if let Some(row) = t.outbox.get(&id) {
+                    let mut row = row.clone();
+                    row.attempts += 1;
+                    row.last_error = Some(e);
+                    changes.push(Change::Outbox(id, Some(row)));
+                }
+            }
+            Ok(())
+        })
+        .await?;
+
+        if count > 0 {
+            debug!("Outbox: {} messages delivered", count);
+        }
+        Ok(count)
+    }
+
+    async fn purge_delivered(&self, retention_sec: i64) -> Result<u64> {
+        self.write(|t, changes| {
+            let cutoff = Utc::now() - chrono::Duration::seconds(retention_sec);
+            let before = changes.len();
+            for (id, row) in &t.outbox {
+                if row.delivered_at.is_some_and(|at| at < cutoff) {
+                    changes.push(Change::Outbox(*id, None));
+                }
+            }
+            Ok((changes.len() - before) as u64)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fcore::{Env, Tag};
+
+    async fn open_temp() -> (FileStorage, String) {
+        let path = std::env::temp_dir()
+            .join(format!("storage-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        (FileStorage::open(&path).await.unwrap(), path)
+    }
+
+    async fn cleanup(path: &str) {
+        let _ = async_fs::remove_file(path).await;
+        let _ = async_fs::remove_file(format!("{}.log", path)).await;
+    }
+
+    fn conn_row(subscription_id: Option<uuid::Uuid>) -> ConnRow {
+        let now = Utc::now();
+        ConnRow {
+            conn_id: uuid::Uuid::new_v4(),
+            password: None,
+            env: "dev".to_string(),
+            created_at: now,
+            modified_at: now,
+            expires_at: None,
+            subscription_id,
+            wg: None,
+            proto: Tag::VlessTcpReality,
+            token: None,
+            is_deleted: false,
+        }
+    }
+
+    fn outbox_message(payload: &[u8]) -> OutboxMessage {
+        OutboxMessage {
+            topic: Topic::Updates(Env::Dev),
+            payload: payload.to_vec(),
+        }
+    }
+
+    async fn new_sub(storage: &FileStorage) -> Subscription {
+        let sub = Subscription::new(uuid::Uuid::new_v4(), None, "code".to_string(), None, None);
+        SubscriptionRepo::create(storage, &sub).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_constraints_reject_without_changes() {
+        let (storage, path) = open_temp().await;
+        let sub = new_sub(&storage).await;
+
+        let row = conn_row(Some(sub.id));
+        ConnRepo::insert(&storage, row.clone(), &[outbox_message(b"a")])
+            .await
+            .unwrap();
+        assert!(ConnRepo::insert(&storage, row, &[outbox_message(b"b")])
+            .await
+            .is_err());
+
+        let orphan = conn_row(Some(uuid::Uuid::new_v4()));
+        assert!(ConnRepo::insert(&storage, orphan.clone(), &[])
+            .await
+            .is_err());
+
+        storage
+            .read(|t| {
+                assert_eq!(t.connections.len(), 1);
+                assert!(!t.connections.contains_key(&orphan.conn_id));
+                assert_eq!(t.outbox.len(), 1);
+            })
+            .await;
+        cleanup(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_add_days_extends_and_restores_together() {
+        let (storage, path) = open_temp().await;
+        let sub = new_sub(&storage).await;
+        let row = conn_row(Some(sub.id));
+        let conn_id = row.conn_id;
+        ConnRepo::insert(&storage, row, &[]).await.unwrap();
+        ConnRepo::delete(&storage, &conn_id, &[]).await.unwrap();
+
+        let restore = [(conn_id, None)];
+        let missing = uuid::Uuid::new_v4();
+        let outbox = [outbox_message(b"restored")];
+        assert!(storage
+            .add_days(&missing, 30, &restore, &outbox)
+            .await
+            .is_err());
+        storage
+            .read(|t| {
+                assert!(t.connections[&conn_id].is_deleted);
+                assert!(t.outbox.is_empty());
+            })
+            .await;
+
+        let extended = storage
+            .add_days(&sub.id, 30, &restore, &outbox)
+            .await
+            .unwrap();
+        assert!(extended.expires_at.unwrap() > Utc::now() + chrono::Duration::days(29));
+        storage
+            .read(|t| {
+                assert!(!t.connections[&conn_id].is_deleted);
+                assert_eq!(t.outbox.len(), 1);
+            })
+            .await;
+        cleanup(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_stops_at_first_failure() {
+        let (storage, path) = open_temp().await;
+        let sub = new_sub(&storage).await;
+        let outbox = [
+            outbox_message(b"1"),
+            outbox_message(b"2"),
+            outbox_message(b"3"),
+        ];
+        ConnRepo::insert(&storage, conn_row(Some(sub.id)), &outbox)
+            .await
+            .unwrap();
+        storage
+            .inner
+            .lock()
+            .await
+            .tables
+            .outbox
+            .get_mut(&2)
+            .unwrap()
+            .topic = "bogus".into();
+
+        let publisher = Publisher::bind(&format!("inproc://outbox-{}", uuid::Uuid::new_v4()))
+            .await
+            .unwrap();
+        assert_eq!(storage.dispatch(&publisher, 10).await.unwrap(), 1);
+
+        storage
+            .read(|t| {
+                assert!(t.outbox[&1].delivered_at.is_some());
+                assert!(t.outbox[&2].delivered_at.is_none());
+                assert_eq!(t.outbox[&2].attempts, 1);
+                assert!(t.outbox[&2].last_error.is_some());
+                assert_eq!(t.outbox[&3].attempts, 0);
+            })
+            .await;
+        cleanup(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_reopen_replays_log_and_snapshot() {
+        let (storage, path) = open_temp().await;
+        let sub = new_sub(&storage).await;
+        let row = conn_row(Some(sub.id));
+        ConnRepo::insert(&storage, row.clone(), &[outbox_message(&[0, 1, 255])])
+            .await
+            .unwrap();
+        drop(storage);
+
+        // Torn record after a crash mid-append
+        append_spill_file(&PathBuf::from(format!("{}.log", path)), &[9, 0, 0, 0, 1])
+            .await
+            .unwrap();
+
+        let storage = FileStorage::open(&path).await.unwrap();
+        storage
+            .read(|t| {
+                assert!(t.subscriptions.contains_key(&sub.id));
+                assert!(t.connections.contains_key(&row.conn_id));
+                assert_eq!(t.outbox[&1].payload, vec![0, 1, 255]);
+            })
+            .await;
+
+        // Compacted on open, the snapshot alone carries everything now
+        assert!(!PathBuf::from(format!("{}.log", path)).exists());
+        ConnRepo::insert(&storage, conn_row(None), &[outbox_message(b"next")])
+            .await
+            .unwrap();
+        assert_eq!(storage.read(|t| t.outbox_seq).await, 2);
+        cleanup(&path).await;
+    }
+}
@@ -0,0 +1,230 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use fcore::{
+    Connection, ConnectionBaseOperations, Error, Key, Message, Node, NodeStatus, Proto, Publisher,
+    Result, Subscription, Tag, Topic, WgParam,
+};
+
+pub(crate) mod file;
+
+/// Shared handle to whichever backend is configured
+pub type Db = Arc<dyn Storage>;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ConnRow {
+    pub conn_id: uuid::Uuid,
+    pub password: Option<String>,
+    pub env: String,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub subscription_id: Option<uuid::Uuid>,
+    pub wg: Option<WgParam>,
+    pub proto: Tag,
+    pub token: Option<uuid::Uuid>,
+    pub is_deleted: bool,
+}
+
+impl From<(uuid::Uuid, Connection)> for ConnRow {
+    fn from((conn_id, conn): (uuid::Uuid, Connection)) -> Self {
+        ConnRow {
+            conn_id,
+            password: conn.get_password(),
+            env: conn.env.to_string(),
+            created_at: conn.created_at,
+            modified_at: conn.modified_at,
+            expires_at: conn.expires_at,
+            subscription_id: conn.subscription_id,
+            wg: conn.get_wireguard().cloned(),
+            proto: conn.get_proto().proto(),
+            token: conn.get_token(),
+            is_deleted: conn.is_deleted,
+        }
+    }
+}
+
+impl TryFrom<ConnRow> for Connection {
+    type Error = Error;
+
+    fn try_from(row: ConnRow) -> Result<Self> {
+        let proto = match row.proto {
+            Tag::Wireguard => {
+                let wg = row
+                    .wg
+                    .ok_or_else(|| Error::Custom("Missing Wireguard param".into()))?;
+
+                Proto::new_wg(&wg)
+            }
+
+            Tag::Shadowsocks => {
+                let password = row
+                    .password
+                    .ok_or_else(|| Error::Custom("Missing Shadowsocks password".into()))?;
+
+                Proto::new_ss(&password)
+            }
+
+            Tag::Hysteria2 => {
+                let token = row
+                    .token
+                    .ok_or_else(|| Error::Custom("Missing Hysteria2 token".into()))?;
+                Proto::new_hysteria2(&token)
+            }
+
+            tag => Proto::new_xray(&tag),
+        };
+
+        Ok(Self {
+            env: row.env.into(),
+            proto,
+            subscription_id: row.subscription_id,
+            created_at: row.created_at,
+            modified_at: row.modified_at,
+            expires_at: row.expires_at,
+            is_deleted: row.is_deleted,
+        })
+    }
+}
+
+/// Node update written together with the change it describes
+#[derive(Debug, Clone)]
+pub struct OutboxMessage {
+    pub topic: Topic,
+    pub payload: Vec<u8>,
+}
+
+impl OutboxMessage {
+    pub fn new(topic: Topic, messages: Vec<Message>) -> Result<Self> {
+        let payload = rkyv::to_bytes::<_, 1024>(&messages)
+            .map_err(|e| Error::Custom(format!("Outbox serialization error: {:?}", e)))?;
+        Ok(Self {
+            topic,
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: uuid::Uuid,
+    pub endpoint: String,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+}
+
+#[async_trait::async_trait]
+pub trait NodeRepo: Send + Sync {
+    async fn upsert(&self, node_id: uuid::Uuid, node: Node) -> Result<()>;
+    async fn all(&self) -> Result<Vec<Node>>;
+    /// Nodes whose row or any inbound changed after `since`, with all their inbounds
+    async fn updated_since(&self, since: DateTime<Utc>) -> Result<Vec<Node>>;
+    async fn update_status(&self, uuid: &uuid::Uuid, env: &str, status: NodeStatus) -> Result<()>;
+}
+
+/// Writes take the node messages that must be committed with them
+#[async_trait::async_trait]
+pub trait ConnRepo: Send + Sync {
+    async fn all(&self) -> Result<Vec<ConnRow>>;
+    /// Rows touched after `since`, deleted ones included
+    async fn updated_since(&self, since: DateTime<Utc>) -> Result<Vec<ConnRow>>;
+    async fn insert(&self, conn: ConnRow, outbox: &[OutboxMessage]) -> Result<()>;
+    async fn delete(&self, conn_id: &uuid::Uuid, outbox: &[OutboxMessage]) -> Result<()>;
+    /// Undeletes the connection, moving it to `wg` if its address was reused
+    async fn restore(
+        &self,
+        conn_id: &uuid::Uuid,
+        wg: Option<&WgParam>,
+        outbox: &[OutboxMessage],
+    ) -> Result<()>;
+}
+
+#[async_trait::async_trait]
+pub trait SubscriptionRepo: Send + Sync {
+    /// Not deleted ones
+    async fn all(&self) -> Result<Vec<Subscription>>;
+    /// Rows touched after `since`, deleted ones included
+    async fn updated_since(&self, since: DateTime<Utc>) -> Result<Vec<Subscription>>;
+    async fn create(&self, sub: &Subscription) -> Result<Subscription>;
+    async fn update_subscription(
+        &self,
+        id: uuid::Uuid,
+        expires_at: DateTime<Utc>,
+        referred_by: Option<&str>,
+        ref_code: &str,
+    ) -> Result<Subscription>;
+    /// Extends the subscription and undeletes `restore` connections
+    /// atomically, together with their node messages
+    async fn add_days(
+        &self,
+        sub_id: &uuid::Uuid,
+        days: i64,
+        restore: &[(uuid::Uuid, Option<WgParam>)],
+        outbox: &[OutboxMessage],
+    ) -> Result<Subscription>;
+}
+
+#[async_trait::async_trait]
+pub trait KeyRepo: Send + Sync {
+    async fn get(&self, code: &str) -> Option<Key>;
+    async fn insert(&self, key: &Key) -> Result<()>;
+    async fn activate(&self, key: &Key) -> Result<()>;
+}
+
+#[async_trait::async_trait]
+pub trait IpPoolRepo: Send + Sync {
+    async fn all(&self) -> Result<Vec<(IpAddr, uuid::Uuid)>>;
+    /// Returns false if the address is held by another connection,
+    /// possibly allocated by another API instance
+    async fn insert(&self, address: &IpAddr, conn_id: &uuid::Uuid) -> Result<bool>;
+    async fn delete(&self, address: &IpAddr, conn_id: &uuid::Uuid) -> Result<()>;
+}
+
+#[async_trait::async_trait]
+pub trait WebhookRepo: Send + Sync {
+    /// Returns false if a delivery with the same dedup key already exists
+    async fn enqueue(
+        &self,
+        endpoint: &str,
+        event: &str,
+        dedup_key: &str,
+        payload: &str,
+    ) -> Result<bool>;
+    /// Leases due deliveries so concurrent workers don't pick the same rows
+    async fn fetch_due(&self, limit: i64, lease_sec: i64) -> Result<Vec<WebhookDelivery>>;
+    async fn mark_delivered(&self, id: &uuid::Uuid) -> Result<()>;
+    /// Schedules next attempt, or gives up when `next_attempt_at` is None
+    async fn mark_failed(
+        &self,
+        id: &uuid::Uuid,
+        error: &str,
+        next_attempt_at: Option<DateTime<Utc>>,
+    ) -> Result<()>;
+}
+
+#[async_trait::async_trait]
+pub trait OutboxRepo: Send + Sync {
+    /// Publishes pending messages in insert order and marks them delivered.
+    /// Stops at the first failure so later messages don't overtake it,
+    /// returns how many were delivered
+    async fn dispatch(&self, publisher: &Publisher, batch: i64) -> Result<usize>;
+    /// Drops delivered messages older than `retention_sec`
+    async fn purge_delivered(&self, retention_sec: i64) -> Result<u64>;
+}
+
+/// Everything the API persists, implemented by Postgres and the embedded file store
+#[async_trait::async_trait]
+pub trait Storage: Send + Sync {
+    fn node(&self) -> &dyn NodeRepo;
+    fn conn(&self) -> &dyn ConnRepo;
+    fn sub(&self) -> &dyn SubscriptionRepo;
+    fn key(&self) -> &dyn KeyRepo;
+    fn ip_pool(&self) -> &dyn IpPoolRepo;
+    fn webhook(&self) -> &dyn WebhookRepo;
+    fn outbox(&self) -> &dyn OutboxRepo;
+    /// Storage clock, change timestamps are compared against it
+    async fn now(&self) -> Result<DateTime<Utc>>;
+}
@@ -1,10 +1,11 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, Notify, RwLock};
 
-use super::{postgres::pg::PgContext, Cache};
+use super::{events::EventBus, storage::Db, Cache};
 use fcore::{
-    Connection, ConnectionApiOperations, ConnectionBaseOperations, NodeStorageOperations,
-    Publisher, SubscriptionOperations,
+    Connection, ConnectionApiOperations, ConnectionBaseOperations, Env, IpAddrMask, IpPool,
+    NodeStorageOperations, Publisher, SubscriptionOperations,
 };
 
 pub(crate) mod tasks;
@@ -17,8 +18,13 @@ where
     S: Send + Sync + Clone + 'static,
 {
     pub memory: Arc<RwLock<Cache<N, C, S>>>,
-    pub db: PgContext,
+    pub db: Db,
     pub publisher: Publisher,
+    pub events: EventBus,
+    pub wg_pool: Arc<Mutex<IpPool>>,
+    pub wg_prefixes_v6: Arc<HashMap<Env, IpAddrMask>>,
+    /// Wakes the outbox dispatcher after a commit
+    pub outbox_notify: Arc<Notify>,
 }
 
 impl<N, C, S> MemSync<N, C, S>
@@ -34,11 +40,21 @@ where
         + PartialEq,
     S: SubscriptionOperations + Send + Sync + Clone + 'static,
 {
-    pub fn new(memory: Arc<RwLock<Cache<N, C, S>>>, db: PgContext, publisher: Publisher) -> Self {
+    pub fn new(
+        memory: Arc<RwLock<Cache<N, C, S>>>,
+        db: Db,
+        publisher: Publisher,
+        wg_pool: IpPool,
+        wg_prefixes_v6: HashMap<Env, IpAddrMask>,
+    ) -> Self {
         Self {
             memory,
             db,
             publisher,
+            events: EventBus::new(),
+            wg_pool: Arc::new(Mutex::new(wg_pool)),
+            wg_prefixes_v6: Arc::new(wg_prefixes_v6),
+            outbox_notify: Arc::new(Notify::new()),
         }
     }
 }
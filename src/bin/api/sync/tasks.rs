@@ -1,18 +1,77 @@
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Utc};
 use futures::future::join_all;
+use std::collections::HashMap;
+use std::net::IpAddr;
 use tracing::{debug, error, info, warn};
 
 use fcore::{
-    Connection, ConnectionApiOperations, ConnectionBaseOperations, ConnectionStorageApiOperations,
-    Env, Node, NodeStatus, NodeStorageOperations, Status, Subscription, SubscriptionOperations,
-    SubscriptionStorageOperations, SyncError, Topic,
+    Command, Connection, ConnectionApiOperations, ConnectionBaseOperations,
+    ConnectionStorageApiOperations, Env, IpAddrMask, IpPool, Message, Node, NodeStatus,
+    NodeStorageOperations, Proto, Status, Subscription, SubscriptionOperations,
+    SubscriptionStorageOperations, SyncError, Topic, WgKeys, WgParam,
 };
 
-use super::super::{http::request::Subscription as SubReq, postgres::connection::ConnRow};
+use super::super::{
+    events::Event,
+    http::request::Subscription as SubReq,
+    storage::{ConnRow, IpPoolRepo, OutboxMessage},
+};
 use super::MemSync;
 
 type SyncResult<T> = std::result::Result<T, SyncError>;
 
+// Addresses taken concurrently by other API instances are skipped
+const WG_ALLOCATE_ATTEMPTS: usize = 16;
+
+async fn allocate_wg(
+    repo: &dyn IpPoolRepo,
+    pool: &mut IpPool,
+    conn_id: &uuid::Uuid,
+) -> SyncResult<IpAddrMask> {
+    for _ in 0..WG_ALLOCATE_ATTEMPTS {
+        let addr = pool
+            .allocate(*conn_id)
+            .map_err(|e| SyncError::Validation(e.to_string()))?;
+        let Some(ip) = addr.as_ipv4() else {
+            return Err(SyncError::Validation("Only IPv4 pool is supported".into()));
+        };
+
+        match repo.insert(&addr.address, conn_id).await {
+            Ok(true) => return Ok(addr),
+            Ok(false) => {
+                debug!("WG address {} is taken by another instance", ip);
+                pool.release(ip, conn_id);
+                pool.reserve_foreign(ip);
+            }
+            Err(e) => {
+                pool.release(ip, conn_id);
+                return Err(SyncError::Database(e));
+            }
+        }
+    }
+
+    Err(SyncError::Memory(format!(
+        "Couldn't allocate WG address for {} in {} attempts",
+        conn_id, WG_ALLOCATE_ATTEMPTS
+    )))
+}
+
+/// Hysteria2 connections go to auth, the rest to their env
+fn conn_topic<T: ConnectionBaseOperations + ConnectionApiOperations>(conn: &T) -> Topic {
+    if conn.get_token().is_some() {
+        Topic::Auth
+    } else {
+        conn.get_env().into()
+    }
+}
+
+fn conn_outbox<T: ConnectionBaseOperations + ConnectionApiOperations>(
+    conn: &T,
+    msg: Message,
+) -> SyncResult<OutboxMessage> {
+    OutboxMessage::new(conn_topic(conn), vec![msg]).map_err(SyncError::Database)
+}
+
 // Input validation traits
 trait Validate {
     fn validate(&self) -> SyncResult<()>;
@@ -61,6 +120,20 @@ where
         &self,
         sub_id: &uuid::Uuid,
     ) -> SyncResult<Vec<uuid::Uuid>>;
+    async fn send_command(&self, topic: &Topic, cmd: &Command) -> SyncResult<()>;
+    async fn publish_conn(&self, conn: &Connection, msg: Message) -> SyncResult<()>;
+    async fn allocate_wg_param(&self, conn_id: &uuid::Uuid, env: &Env) -> SyncResult<WgParam>;
+    async fn release_wg_address(
+        &self,
+        conn_id: &uuid::Uuid,
+        address: &IpAddrMask,
+    ) -> SyncResult<()>;
+    async fn reclaim_wg_address(
+        &self,
+        conn_id: &uuid::Uuid,
+        address: &IpAddrMask,
+    ) -> SyncResult<Option<IpAddrMask>>;
+    async fn load_wg_allocations(&self) -> SyncResult<()>;
 }
 
 #[async_trait::async_trait]
@@ -95,6 +168,7 @@ where
             error!("Failed to insert node {} into database: {}", node_id, e);
             return Err(SyncError::Database(e));
         }
+        self.outbox_notify.notify_one();
 
         // Insert into memory
         let result = {
@@ -103,12 +177,12 @@ where
         };
 
         match result {
-            Ok(Status::Ok(_)) | Ok(Status::AlreadyExist(_)) => {
-                info!("Successfully added node: {}", node_id);
-                Ok(Status::Ok(*node_id))
-            }
-            Ok(Status::Updated(_)) => {
+            Ok(Status::Ok(_)) | Ok(Status::AlreadyExist(_)) | Ok(Status::Updated(_)) => {
                 info!("Successfully added node: {}", node_id);
+                self.events.emit(Event::NodeRegistered {
+                    id: *node_id,
+                    env: node.env.clone(),
+                });
                 Ok(Status::Ok(*node_id))
             }
             Ok(Status::NotModified(id)) => {
@@ -143,6 +217,7 @@ where
             );
             return Err(SyncError::Database(e));
         }
+        self.outbox_notify.notify_one();
 
         // Insert into memory
         let result = {
@@ -153,6 +228,10 @@ where
         match result {
             Status::Ok(id) => {
                 info!("Successfully added subscription: {}", id);
+                self.events.emit(Event::SubscriptionCreated {
+                    id,
+                    expires_at: sub.expires_at(),
+                });
                 Ok(Status::Ok(id))
             }
             Status::Updated(id) => {
@@ -189,14 +268,22 @@ where
         // Create database row
         let conn_row: ConnRow = (*conn_id, conn.clone()).into();
 
-        // Insert into database first
-        if let Err(e) = self.db.conn().insert(conn_row).await {
+        // Mtproto is served without node updates
+        let outbox = if conn.get_proto().is_mtproto() {
+            vec![]
+        } else {
+            vec![conn_outbox(&conn, conn.as_create_message(conn_id))?]
+        };
+
+        // Insert into database first, the node message is committed with it
+        if let Err(e) = self.db.conn().insert(conn_row, &outbox).await {
             error!(
                 "Failed to insert connection {} into database: {}",
                 conn_id, e
             );
             return Err(SyncError::Database(e));
         }
+        self.outbox_notify.notify_one();
 
         // Insert into memory
         let result = {
@@ -209,8 +296,18 @@ where
         };
 
         match result {
-            Ok(Status::Ok(id)) | Ok(Status::AlreadyExist(id)) => {
+            Ok(Status::Ok(id)) => {
                 info!("Successfully added connection: {}", id);
+                self.events.emit(Event::ConnectionCreated {
+                    id,
+                    subscription_id: conn.get_subscription_id(),
+                    env: conn.get_env(),
+                    proto: conn.get_proto().proto(),
+                });
+                Ok(Status::Ok(id))
+            }
+            Ok(Status::AlreadyExist(id)) => {
+                info!("Connection already exists: {}", id);
                 Ok(Status::Ok(id))
             }
             Ok(Status::BadRequest(id, msg)) => {
@@ -250,7 +347,9 @@ where
             }
         }
 
-        if let Err(e) = self.db.conn().delete(conn_id).await {
+        let outbox = [conn_outbox(conn, conn.as_delete_message(conn_id))?];
+
+        if let Err(e) = self.db.conn().delete(conn_id, &outbox).await {
             error!(
                 "CRITICAL: Failed to delete connection {} from DB: {}",
                 conn_id, e
@@ -258,37 +357,15 @@ where
             return Err(SyncError::Database(e));
         }
         debug!("Connection {} successfully removed from database", conn_id);
-
-        let msg = vec![conn.as_delete_message(conn_id)];
-        let topic = if conn.get_token().is_some() {
-            Topic::Auth
-        } else {
-            conn.get_env().into()
-        };
-
-        match rkyv::to_bytes::<_, 1024>(&msg) {
-            Ok(bytes) => {
-                info!("Publishing delete command to topic: {}", topic);
-                if let Err(e) = self.publisher.send_binary(&topic, bytes.as_ref()).await {
-                    error!(
-                        "NETWORK ERROR: Failed to send delete signal for {} to bus: {:?}",
-                        conn_id, e
-                    );
-
-                    return Err(SyncError::Zmq(e));
-                }
-            }
-            Err(e) => {
-                error!("SERIALIZATION ERROR for connection {}: {:?}", conn_id, e);
-                return Err(SyncError::RkyvSerialize(e));
-            }
-        }
+        self.outbox_notify.notify_one();
 
         {
             let mut memory = self.memory.write().await;
-            if let Some(conn_mut) = memory.connections.get_mut(conn_id) {
+            let updated = memory.connections.update(conn_id, |conn_mut| {
                 conn_mut.set_deleted(true);
                 conn_mut.set_modified_at();
+            });
+            if updated.is_some() {
                 info!(
                     "Memory state updated: connection {} marked as deleted",
                     conn_id
@@ -296,7 +373,14 @@ where
             }
         }
 
+        if let Some(wg) = conn.get_wireguard() {
+            if let Err(e) = self.release_wg_address(conn_id, &wg.address).await {
+                warn!("Couldn't release WG address of {}: {}", conn_id, e);
+            }
+        }
+
         info!("Successfully completed deletion flow for: {}", conn_id);
+        self.events.emit(Event::ConnectionDeleted { id: *conn_id });
         Ok(Status::Ok(*conn_id))
     }
 
@@ -335,33 +419,10 @@ where
 
         let this = self.clone();
 
-        let tasks = conns_to_restore.into_iter().map(|(conn_id, conn)| {
+        let tasks = conns_to_restore.into_iter().map(|(conn_id, _)| {
             let this = this.clone();
             async move {
-                let msg = vec![conn.as_update_message(&conn_id)];
-
-                let bytes = match rkyv::to_bytes::<_, 1024>(&msg) {
-                    Ok(b) => b,
-                    Err(e) => {
-                        error!("Serialization failed for {}: {:?}", conn_id, e);
-                        return None;
-                    }
-                };
-
-                let topic = if conn.get_token().is_some() {
-                    Topic::Auth
-                } else {
-                    conn.get_env().into()
-                };
-
-                if let Err(e) = this.publisher.send_binary(&topic, bytes.as_ref()).await {
-                    error!(
-                        "Failed to send restore message for {} to {}: {:?}",
-                        conn_id, topic, e
-                    );
-                    return None;
-                }
-
+                // Node updates go out through the outbox
                 match this.restore_connection(&conn_id).await {
                     Ok(Status::Ok(_)) | Ok(Status::Updated(_)) => {
                         debug!("Connection {} restored", conn_id);
@@ -383,6 +444,174 @@ where
         Ok(results.into_iter().flatten().collect())
     }
 
+    async fn send_command(&self, topic: &Topic, cmd: &Command) -> SyncResult<()> {
+        let bytes = rkyv::to_bytes::<_, 256>(cmd).map_err(|e| {
+            error!("SERIALIZATION ERROR for command {}: {:?}", cmd.id, e);
+            SyncError::RkyvSerialize(e)
+        })?;
+
+        info!("Publishing command {} to topic: {}", cmd, topic);
+        self.publisher
+            .send_binary(topic, bytes.as_ref())
+            .await
+            .map_err(|e| {
+                error!("Failed to send command {} to {}: {:?}", cmd.id, topic, e);
+                SyncError::Zmq(e)
+            })
+    }
+
+    async fn publish_conn(&self, conn: &Connection, msg: Message) -> SyncResult<()> {
+        let topic = conn_topic(conn);
+
+        let bytes = rkyv::to_bytes::<_, 1024>(&vec![msg]).map_err(|e| {
+            error!("SERIALIZATION ERROR for connection message: {:?}", e);
+            SyncError::RkyvSerialize(e)
+        })?;
+
+        self.publisher
+            .send_binary(&topic, bytes.as_ref())
+            .await
+            .map_err(|e| {
+                error!("Failed to publish connection message to {}: {:?}", topic, e);
+                SyncError::Zmq(e)
+            })
+    }
+
+    async fn allocate_wg_param(&self, conn_id: &uuid::Uuid, env: &Env) -> SyncResult<WgParam> {
+        let repo = self.db.ip_pool();
+        let mut pool = self.wg_pool.lock().await;
+        let address = allocate_wg(repo, &mut pool, conn_id).await?;
+        let address_v6 = self
+            .wg_prefixes_v6
+            .get(env)
+            .and_then(|prefix| pool.ipv6_for(address.as_ipv4()?, prefix));
+
+        debug!(
+            "WG address {} {:?} allocated to {}",
+            address, address_v6, conn_id
+        );
+        Ok(WgParam {
+            keys: WgKeys::default(),
+            address,
+            address_v6,
+        })
+    }
+
+    async fn release_wg_address(
+        &self,
+        conn_id: &uuid::Uuid,
+        address: &IpAddrMask,
+    ) -> SyncResult<()> {
+        let repo = self.db.ip_pool();
+        let mut pool = self.wg_pool.lock().await;
+        if let Some(ip) = address.as_ipv4() {
+            pool.release(ip, conn_id);
+        }
+        repo.delete(&address.address, conn_id).await?;
+        debug!("WG address {} released by {}", address, conn_id);
+        Ok(())
+    }
+
+    async fn reclaim_wg_address(
+        &self,
+        conn_id: &uuid::Uuid,
+        address: &IpAddrMask,
+    ) -> SyncResult<Option<IpAddrMask>> {
+        let repo = self.db.ip_pool();
+        let mut pool = self.wg_pool.lock().await;
+
+        if let Some(ip) = address.as_ipv4() {
+            if !pool.contains(ip) {
+                // Allocated before the pool existed, leave as is
+                return Ok(None);
+            }
+            if pool.claim(ip, *conn_id).is_ok() {
+                if repo.insert(&address.address, conn_id).await? {
+                    return Ok(None);
+                }
+                pool.release(ip, conn_id);
+                pool.reserve_foreign(ip);
+            }
+            warn!(
+                "WG address {} of {} was reused, allocating a new one",
+                address, conn_id
+            );
+        }
+
+        allocate_wg(repo, &mut pool, conn_id).await.map(Some)
+    }
+
+    async fn load_wg_allocations(&self) -> SyncResult<()> {
+        let repo = self.db.ip_pool();
+        let rows = repo.all().await?;
+
+        // conn_id -> (deleted, address)
+        let wg_conns: HashMap<uuid::Uuid, (bool, IpAddr)> = {
+            let mem = self.memory.read().await;
+            mem.connections
+                .iter()
+                .filter_map(|(id, conn)| {
+                    conn.get_wireguard()
+                        .map(|wg| (*id, (conn.get_deleted(), wg.address.address)))
+                })
+                .collect()
+        };
+
+        let mut pool = self.wg_pool.lock().await;
+        let mut fresh = IpPool::new(pool.network().clone())?;
+
+        for (address, conn_id) in rows {
+            let IpAddr::V4(ip) = address else {
+                continue;
+            };
+            if matches!(wg_conns.get(&conn_id), Some((true, _))) {
+                // Deleted while the row wasn't released
+                repo.delete(&address, &conn_id).await?;
+                continue;
+            }
+            if fresh.contains(ip) {
+                let _ = fresh.claim(ip, conn_id);
+            }
+        }
+
+        let mut backfilled = 0;
+        let mut conflicts = 0;
+        for (conn_id, (deleted, address)) in wg_conns {
+            let IpAddr::V4(ip) = address else {
+                continue;
+            };
+            if deleted || !fresh.contains(ip) {
+                continue;
+            }
+            match fresh.owner(ip) {
+                Some(owner) if owner == conn_id => {}
+                Some(owner) => {
+                    conflicts += 1;
+                    warn!(
+                        "WG address conflict: {} of {} is allocated to {}",
+                        ip, conn_id, owner
+                    );
+                }
+                None => {
+                    if fresh.claim(ip, conn_id).is_ok() && repo.insert(&address, &conn_id).await? {
+                        backfilled += 1;
+                    }
+                }
+            }
+        }
+
+        info!(
+            "WG pool {}: {} allocated, {} available, {} backfilled, {} conflicts",
+            fresh.network(),
+            fresh.allocated(),
+            fresh.available(),
+            backfilled,
+            conflicts
+        );
+        *pool = fresh;
+        Ok(())
+    }
+
     async fn restore_connection(&self, conn_id: &uuid::Uuid) -> SyncResult<Status> {
         info!("Restoring connection: {}", conn_id);
 
@@ -392,7 +621,7 @@ where
             memory.connections.get(conn_id).cloned()
         };
 
-        let _conn = match current_conn {
+        let conn = match current_conn {
             Some(c) => c,
             None => {
                 warn!("Connection {} not found for restoration", conn_id);
@@ -400,25 +629,28 @@ where
             }
         };
 
+        let (new_wg, msg) = self.prepare_restore(conn_id, &conn).await?;
+
         // Undelete from database first
-        if let Err(e) = self.db.conn().restore(conn_id).await {
+        if let Err(e) = self
+            .db
+            .conn()
+            .restore(conn_id, new_wg.as_ref(), &[msg])
+            .await
+        {
             error!(
                 "Failed to restore connection {} from database: {}",
                 conn_id, e
             );
+            self.abort_restore(conn_id, &conn, new_wg.as_ref()).await;
             return Err(SyncError::Database(e));
         }
+        self.outbox_notify.notify_one();
 
-        // Mark as undeleted in memory
-        {
-            let mut memory = self.memory.write().await;
-            if let Some(conn_mut) = memory.connections.get_mut(conn_id) {
-                conn_mut.set_deleted(false);
-                conn_mut.set_modified_at();
-            }
-        }
+        self.apply_restore(conn_id, new_wg).await;
 
         info!("Successfully restored connection: {}", conn_id);
+        self.events.emit(Event::ConnectionRestored { id: *conn_id });
         Ok(Status::Ok(*conn_id))
     }
 
@@ -476,6 +708,11 @@ where
         }
 
         debug!("Successfully updated node {} status", uuid);
+        self.events.emit(Event::NodeStatusChanged {
+            id: *uuid,
+            env: env.clone(),
+            status,
+        });
         Ok(())
     }
 
@@ -484,8 +721,8 @@ where
 
         let mut memory = self.memory.write().await;
 
-        let sub = match memory.subscriptions.find_by_id_mut(sub_id) {
-            Some(s) => s,
+        let mut sub = match memory.subscriptions.find_by_id(sub_id) {
+            Some(s) => s.clone(),
             None => {
                 warn!("Subscription {} not found for update", sub_id);
                 return Ok(Status::NotFound(*sub_id));
@@ -507,6 +744,7 @@ where
         if let Some(limit_bytes) = req.limit_bytes {
             sub.set_limit_bytes(limit_bytes);
         }
+        memory.subscriptions.update(sub.clone());
 
         let expires_at = sub
             .expires_at()
@@ -529,64 +767,234 @@ where
         }
 
         info!("Successfully updated subscription: {}", sub_id);
+        self.events.emit(Event::SubscriptionUpdated {
+            id: *sub_id,
+            expires_at: Some(expires_at),
+        });
         Ok(Status::Updated(*sub_id))
     }
 
     async fn add_days(&self, sub_id: &uuid::Uuid, days: i64) -> SyncResult<Status> {
-        let sub_db = self.db.sub();
-
-        let was_inactive = {
+        let (was_inactive, deleted_conns) = {
             let mem = self.memory.read().await;
-            mem.subscriptions
-                .get(sub_id)
-                .map(|s| !s.is_active())
-                .unwrap_or(false)
+            let Some(sub) = mem.subscriptions.get(sub_id) else {
+                warn!("Subscription {} not found for update", sub_id);
+                return Ok(Status::NotFound(*sub_id));
+            };
+
+            let was_inactive = !sub.is_active();
+            let deleted_conns: Vec<(uuid::Uuid, C)> = if was_inactive {
+                mem.connections
+                    .get_by_subscription_id(sub_id)
+                    .map(|conns| conns.into_iter().filter(|(_, c)| c.get_deleted()).collect())
+                    .unwrap_or_default()
+            } else {
+                vec![]
+            };
+            (was_inactive, deleted_conns)
         };
 
-        {
-            let mut mem = self.memory.write().await;
+        // Connections are undeleted in the same transaction as the extension
+        let mut restore = Vec::with_capacity(deleted_conns.len());
+        let mut outbox = Vec::with_capacity(deleted_conns.len());
+        let mut prepared = Vec::with_capacity(deleted_conns.len());
+        for (conn_id, conn) in deleted_conns {
+            match self.prepare_restore(&conn_id, &conn).await {
+                Ok((wg, msg)) => {
+                    restore.push((conn_id, wg));
+                    outbox.push(msg);
+                    prepared.push(conn);
+                }
+                Err(e) => error!("Connection {} won't be restored: {:?}", conn_id, e),
+            }
+        }
 
-            let sub = match mem.subscriptions.find_by_id_mut(sub_id) {
-                Some(s) => s,
-                None => {
-                    warn!("Subscription {} not found for update", sub_id);
-                    return Ok(Status::NotFound(*sub_id));
+        let sub = match self
+            .db
+            .sub()
+            .add_days(sub_id, days, &restore, &outbox)
+            .await
+        {
+            Ok(sub) => sub,
+            Err(e) => {
+                for ((conn_id, wg), conn) in restore.iter().zip(&prepared) {
+                    self.abort_restore(conn_id, conn, wg.as_ref()).await;
                 }
-            };
+                return Err(SyncError::Database(e));
+            }
+        };
+        if !outbox.is_empty() {
+            self.outbox_notify.notify_one();
+        }
+
+        {
+            let mut mem = self.memory.write().await;
+            if let (Some(mem_sub), Some(expires_at)) =
+                (mem.subscriptions.find_by_id(sub_id), sub.expires_at())
+            {
+                let mut mem_sub = mem_sub.clone();
+                let _ = mem_sub.set_expires_at(expires_at);
+                mem.subscriptions.update(mem_sub);
+            }
+        }
 
-            let now = Utc::now();
+        self.events.emit(Event::SubscriptionExtended {
+            id: sub.id,
+            days,
+            expires_at: sub.expires_at(),
+        });
 
-            let new_expires = match sub.expires_at() {
-                Some(exp) if exp > now => exp + Duration::days(days),
-                _ => now + Duration::days(days),
-            };
-            let _ = sub.set_expires_at(new_expires);
+        if was_inactive {
+            info!(
+                "Restored {} connections after subscription activation {}",
+                restore.len(),
+                sub_id
+            );
+        }
+        for (conn_id, wg) in restore {
+            self.apply_restore(&conn_id, wg).await;
+            self.events.emit(Event::ConnectionRestored { id: conn_id });
         }
 
-        match sub_db.add_days(sub_id, days).await {
-            Ok(sub) => {
-                if was_inactive {
-                    info!(
-                        "Restoring connections after subscription activation {}",
-                        sub_id
-                    );
+        Ok(Status::Updated(sub.id))
+    }
+}
 
-                    match self.restore_connections_by_subscription(sub_id).await {
-                        Ok(restored) => {
-                            debug!(
-                                "Post-update restore: {} connections restored for {}",
-                                restored.len(),
-                                sub_id
-                            );
+impl<N, C, S> MemSync<N, C, S>
+where
+    N: NodeStorageOperations + Send + Sync + Clone + 'static,
+    C: ConnectionBaseOperations
+        + ConnectionApiOperations
+        + Send
+        + Sync
+        + Clone
+        + 'static
+        + From<Connection>
+        + PartialEq,
+    Connection: From<C>,
+    S: SubscriptionOperations
+        + Send
+        + Sync
+        + Clone
+        + 'static
+        + PartialEq
+        + std::convert::From<Subscription>,
+{
+    /// Marks nodes offline when nothing was heard from them for `timeout`
+    /// and back online once they report again. Nodes never heard from
+    /// count from `since`
+    pub async fn check_node_liveness<F>(
+        &self,
+        last_seen: F,
+        since: DateTime<Utc>,
+        timeout: chrono::Duration,
+    ) where
+        F: Fn(&uuid::Uuid) -> Option<DateTime<Utc>> + Sync,
+    {
+        let now = Utc::now();
+        let changes: Vec<(uuid::Uuid, Env, NodeStatus)> = {
+            let memory = self.memory.read().await;
+            memory
+                .nodes
+                .iter_nodes()
+                .filter_map(|(id, node)| {
+                    let seen = last_seen(id);
+                    match node.status {
+                        NodeStatus::Online if now - seen.unwrap_or(since) >= timeout => {
+                            Some((*id, node.env.clone(), NodeStatus::Offline))
                         }
-                        Err(e) => {
-                            error!("Post-update restore FAILED for {}: {:?}", sub_id, e);
+                        NodeStatus::Offline if seen.is_some_and(|t| now - t < timeout) => {
+                            Some((*id, node.env.clone(), NodeStatus::Online))
                         }
+                        _ => None,
                     }
+                })
+                .collect()
+        };
+
+        for (id, env, status) in changes {
+            match status {
+                NodeStatus::Offline => warn!(
+                    "Node {} in {} silent for {}s, marking offline",
+                    id,
+                    env,
+                    timeout.num_seconds()
+                ),
+                NodeStatus::Online => info!("Node {} in {} reports again", id, env),
+            }
+            if let Err(e) = self.update_node_status(&id, &env, status).await {
+                error!("Failed to update node {} status: {:?}", id, e);
+            }
+        }
+    }
+
+    /// New WG address if the old one was reused while the connection was
+    /// deleted, and the node message announcing the restored connection
+    async fn prepare_restore(
+        &self,
+        conn_id: &uuid::Uuid,
+        conn: &C,
+    ) -> SyncResult<(Option<WgParam>, OutboxMessage)> {
+        let new_wg = match conn.get_wireguard() {
+            Some(wg) => match self.reclaim_wg_address(conn_id, &wg.address).await? {
+                Some(address) => {
+                    let address_v6 =
+                        match (self.wg_prefixes_v6.get(&conn.get_env()), address.as_ipv4()) {
+                            (Some(prefix), Some(ip)) => {
+                                self.wg_pool.lock().await.ipv6_for(ip, prefix)
+                            }
+                            _ => None,
+                        };
+                    Some(WgParam {
+                        keys: wg.keys.clone(),
+                        address,
+                        address_v6,
+                    })
                 }
-                Ok(Status::Updated(sub.id))
+                None => None,
+            },
+            None => None,
+        };
+
+        let mut restored = conn.clone();
+        restored.set_deleted(false);
+        if let Some(wg) = new_wg.clone() {
+            restored.set_proto(Proto::Wireguard { param: wg });
+        }
+        let msg = match conn_outbox(&restored, restored.as_update_message(conn_id)) {
+            Ok(msg) => msg,
+            Err(e) => {
+                self.abort_restore(conn_id, conn, new_wg.as_ref()).await;
+                return Err(e);
             }
-            Err(e) => Err(SyncError::Database(e)),
+        };
+
+        Ok((new_wg, msg))
+    }
+
+    /// Gives back the address claimed by `prepare_restore` when the restore
+    /// didn't commit, the connection stays deleted and holds none
+    async fn abort_restore(&self, conn_id: &uuid::Uuid, conn: &C, new_wg: Option<&WgParam>) {
+        let Some(wg) = new_wg.or(conn.get_wireguard()) else {
+            return;
+        };
+        if let Err(e) = self.release_wg_address(conn_id, &wg.address).await {
+            error!(
+                "Failed to release WG address {} of {}: {:?}",
+                wg.address, conn_id, e
+            );
         }
     }
+
+    async fn apply_restore(&self, conn_id: &uuid::Uuid, new_wg: Option<WgParam>) {
+        let mut memory = self.memory.write().await;
+        memory.connections.update(conn_id, |conn_mut| {
+            conn_mut.set_deleted(false);
+            if let Some(wg) = new_wg {
+                info!("Connection {} got new WG address {}", conn_id, wg.address);
+                conn_mut.set_proto(Proto::Wireguard { param: wg });
+            }
+            conn_mut.set_modified_at();
+        });
+    }
 }
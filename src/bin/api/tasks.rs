@@ -1,28 +1,39 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use rand::Rng;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use tracing::{debug, error, info, warn};
 
 use fcore::{
-    measure_time, Connection, ConnectionBaseOperations, ConnectionStorageApiOperations, Env, Node,
-    Result, Status, Subscription, SubscriptionOperations,
+    measure_time, Connection, ConnectionApiOperations, ConnectionBaseOperations,
+    ConnectionStorageApiOperations, Env, Message, Node, Result, Status, Subscription,
+    SubscriptionOperations, SubscriptionStorageOperations,
 };
 
 use super::{
+    events::Event,
     postgres::pg::Tasks as MemoryCacheTasks,
     service::{Cache, Service},
     sync::tasks::SyncOp,
 };
 
+/// Rows committed around the previous sync may carry slightly older timestamps
+const DELTA_OVERLAP: chrono::Duration = chrono::Duration::seconds(30);
+
 #[async_trait::async_trait]
 pub trait Tasks {
     async fn get_state_from_db(&self) -> Result<()>;
+    async fn sync_db_changes(&self) -> Result<()>;
+    async fn warm_start(&self) -> Result<bool>;
     async fn periodic_db_sync(&self, interval_sec: u64);
+    async fn periodic_cache_snapshot(&self, interval_sec: u64);
     async fn cleanup_expired_connections(&self, interval_sec: u64);
     async fn cleanup_expired_subscriptions(&self, interval_sec: u64);
     async fn restore_subscriptions(&self, interval_sec: u64);
+    async fn dispatch_outbox(&self, poll_interval_ms: u64, batch: i64, retention_sec: i64);
+    async fn watch_node_liveness(&self, timeout_sec: u64);
 }
 
 #[async_trait::async_trait]
@@ -74,6 +85,9 @@ impl Tasks for Service<HashMap<Env, Vec<Node>>, Connection, Subscription> {
     async fn cleanup_expired_subscriptions(&self, interval_sec: u64) {
         let mut interval = tokio::time::interval(Duration::from_secs(interval_sec));
 
+        // Subscriptions already expired at startup are not reported
+        let mut reported: Option<HashSet<uuid::Uuid>> = None;
+
         loop {
             interval.tick().await;
             debug!("Run cleanup subscriptions task");
@@ -86,6 +100,21 @@ impl Tasks for Service<HashMap<Env, Vec<Node>>, Connection, Subscription> {
                     .collect()
             };
 
+            match reported.as_mut() {
+                None => reported = Some(expired_subs.iter().copied().collect()),
+                Some(reported) => {
+                    let expired: HashSet<uuid::Uuid> = expired_subs.iter().copied().collect();
+                    reported.retain(|id| expired.contains(id));
+                    for sub_id in &expired_subs {
+                        if reported.insert(*sub_id) {
+                            self.sync
+                                .events
+                                .emit(Event::SubscriptionExpired { id: *sub_id });
+                        }
+                    }
+                }
+            }
+
             for sub_id in expired_subs {
                 let conns_to_delete: Vec<(uuid::Uuid, Connection)> = {
                     let mem = self.sync.memory.read().await;
@@ -157,13 +186,22 @@ impl Tasks for Service<HashMap<Env, Vec<Node>>, Connection, Subscription> {
 
     async fn periodic_db_sync(&self, interval_sec: u64) {
         let base = Duration::from_secs(interval_sec);
+        let full_every = self.settings.tasks.db_full_sync_every.max(1);
+        let mut round: u64 = 0;
 
         loop {
             let jitter = rand::thread_rng().gen_range(0..=30);
             let interval_sec_with_jitter = base + Duration::from_secs(jitter);
             tokio::time::sleep(interval_sec_with_jitter).await;
+            round += 1;
 
-            if let Err(e) = measure_time(self.get_state_from_db(), "Periodic DB Sync").await {
+            let res = if round.is_multiple_of(full_every) {
+                measure_time(self.get_state_from_db(), "Periodic DB Sync").await
+            } else {
+                measure_time(self.sync_db_changes(), "Periodic DB Delta Sync").await
+            };
+
+            if let Err(e) = res {
                 error!("Periodic DB sync failed: {:?}", e);
             } else {
                 info!("Periodic DB sync completed successfully");
@@ -173,7 +211,7 @@ impl Tasks for Service<HashMap<Env, Vec<Node>>, Connection, Subscription> {
 
     async fn get_state_from_db(&self) -> Result<()> {
         let db = self.sync.db.clone();
-        let mut mem = self.sync.memory.write().await;
+        let started_at = db.now().await?.timestamp();
 
         let mut tmp_mem: Cache<HashMap<Env, Vec<Node>>, Connection, Subscription> = Cache::new();
 
@@ -194,8 +232,220 @@ impl Tasks for Service<HashMap<Env, Vec<Node>>, Connection, Subscription> {
             tmp_mem.add_subscription(sub).await;
         }
 
-        *mem = tmp_mem;
+        // Handlers only wait for the swap, not for the queries
+        *self.sync.memory.write().await = tmp_mem;
+        self.last_db_sync.store(started_at, Ordering::Relaxed);
+
+        // Handlers kept writing while the queries ran, replay their rows on top
+        if let Err(e) = self.sync_db_changes().await {
+            error!("Failed to catch up after the full DB sync: {:?}", e);
+        }
+
+        if let Err(e) = self.sync.load_wg_allocations().await {
+            error!("Failed to load WG allocations: {}", e);
+        }
 
         Ok(())
     }
+
+    /// Applies rows changed since the last sync. Connections that differ from
+    /// memory were changed elsewhere (another instance or a manual edit) and are
+    /// published to nodes, which treat repeated messages as no-ops
+    async fn sync_db_changes(&self) -> Result<()> {
+        let last_sync = self.last_db_sync.load(Ordering::Relaxed);
+        let Some(last_sync) = DateTime::from_timestamp(last_sync, 0).filter(|_| last_sync > 0)
+        else {
+            return self.get_state_from_db().await;
+        };
+
+        let db = self.sync.db.clone();
+        let started_at = db.now().await?;
+        let since = last_sync - DELTA_OVERLAP;
+
+        let node_repo = db.node();
+        let conn_repo = db.conn();
+        let sub_repo = db.sub();
+
+        let (nodes, conns, subscriptions) = tokio::try_join!(
+            node_repo.updated_since(since),
+            conn_repo.updated_since(since),
+            sub_repo.updated_since(since)
+        )?;
+
+        let mut changed: Vec<(uuid::Uuid, Connection, Message)> = Vec::new();
+        {
+            let mut mem = self.sync.memory.write().await;
+
+            for node in nodes {
+                mem.add_node(node).await?;
+            }
+
+            for sub in subscriptions {
+                if sub.is_deleted {
+                    mem.subscriptions.delete(&sub.id);
+                } else {
+                    mem.add_subscription(sub).await;
+                }
+            }
+
+            for row in conns {
+                let conn_id = row.conn_id;
+                let conn: Connection = match row.try_into() {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!("Skipping changed connection {}: {}", conn_id, e);
+                        continue;
+                    }
+                };
+
+                // Equality skips stats and timestamps, those still land in memory.
+                // Nodes do get expires_at, so a change of it is published
+                let msg = match mem.connections.get(&conn_id) {
+                    Some(existing)
+                        if existing == &conn
+                            && existing.get_expires_at() == conn.get_expires_at() =>
+                    {
+                        None
+                    }
+                    _ if conn.get_deleted() => Some(conn.as_delete_message(&conn_id)),
+                    Some(_) => Some(conn.as_update_message(&conn_id)),
+                    None => Some(conn.as_create_message(&conn_id)),
+                };
+
+                mem.connections.insert(conn_id, conn.clone());
+                if let Some(msg) = msg {
+                    changed.push((conn_id, conn, msg));
+                }
+            }
+        }
+        self.last_db_sync
+            .store(started_at.timestamp(), Ordering::Relaxed);
+
+        let wg_changed = changed
+            .iter()
+            .any(|(_, conn, _)| conn.get_wireguard().is_some());
+
+        for (conn_id, conn, msg) in changed {
+            debug!("Connection {} changed outside this instance", conn_id);
+            if let Err(e) = self.sync.publish_conn(&conn, msg).await {
+                error!("Failed to publish change of {}: {:?}", conn_id, e);
+            }
+        }
+
+        if wg_changed {
+            if let Err(e) = self.sync.load_wg_allocations().await {
+                error!("Failed to load WG allocations: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fills memory from the cache snapshot, false if there is none to use
+    async fn warm_start(&self) -> Result<bool> {
+        let Some(snapshot) = &self.cache_snapshot else {
+            return Ok(false);
+        };
+
+        let Some((state, synced_at)) = snapshot.load().await? else {
+            info!("No usable cache snapshot at {}", snapshot.path);
+            return Ok(false);
+        };
+
+        info!(
+            "Cache snapshot loaded: {} nodes, {} connections, {} subscriptions, synced at {}",
+            state.nodes.values().map(Vec::len).sum::<usize>(),
+            state.connections.len(),
+            state.subscriptions.len(),
+            synced_at
+        );
+
+        *self.sync.memory.write().await = state;
+        self.last_db_sync.store(synced_at, Ordering::Relaxed);
+
+        if let Err(e) = self.sync.load_wg_allocations().await {
+            error!("Failed to load WG allocations: {}", e);
+        }
+
+        Ok(true)
+    }
+
+    async fn periodic_cache_snapshot(&self, interval_sec: u64) {
+        let Some(snapshot) = &self.cache_snapshot else {
+            return;
+        };
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_sec));
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let synced_at = self.last_db_sync.load(Ordering::Relaxed);
+            if synced_at == 0 {
+                continue;
+            }
+
+            let state = self.sync.memory.read().await.clone();
+            match measure_time(snapshot.save(&state, synced_at), "Cache snapshot").await {
+                Ok(()) => debug!("Cache snapshot written to {}", snapshot.path),
+                Err(e) => error!("Failed to write cache snapshot: {}", e),
+            }
+        }
+    }
+
+    async fn dispatch_outbox(&self, poll_interval_ms: u64, batch: i64, retention_sec: i64) {
+        let outbox = self.sync.db.outbox();
+        let poll_interval = Duration::from_millis(poll_interval_ms);
+        let mut purge = tokio::time::interval(Duration::from_secs(3600));
+
+        loop {
+            // Polling picks up rows left by crashed instances or failed publishes
+            tokio::select! {
+                _ = self.sync.outbox_notify.notified() => {}
+                _ = tokio::time::sleep(poll_interval) => {}
+                _ = purge.tick() => {
+                    match outbox.purge_delivered(retention_sec).await {
+                        Ok(0) => {}
+                        Ok(n) => debug!("Purged {} delivered outbox messages", n),
+                        Err(e) => error!("Failed to purge outbox: {}", e),
+                    }
+                }
+            }
+
+            loop {
+                match outbox.dispatch(&self.sync.publisher, batch).await {
+                    Ok(n) if n as i64 == batch => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        error!("Outbox dispatch failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn watch_node_liveness(&self, timeout_sec: u64) {
+        let timeout = chrono::Duration::seconds(timeout_sec as i64);
+        let mut interval =
+            tokio::time::interval(Duration::from_secs((timeout_sec / 4).clamp(1, 60)));
+        // Metrics don't survive a restart, silent nodes get the full timeout from here
+        let since = Utc::now();
+
+        loop {
+            interval.tick().await;
+            debug!("Run node liveness check");
+            self.sync
+                .check_node_liveness(
+                    |id| {
+                        self.metrics
+                            .last_seen(id)
+                            .and_then(DateTime::from_timestamp_millis)
+                    },
+                    since,
+                    timeout,
+                )
+                .await;
+        }
+    }
 }
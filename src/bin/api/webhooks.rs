@@ -0,0 +1,412 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use tracing::{debug, error, info, warn};
+
+use fcore::{Connection, Env, Node, NodeStatus, Subscription, SubscriptionOperations};
+
+use super::{
+    config::WebhooksConfig,
+    events::{Event, EventEnvelope},
+    service::Service,
+    storage::{WebhookDelivery, WebhookRepo},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_HEADER: &str = "X-Fcore-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Fcore-Timestamp";
+pub const EVENT_HEADER: &str = "X-Fcore-Event";
+pub const DELIVERY_HEADER: &str = "X-Fcore-Delivery";
+
+/// Condition events are re-emitted at most once a day
+const EMITTED_TTL: Duration = Duration::from_secs(86400);
+
+/// Webhook name and dedup key for events delivered to external services
+fn webhook_event(envelope: &EventEnvelope) -> Option<(&'static str, String)> {
+    match &envelope.event {
+        Event::TrialCreated {
+            subscription_id, ..
+        } => Some((
+            "trial_created",
+            format!("trial_created:{}", subscription_id),
+        )),
+        Event::SubscriptionExpiring {
+            id,
+            days_left,
+            expires_at,
+        } => Some((
+            "subscription_expiring",
+            format!(
+                "subscription_expiring:{}:{}:{}",
+                id,
+                days_left,
+                expires_at.timestamp()
+            ),
+        )),
+        Event::QuotaExceeded {
+            id, limit_bytes, ..
+        } => Some((
+            "quota_exceeded",
+            format!("quota_exceeded:{}:{}", id, limit_bytes),
+        )),
+        Event::KeyActivated { id, .. } => Some(("key_activated", format!("key_activated:{}", id))),
+        Event::NodeStatusChanged {
+            id,
+            status: NodeStatus::Offline,
+            ..
+        } => Some((
+            "node_offline",
+            format!(
+                "node_offline:{}:{}",
+                id,
+                envelope.timestamp.timestamp_millis()
+            ),
+        )),
+        _ => None,
+    }
+}
+
+/// Hex HMAC-SHA256 of "<timestamp>.<body>"
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn backoff(config: &WebhooksConfig, attempts: i32) -> Duration {
+    let exp = attempts.clamp(0, 20) as u32;
+    let delay = config
+        .backoff_base_sec
+        .saturating_mul(2u64.saturating_pow(exp))
+        .min(config.backoff_max_sec);
+    let jitter = rand::thread_rng().gen_range(0..=delay / 10);
+    Duration::from_secs(delay + jitter)
+}
+
+/// Queues a delivery per subscribed endpoint if the event is a webhook one
+async fn queue_webhooks(db: &dyn WebhookRepo, config: &WebhooksConfig, envelope: &EventEnvelope) {
+    let Some((name, dedup_key)) = webhook_event(envelope) else {
+        return;
+    };
+
+    let payload = serde_json::json!({
+        "event": name,
+        "timestamp": envelope.timestamp,
+        "data": envelope.event,
+    })
+    .to_string();
+
+    for endpoint in &config.endpoints {
+        if !endpoint.events.is_empty() && !endpoint.events.iter().any(|e| e == name) {
+            continue;
+        }
+
+        match db.enqueue(&endpoint.url, name, &dedup_key, &payload).await {
+            Ok(true) => debug!("Webhooks: queued {} for {}", name, endpoint.url),
+            Ok(false) => debug!("Webhooks: {} already queued", dedup_key),
+            Err(e) => error!("Webhooks: failed to queue {}: {}", dedup_key, e),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Webhooks {
+    async fn run_webhook_listener(&self);
+    async fn scan_webhook_conditions(&self, interval_sec: u64);
+    async fn deliver_webhooks(&self, interval_sec: u64);
+    async fn deliver(&self, client: &reqwest::Client, delivery: WebhookDelivery);
+}
+
+#[async_trait::async_trait]
+impl Webhooks for Service<HashMap<Env, Vec<Node>>, Connection, Subscription> {
+    async fn run_webhook_listener(&self) {
+        let config = &self.settings.webhooks;
+        let mut rx = self.sync.events.subscribe();
+        let db = self.sync.db.webhook();
+
+        loop {
+            let envelope = match rx.recv().await {
+                Ok(envelope) => envelope,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Webhooks: listener lagged, {} events skipped", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => {
+                    warn!("Webhooks: event bus closed");
+                    return;
+                }
+            };
+
+            queue_webhooks(db, config, &envelope).await;
+        }
+    }
+
+    async fn scan_webhook_conditions(&self, interval_sec: u64) {
+        let expiring_days = &self.settings.webhooks.expiring_days;
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_sec));
+        // Keeps the bus quiet between scans, the DB dedup key covers anything older
+        let mut emitted: HashMap<String, Instant> = HashMap::new();
+
+        loop {
+            interval.tick().await;
+            debug!("Run webhook conditions scan");
+            emitted.retain(|_, at| at.elapsed() < EMITTED_TTL);
+
+            let mut events = vec![];
+            {
+                let mem = self.sync.memory.read().await;
+                for (id, sub) in mem.subscriptions.iter() {
+                    if !sub.is_active() {
+                        continue;
+                    }
+
+                    if let (Some(days_left), Some(expires_at)) =
+                        (sub.days_remaining(), sub.expires_at())
+                    {
+                        if expiring_days.contains(&days_left) {
+                            events.push(Event::SubscriptionExpiring {
+                                id: *id,
+                                days_left,
+                                expires_at,
+                            });
+                        }
+                    }
+
+                    if let (Some(limit_bytes), Some(downlink_bytes)) =
+                        (sub.limit_bytes(), sub.downlink_bytes())
+                    {
+                        if limit_bytes > 0 && downlink_bytes >= limit_bytes {
+                            events.push(Event::QuotaExceeded {
+                                id: *id,
+                                limit_bytes,
+                                downlink_bytes,
+                            });
+                        }
+                    }
+                }
+            }
+
+            for event in events {
+                let key = match &event {
+                    Event::SubscriptionExpiring {
+                        id,
+                        days_left,
+                        expires_at,
+                    } => format!("{}:{}:{}", id, days_left, expires_at.timestamp()),
+                    Event::QuotaExceeded {
+                        id, limit_bytes, ..
+                    } => {
+                        format!("{}:{}", id, limit_bytes)
+                    }
+                    _ => continue,
+                };
+                if let Entry::Vacant(entry) = emitted.entry(key) {
+                    entry.insert(Instant::now());
+                    self.sync.events.emit(event);
+                }
+            }
+        }
+    }
+
+    async fn deliver_webhooks(&self, interval_sec: u64) {
+        let config = &self.settings.webhooks;
+        let client = match reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_sec))
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Webhooks: failed to build HTTP client: {}", e);
+                return;
+            }
+        };
+        let db = self.sync.db.webhook();
+        let lease_sec = (config.timeout_sec + 60) as i64;
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_sec));
+
+        loop {
+            interval.tick().await;
+
+            let due = match db.fetch_due(config.batch_size, lease_sec).await {
+                Ok(due) => due,
+                Err(e) => {
+                    error!("Webhooks: failed to fetch due deliveries: {}", e);
+                    continue;
+                }
+            };
+
+            if !due.is_empty() {
+                debug!("Webhooks: {} deliveries due", due.len());
+            }
+
+            for delivery in due {
+                self.deliver(&client, delivery).await;
+            }
+        }
+    }
+
+    async fn deliver(&self, client: &reqwest::Client, delivery: WebhookDelivery) {
+        let config = &self.settings.webhooks;
+        let db = self.sync.db.webhook();
+
+        let result = match config.endpoints.iter().find(|e| e.url == delivery.endpoint) {
+            None => Err("endpoint is not configured".to_string()),
+            Some(endpoint) => {
+                let timestamp = Utc::now().timestamp();
+                let signature = sign(&endpoint.secret, timestamp, &delivery.payload);
+
+                client
+                    .post(&endpoint.url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+                    .header(TIMESTAMP_HEADER, timestamp.to_string())
+                    .header(EVENT_HEADER, &delivery.event)
+                    .header(DELIVERY_HEADER, delivery.id.to_string())
+                    .body(delivery.payload.clone())
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|res| {
+                        if res.status().is_success() {
+                            Ok(())
+                        } else {
+                            Err(format!("HTTP {}", res.status()))
+                        }
+                    })
+            }
+        };
+
+        let res = match result {
+            Ok(()) => {
+                info!(
+                    "Webhooks: {} {} delivered to {}",
+                    delivery.event, delivery.id, delivery.endpoint
+                );
+                db.mark_delivered(&delivery.id).await
+            }
+            Err(err) => {
+                let attempts = delivery.attempts + 1;
+                let next_attempt_at = (attempts < config.max_attempts).then(|| {
+                    let delay = backoff(config, delivery.attempts);
+                    Utc::now() + ChronoDuration::seconds(delay.as_secs() as i64)
+                });
+
+                match next_attempt_at {
+                    Some(at) => warn!(
+                        "Webhooks: {} {} to {} failed ({}), retry at {}",
+                        delivery.event, delivery.id, delivery.endpoint, err, at
+                    ),
+                    None => error!(
+                        "Webhooks: {} {} to {} failed ({}), giving up after {} attempts",
+                        delivery.event, delivery.id, delivery.endpoint, err, attempts
+                    ),
+                }
+                db.mark_failed(&delivery.id, &err, next_attempt_at).await
+            }
+        };
+
+        if let Err(e) = res {
+            error!("Webhooks: failed to update delivery {}: {}", delivery.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::WebhookEndpoint,
+        service::State,
+        storage::{file::FileStorage, Storage},
+        sync::{tasks::SyncOp, MemSync},
+    };
+    use fcore::{IpPool, NodeAddress, NodeStorageOperations, NodeType, Publisher};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    fn node() -> Node {
+        let now = Utc::now();
+        Node {
+            uuid: uuid::Uuid::new_v4(),
+            env: Env::Dev,
+            hostname: "node-1".to_string(),
+            address: NodeAddress::Ip("127.0.0.1".parse().unwrap()),
+            status: NodeStatus::Online,
+            label: "node-1".to_string(),
+            interface: "eth0".to_string(),
+            created_at: now,
+            modified_at: now,
+            inbounds: HashMap::new(),
+            cores: 1,
+            max_bandwidth_bps: 0,
+            country: "NL".to_string(),
+            r#type: NodeType::Common,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_silent_node_queues_node_offline() {
+        let path = std::env::temp_dir()
+            .join(format!("webhooks-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        let storage = Arc::new(FileStorage::open(&path).await.unwrap());
+        let publisher = Publisher::bind(&format!("inproc://webhooks-{}", uuid::Uuid::new_v4()))
+            .await
+            .unwrap();
+        let sync: MemSync<_, Connection, Subscription> = MemSync::new(
+            Arc::new(RwLock::new(State::new())),
+            storage.clone(),
+            publisher,
+            IpPool::new("10.0.0.0/24".parse().unwrap()).unwrap(),
+            HashMap::new(),
+        );
+
+        let node = node();
+        sync.add_node(&node.uuid, node.clone()).await.unwrap();
+        let mut rx = sync.events.subscribe();
+
+        let now = Utc::now();
+        let stale = now - ChronoDuration::minutes(10);
+        sync.check_node_liveness(|_| Some(stale), now, ChronoDuration::minutes(5))
+            .await;
+
+        let envelope = rx.recv().await.unwrap();
+        assert!(matches!(
+            envelope.event,
+            Event::NodeStatusChanged {
+                status: NodeStatus::Offline,
+                ..
+            }
+        ));
+        let stored = sync.memory.read().await.nodes.get_by_id(&node.uuid);
+        assert_eq!(stored.unwrap().status, NodeStatus::Offline);
+
+        let config = WebhooksConfig {
+            endpoints: vec![WebhookEndpoint {
+                url: "http://localhost/hook".to_string(),
+                secret: "secret".to_string(),
+                events: vec!["node_offline".to_string()],
+            }],
+            ..Default::default()
+        };
+        queue_webhooks(storage.webhook(), &config, &envelope).await;
+
+        let due = storage.webhook().fetch_due(10, 60).await.unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].event, "node_offline");
+        assert!(due[0].payload.contains(&node.uuid.to_string()));
+
+        let _ = tokio::fs::remove_file(&path).await;
+        let _ = tokio::fs::remove_file(format!("{}.log", path)).await;
+    }
+}
@@ -1,7 +1,8 @@
+use reqwest::Url;
 use serde::Deserialize;
 use std::net::Ipv4Addr;
 
-use fcore::{ApiAccessConfig, MetricsTxConfig, NodeConfigRaw, Result, Settings};
+use fcore::{ApiAccessConfig, Error, MetricsTxConfig, NodeConfigRaw, Result, Settings};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct ServiceSettings {
@@ -9,10 +10,16 @@ pub struct ServiceSettings {
     pub node: NodeConfigRaw,
     pub api: ApiAccessConfig,
     pub metrics: MetricsTxConfig,
+    #[serde(default)]
+    pub h2: H2StatsConfig,
 }
 
 impl Settings for ServiceSettings {
     fn validate(&self) -> Result<()> {
+        if self.h2.enabled {
+            Url::parse(&self.h2.endpoint)
+                .map_err(|e| Error::Custom(format!("h2.endpoint: {}", e)))?;
+        }
         Ok(())
     }
 }
@@ -25,6 +32,10 @@ fn default_listen_port() -> u16 {
     3000
 }
 
+fn default_h2_stats_endpoint() -> String {
+    "http://127.0.0.1:25000".to_string()
+}
+
 fn default_cors_origin() -> String {
     "http://localhost:8080".to_string()
 }
@@ -42,3 +53,23 @@ pub struct ServiceConfig {
     pub origin: String,
     pub updates_endpoint_zmq: String,
 }
+
+/// Hysteria2 `trafficStats` API of the server this auth service backs
+#[derive(Clone, Debug, Deserialize)]
+pub struct H2StatsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_h2_stats_endpoint")]
+    pub endpoint: String,
+    pub secret: Option<String>,
+}
+
+impl Default for H2StatsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: default_h2_stats_endpoint(),
+            secret: None,
+        }
+    }
+}
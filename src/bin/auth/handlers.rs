@@ -1,21 +1,27 @@
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 
 use fcore::{ConnectionBaseOperations, ConnectionStorageBaseOperations, Connections};
 
+use super::metrics::AuthStats;
 use super::request;
 use super::response;
 
 pub async fn auth_handler<C>(
     req: request::Auth,
     memory: Arc<RwLock<Connections<C>>>,
+    stats: Arc<AuthStats>,
 ) -> Result<impl warp::Reply, warp::Rejection>
 where
     C: ConnectionBaseOperations + Sync + Send + Clone + 'static + std::fmt::Display,
 {
     tracing::debug!("Auth req {} {} {}", req.auth, req.addr, req.tx);
-    let mem = memory.read().await;
-    if let Some(id) = mem.validate_token(&req.auth) {
+    let started = Instant::now();
+    let id = memory.read().await.validate_token(&req.auth);
+    stats.record(id.is_some(), started.elapsed());
+
+    if let Some(id) = id {
         Ok(warp::reply::json(&response::Auth {
             ok: true,
             id: Some(id.to_string()),
@@ -1,4 +1,9 @@
-use fcore::{ConnectionBaseOperations, HasMetrics, MetricBuffer, Node};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use fcore::{
+    ConnectionBaseOperations, ConnectionStorageBaseOperations, HasMetrics, MetricBuffer, Node,
+};
 
 use super::service::Service;
 
@@ -14,3 +19,128 @@ where
         &self.node
     }
 }
+
+/// Counters of the auth endpoint, latency is reset on every collection
+#[derive(Default, Debug)]
+pub struct AuthStats {
+    pub accepted: AtomicU64,
+    pub rejected: AtomicU64,
+    latency_us_sum: AtomicU64,
+    latency_us_max: AtomicU64,
+    latency_count: AtomicU64,
+}
+
+impl AuthStats {
+    pub fn record(&self, ok: bool, elapsed: Duration) {
+        if ok {
+            self.accepted.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        let us = elapsed.as_micros() as u64;
+        self.latency_us_sum.fetch_add(us, Ordering::Relaxed);
+        self.latency_us_max.fetch_max(us, Ordering::Relaxed);
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Average and max latency in microseconds since the last call
+    pub fn take_latency(&self) -> Option<(f64, u64)> {
+        let count = self.latency_count.swap(0, Ordering::Relaxed);
+        let sum = self.latency_us_sum.swap(0, Ordering::Relaxed);
+        let max = self.latency_us_max.swap(0, Ordering::Relaxed);
+        (count > 0).then(|| (sum as f64 / count as f64, max))
+    }
+}
+
+#[async_trait::async_trait]
+pub trait BusinessMetrics {
+    async fn collect_h2_metrics(&self);
+    async fn collect_auth_metrics(&self);
+}
+
+#[async_trait::async_trait]
+impl<C> BusinessMetrics for Service<C>
+where
+    C: ConnectionBaseOperations + Send + Sync + Clone + 'static,
+{
+    async fn collect_h2_metrics(&self) {
+        let Some(client) = &self.h2_client else {
+            return;
+        };
+
+        let (traffic, online) = match tokio::try_join!(client.traffic(), client.online()) {
+            Ok(res) => res,
+            Err(e) => {
+                tracing::error!("Failed to fetch Hysteria2 stats: {}", e);
+                return;
+            }
+        };
+
+        let node_uuid = self.node.uuid;
+        let base_tags = self.node.get_base_tags();
+
+        let active_conns = {
+            let mem = self.memory.read().await;
+            mem.keys().cloned().collect::<Vec<_>>()
+        };
+
+        // Hysteria2 ids are the conn ids returned by the auth handler
+        for conn_id in active_conns {
+            let id = conn_id.to_string();
+            let Some(stats) = traffic.get(&id) else {
+                continue;
+            };
+
+            let mut metric_tags = base_tags.clone();
+            metric_tags.insert("conn_id".to_string(), id.clone());
+            metric_tags.insert("proto".to_string(), "hysteria2".to_string());
+
+            self.metrics.push(
+                node_uuid,
+                "user.traffic.downlink",
+                stats.tx as f64,
+                metric_tags.clone(),
+            );
+            self.metrics.push(
+                node_uuid,
+                "user.traffic.uplink",
+                stats.rx as f64,
+                metric_tags.clone(),
+            );
+            self.metrics.push(
+                node_uuid,
+                "user.online",
+                online.get(&id).copied().unwrap_or(0) as f64,
+                metric_tags,
+            );
+        }
+    }
+
+    async fn collect_auth_metrics(&self) {
+        let node_uuid = self.node.uuid;
+        let tags = self.node.get_base_tags();
+
+        let indexed = self.memory.read().await.len();
+        let stats = &self.auth_stats;
+
+        let mut values = vec![
+            ("auth.connections", indexed as f64),
+            (
+                "auth.accepted",
+                stats.accepted.load(Ordering::Relaxed) as f64,
+            ),
+            (
+                "auth.rejected",
+                stats.rejected.load(Ordering::Relaxed) as f64,
+            ),
+        ];
+        if let Some((avg, max)) = stats.take_latency() {
+            values.push(("auth.latency_avg_us", avg));
+            values.push(("auth.latency_max_us", max as f64));
+        }
+
+        for (name, value) in values {
+            self.metrics.push(node_uuid, name, value, tags.clone());
+        }
+    }
+}
@@ -10,14 +10,16 @@ use tokio::{
 use warp::Filter;
 
 use fcore::{
-    BaseConnection as Connection, ConnectionBaseOperations, Connections, MetricBuffer, Node,
-    NodeConfig, Publisher, Result, SnapshotManager, Subscriber, Tag, Topic,
+    serve_prometheus, BaseConnection as Connection, ConnectionBaseOperations, Connections,
+    Hysteria2Client, MetricBuffer, Node, NodeConfig, Publisher, Result, SnapshotManager,
+    Subscriber, Tag, Topic,
 };
 
 use super::config::ServiceSettings;
 
 use super::handlers::auth_handler;
 use super::http::ApiRequests;
+use super::metrics::AuthStats;
 use super::request;
 use super::tasks::Tasks;
 
@@ -27,8 +29,10 @@ where
 {
     pub memory: Arc<RwLock<Connections<C>>>,
     pub metrics: Arc<MetricBuffer>,
+    pub auth_stats: Arc<AuthStats>,
     pub node: Node,
     pub subscriber: Subscriber,
+    pub h2_client: Option<Hysteria2Client>,
     pub listen: Ipv4Addr,
     pub port: u16,
     pub origin: String,
@@ -42,6 +46,7 @@ where
         metrics: Arc<MetricBuffer>,
         node: Node,
         subscriber: Subscriber,
+        h2_client: Option<Hysteria2Client>,
         listen: (Ipv4Addr, u16),
         origin: String,
     ) -> Self {
@@ -49,8 +54,10 @@ where
         Self {
             memory,
             metrics,
+            auth_stats: Arc::new(AuthStats::default()),
             node,
             subscriber,
+            h2_client,
             listen: listen.0,
             port: listen.1,
             origin,
@@ -70,11 +77,13 @@ where
         tracing::debug!("CORS: {:?}", cors.clone());
 
         let memory = self.memory.clone();
+        let auth_stats = self.auth_stats.clone();
 
         let auth_route = warp::post()
             .and(warp::path("auth"))
             .and(warp::body::json::<request::Auth>())
             .and(warp::any().map(move || memory.clone()))
+            .and(warp::any().map(move || auth_stats.clone()))
             .and_then(auth_handler);
 
         let routes = health_check.or(auth_route);
@@ -99,21 +108,40 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
         vec![topic_init, Topic::Auth],
     );
 
-    let metrics = MetricBuffer {
-        batch: parking_lot::Mutex::new(Vec::new()),
-        publisher: Publisher::connect(&settings.metrics.publisher).await?,
+    let metrics = MetricBuffer::new(
+        Publisher::connect(&settings.metrics.publisher).await?,
+        &settings.metrics,
+    );
+
+    if let (Some(config), Some(registry)) = (&settings.metrics.prometheus, &metrics.prometheus) {
+        let registry = registry.clone();
+        tasks.push(tokio::spawn(serve_prometheus(config.clone(), move || {
+            registry.render()
+        })));
+    }
+
+    let h2_client = if settings.h2.enabled {
+        tracing::info!("Hysteria2 traffic stats API: {}", settings.h2.endpoint);
+        Some(Hysteria2Client::new(
+            &settings.h2.endpoint,
+            settings.h2.secret.clone(),
+        )?)
+    } else {
+        None
     };
 
     let auth_service = Arc::new(Service::<Connection>::new(
         Arc::new(metrics),
         node,
         subscriber?,
+        h2_client,
         (settings.service.listen, settings.service.port),
         settings.service.origin.clone(),
     ));
 
     let snapshot_manager = SnapshotManager::new(
         settings.clone().service.snapshot_path,
+        auth_service.node.uuid,
         auth_service.memory.clone(),
     );
 
@@ -129,8 +157,8 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
                 Some(timestamp)
             }
             Ok(None) => {
-                tracing::error!("Snapshot file exists but couldn't be loaded");
-                panic!("napshot file exists but couldn't be loaded")
+                tracing::warn!("Snapshot was quarantined, starting fresh");
+                None
             }
             Err(e) => {
                 tracing::error!("Failed to load snapshot: {}", e);
@@ -1,12 +1,12 @@
 use async_trait::async_trait;
 use rkyv::{AlignedVec, Deserialize, Infallible};
-use tokio::time::Duration;
 
 use fcore::{
     Action, BaseConnection as Connection, ConnectionBaseOperations,
-    ConnectionStorageBaseOperations, Error, Message, Metrics, Proto, Result, Topic,
+    ConnectionStorageBaseOperations, Error, Message, Metrics, Proto, Result, Tag, Topic,
 };
 
+use super::metrics::BusinessMetrics;
 use super::service::Service;
 
 #[async_trait]
@@ -63,6 +63,11 @@ where
                     tracing::trace!("SUB: Skipping init for another node: {}", uuid);
                     continue;
                 }
+
+                Topic::Command(_) | Topic::EnvCommand(_) => {
+                    tracing::trace!("SUB: Ignoring command topic: {:?}", topic);
+                    continue;
+                }
                 _ => {
                     tracing::debug!("SUB: Accepted for processing: {:?}", topic);
                 }
@@ -90,12 +95,11 @@ where
                     tracing::error!("SUB: Failed to handle messages: {}", err);
                 }
             }
-
-            tokio::time::sleep(Duration::from_millis(1)).await;
         }
     }
     async fn handle_messages_batch(&self, messages: Vec<Message>) -> Result<()> {
         let mut mem = self.memory.write().await;
+        let mut kicked = vec![];
 
         tracing::debug!("Got {} messages", messages.len());
 
@@ -111,9 +115,9 @@ where
                             msg.expires_at.map(Into::into),
                             msg.subscription_id,
                         );
-                        mem.add(&conn_id, conn.into()).map(|_| ()).map_err(|err| {
-                            Error::Custom(format!("Failed to add conn {}: {}", conn_id, err))
-                        })
+                        // Upsert, so a rotated token replaces the old one in the index
+                        mem.insert(conn_id, conn.into());
+                        Ok(())
                     } else {
                         tracing::debug!("Skipped message {:?}", msg);
                         Ok(())
@@ -122,6 +126,9 @@ where
 
                 Action::Delete => {
                     let _ = mem.remove(&conn_id);
+                    if msg.tag == Tag::Hysteria2 {
+                        kicked.push(conn_id.to_string());
+                    }
                     Ok(())
                 }
 
@@ -130,6 +137,14 @@ where
 
             res?;
         }
+        drop(mem);
+
+        // Removing from memory only stops new sessions, live ones have to be kicked
+        if let Some(client) = &self.h2_client {
+            if let Err(e) = client.kick(&kicked).await {
+                tracing::error!("Failed to kick Hysteria2 users {:?}: {}", kicked, e);
+            }
+        }
 
         Ok(())
     }
@@ -141,5 +156,11 @@ where
         self.loadavg().await;
         self.memory().await;
         self.disk_usage().await;
+        self.bus(&self.subscriber).await;
+        self.collect_h2_metrics().await;
+        self.collect_auth_metrics().await;
+        if let Some(registry) = &self.metrics.prometheus {
+            registry.sweep();
+        }
     }
 }
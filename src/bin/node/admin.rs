@@ -0,0 +1,238 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+#[cfg(feature = "xray")]
+use fcore::{Prefix, Stat, StatKind, StatsOp};
+
+use fcore::{
+    http::{filters::auth, AuthError},
+    Command, CommandKind, ConnectionBaseOperations, Hysteria2Client, Tag, VERSION,
+};
+
+use super::config::AdminConfig;
+use super::node::Node;
+
+#[derive(Serialize)]
+pub struct UpdatesStatus {
+    pub seq: u64,
+    pub last_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SnapshotStatus {
+    pub last_at: Option<i64>,
+    pub age_secs: Option<i64>,
+}
+
+/// None means the backend is not configured on this node
+#[derive(Serialize)]
+pub struct Backends {
+    pub xray: Option<bool>,
+    pub wireguard: Option<bool>,
+    pub hysteria2: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct NodeAdminStatus {
+    pub version: String,
+    pub uuid: uuid::Uuid,
+    pub env: String,
+    pub hostname: String,
+    pub draining: bool,
+    pub uptime_secs: i64,
+    pub inbounds: Vec<Tag>,
+    pub connections: BTreeMap<String, usize>,
+    pub updates: UpdatesStatus,
+    pub snapshot: SnapshotStatus,
+    pub backends: Backends,
+}
+
+fn unix_or_none(ts: i64) -> Option<i64> {
+    (ts > 0).then_some(ts)
+}
+
+#[async_trait::async_trait]
+pub trait Admin {
+    async fn admin_status(&self, h2_client: Option<&Hysteria2Client>) -> NodeAdminStatus;
+    async fn backends(&self, h2_client: Option<&Hysteria2Client>) -> Backends;
+}
+
+#[async_trait::async_trait]
+impl<C> Admin for Node<C>
+where
+    C: ConnectionBaseOperations + Send + Sync + Clone + 'static,
+{
+    async fn admin_status(&self, h2_client: Option<&Hysteria2Client>) -> NodeAdminStatus {
+        let now = chrono::Utc::now().timestamp();
+
+        let mut connections = BTreeMap::new();
+        {
+            let mem = self.memory.read().await;
+            for conn in mem.values() {
+                *connections
+                    .entry(conn.get_proto().proto().to_string())
+                    .or_insert(0) += 1;
+            }
+        }
+
+        let mut inbounds = self.inbound_tags();
+        inbounds.sort_by_key(|tag| tag.to_string());
+
+        let last_snapshot = unix_or_none(self.last_snapshot_at.load(Ordering::Relaxed));
+
+        NodeAdminStatus {
+            version: VERSION.to_string(),
+            uuid: self.node.uuid,
+            env: self.node.env.to_string(),
+            hostname: self.node.hostname.clone(),
+            draining: self.draining.load(Ordering::Relaxed),
+            uptime_secs: now - self.started_at,
+            inbounds,
+            connections,
+            updates: UpdatesStatus {
+                seq: self.updates_seq.load(Ordering::Relaxed),
+                last_at: unix_or_none(self.last_update_at.load(Ordering::Relaxed)),
+            },
+            snapshot: SnapshotStatus {
+                last_at: last_snapshot,
+                age_secs: last_snapshot.map(|ts| now - ts),
+            },
+            backends: self.backends(h2_client).await,
+        }
+    }
+
+    async fn backends(&self, h2_client: Option<&Hysteria2Client>) -> Backends {
+        let tags = self.inbound_tags();
+
+        #[cfg(feature = "xray")]
+        let xray = match tags.iter().find(|tag| tag.is_xray()) {
+            Some(tag) if self.stats_client.is_some() => {
+                let res = self
+                    .stat(
+                        Prefix::InboundPrefix(*tag),
+                        Stat::Inbound(StatKind::Downlink),
+                        false,
+                    )
+                    .await;
+                // A missing counter still means Xray answered
+                Some(match res {
+                    Ok(_) => true,
+                    Err(e) => e.code() == tonic::Code::NotFound,
+                })
+            }
+            _ => None,
+        };
+        #[cfg(not(feature = "xray"))]
+        let xray = None;
+
+        #[cfg(feature = "wireguard")]
+        let wireguard = self.wg_client.as_ref().map(|wg| wg.validate().is_ok());
+        #[cfg(not(feature = "wireguard"))]
+        let wireguard = None;
+
+        let hysteria2 = match h2_client {
+            Some(client) if tags.contains(&Tag::Hysteria2) => Some(client.online().await.is_ok()),
+            _ => None,
+        };
+
+        Backends {
+            xray,
+            wireguard,
+            hysteria2,
+        }
+    }
+}
+
+pub async fn serve<C>(node: Arc<Node<C>>, config: AdminConfig, h2_client: Option<Hysteria2Client>)
+where
+    C: ConnectionBaseOperations + Send + Sync + Clone + 'static,
+{
+    let token = Arc::new(config.token.clone());
+    let with_node = {
+        let node = node.clone();
+        warp::any().map(move || node.clone())
+    };
+
+    let health = warp::get()
+        .and(warp::path("health"))
+        .and(warp::path::end())
+        .map(|| "OK");
+
+    let status = warp::get()
+        .and(warp::path("status"))
+        .and(warp::path::end())
+        .and(auth(token.clone()))
+        .and(with_node.clone())
+        .and(warp::any().map(move || h2_client.clone()))
+        .and_then(status_handler);
+
+    let resync = warp::post()
+        .and(warp::path("resync"))
+        .and(warp::path::end())
+        .and(auth(token.clone()))
+        .and(with_node.clone())
+        .and(warp::any().map(|| CommandKind::Resync))
+        .and_then(command_handler);
+
+    let snapshot = warp::post()
+        .and(warp::path("snapshot"))
+        .and(warp::path::end())
+        .and(auth(token))
+        .and(with_node)
+        .and(warp::any().map(|| CommandKind::Snapshot))
+        .and_then(command_handler);
+
+    let routes = health.or(status).or(resync).or(snapshot).recover(rejection);
+
+    let addr = SocketAddr::new(config.listen.into(), config.port);
+    tracing::info!("Admin API listening on {}", addr);
+    warp::serve(routes).run(addr).await;
+}
+
+async fn status_handler<C>(
+    node: Arc<Node<C>>,
+    h2_client: Option<Hysteria2Client>,
+) -> Result<impl Reply, Rejection>
+where
+    C: ConnectionBaseOperations + Send + Sync + Clone + 'static,
+{
+    let status = node.admin_status(h2_client.as_ref()).await;
+    Ok(warp::reply::json(&status))
+}
+
+/// Triggers go through the same queue as ZMQ commands
+async fn command_handler<C>(node: Arc<Node<C>>, kind: CommandKind) -> Result<impl Reply, Rejection>
+where
+    C: ConnectionBaseOperations + Send + Sync + Clone + 'static,
+{
+    let cmd = Command::new(kind);
+    let id = cmd.id;
+    match node.commands.send(cmd).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "queued": kind, "id": id })),
+            StatusCode::ACCEPTED,
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": e.to_string() })),
+            StatusCode::SERVICE_UNAVAILABLE,
+        )),
+    }
+}
+
+async fn rejection(reject: Rejection) -> Result<impl Reply, Rejection> {
+    if reject.find::<AuthError>().is_some()
+        || reject.find::<warp::reject::MissingHeader>().is_some()
+    {
+        Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": "UNAUTHORIZED" })),
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else {
+        Err(reject)
+    }
+}
@@ -1,11 +1,24 @@
 use serde::Deserialize;
+use std::net::Ipv4Addr;
 
-use fcore::{ApiAccessConfig, MetricsTxConfig, NodeConfigRaw, Result, Settings};
+use fcore::{ApiAccessConfig, Error, MetricsTxConfig, NodeConfigRaw, Result, Settings};
 
 fn default_disabled() -> bool {
     false
 }
 
+fn default_reconcile_interval() -> u64 {
+    300
+}
+
+fn default_admin_listen() -> Ipv4Addr {
+    Ipv4Addr::LOCALHOST
+}
+
+fn default_admin_port() -> u16 {
+    3005
+}
+
 fn default_log_level() -> String {
     "debug".to_string()
 }
@@ -29,10 +42,17 @@ pub struct ServiceSettings {
     pub api: ApiAccessConfig,
     #[serde(default)]
     pub metrics: MetricsTxConfig,
+    #[serde(default)]
+    pub reconcile: ReconcileConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
 }
 
 impl Settings for ServiceSettings {
     fn validate(&self) -> Result<()> {
+        if self.admin.enabled && self.admin.token.trim().is_empty() {
+            return Err(Error::Custom("admin.token is required".into()));
+        }
         Ok(())
     }
 }
@@ -43,6 +63,9 @@ pub struct ServiceConfig {
     pub log_level: String,
     pub snapshot_interval: u64,
     pub snapshot_path: String,
+    /// Journal of updates applied since the last snapshot, disabled when unset
+    #[serde(default)]
+    pub journal_path: Option<String>,
     pub updates_endpoint_zmq: String,
 }
 
@@ -59,6 +82,9 @@ pub struct H2Config {
     #[serde(default = "default_disabled")]
     pub enabled: bool,
     pub path: String,
+    /// Hysteria2 `trafficStats` API, used to check the backend is alive
+    pub stats_endpoint: Option<String>,
+    pub stats_secret: Option<String>,
 }
 
 #[cfg(feature = "wireguard")]
@@ -75,3 +101,48 @@ pub struct MtprotoConfig {
     pub enabled: bool,
     pub path: String,
 }
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReconcileConfig {
+    #[serde(default = "default_disabled")]
+    pub enabled: bool,
+    #[serde(default = "default_reconcile_interval")]
+    pub interval: u64,
+    /// Only report drift, don't touch Xray/WG
+    #[serde(default = "default_disabled")]
+    pub dry_run: bool,
+}
+
+impl Default for ReconcileConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: default_reconcile_interval(),
+            dry_run: false,
+        }
+    }
+}
+
+/// Local HTTP API for status and manual triggers
+#[derive(Clone, Debug, Deserialize)]
+pub struct AdminConfig {
+    #[serde(default = "default_disabled")]
+    pub enabled: bool,
+    #[serde(default = "default_admin_listen")]
+    pub listen: Ipv4Addr,
+    #[serde(default = "default_admin_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub token: String,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: default_admin_listen(),
+            port: default_admin_port(),
+            token: String::new(),
+        }
+    }
+}
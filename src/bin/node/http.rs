@@ -2,14 +2,13 @@ use async_trait::async_trait;
 use reqwest::{Client as HttpClient, StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
 
 use fcore::{
     http::{
         request::ConnType,
         response::{Instance, InstanceWithId, ResponseMessage},
     },
-    ConnectionBaseOperations, Env, Error, Inbound, NodeType, Result, Tag, Topic,
+    ConnectionBaseOperations, Env, Error, Inbound, NodeAddress, NodeType, Result, Tag, Topic,
 };
 
 use crate::node::Node;
@@ -18,7 +17,7 @@ use crate::node::Node;
 pub struct NodeRequest {
     pub env: Env,
     pub hostname: String,
-    pub address: Ipv4Addr,
+    pub address: NodeAddress,
     pub inbounds: HashMap<Tag, Inbound>,
     pub uuid: uuid::Uuid,
     pub label: String,
@@ -133,8 +132,8 @@ where
         let node_request = NodeRequest {
             env: node.env.clone(),
             hostname: node.hostname.clone(),
-            address: node.address,
-            inbounds: node.inbounds.clone(),
+            address: node.address.clone(),
+            inbounds: self.inbounds.read().clone(),
             uuid: node.uuid,
             label: node.label.clone(),
             interface: node.interface.clone(),
@@ -0,0 +1,49 @@
+use fcore::{
+    Action, BaseConnection as Connection, ConnectionBaseOperations,
+    ConnectionStorageBaseOperations, Result,
+};
+
+use super::node::Node;
+
+#[async_trait::async_trait]
+pub trait JournalReplay {
+    async fn replay_journal(&self) -> Result<usize>;
+}
+
+#[async_trait::async_trait]
+impl<C> JournalReplay for Node<C>
+where
+    C: ConnectionBaseOperations + Send + Sync + Clone + 'static + From<Connection>,
+{
+    /// Re-applies journaled messages to memory only, backends are restored from memory afterwards
+    async fn replay_journal(&self) -> Result<usize> {
+        let Some(journal) = &self.journal else {
+            return Ok(0);
+        };
+
+        let messages = journal.replay().await?;
+        let mut mem = self.memory.write().await;
+
+        for msg in &messages {
+            match msg.action {
+                Action::Create | Action::Update => {
+                    if let Some(proto) = msg.proto() {
+                        let conn = Connection::new(
+                            proto,
+                            msg.expires_at.clone().map(Into::into),
+                            msg.subscription_id,
+                        );
+                        // Same as the live handler, an existing entry is kept as is
+                        let _ = mem.add(&msg.conn_id, conn.into());
+                    }
+                }
+                Action::Delete => {
+                    let _ = mem.remove(&msg.conn_id);
+                }
+                Action::ResetStat => {}
+            }
+        }
+
+        Ok(messages.len())
+    }
+}
@@ -1,7 +1,12 @@
+mod admin;
 mod config;
 mod http;
+mod journal;
 mod metrics;
 mod node;
+#[cfg(any(feature = "xray", feature = "wireguard"))]
+mod reconcile;
+mod reload;
 mod snapshot;
 #[cfg(feature = "xray")]
 mod stats;
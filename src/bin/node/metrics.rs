@@ -40,12 +40,12 @@ where
         let node_uuid = self.node.uuid;
         let base_tags = self.node.get_base_tags();
 
-        for tag in self.node.inbounds.keys() {
+        for tag in self.inbound_tags() {
             if matches!(tag, Tag::Hysteria2 | Tag::Mtproto) {
                 continue;
             }
 
-            let prefix = Prefix::InboundPrefix(*tag);
+            let prefix = Prefix::InboundPrefix(tag);
 
             if let Ok(stats) = self.inbound(prefix).await {
                 let mut metric_tags = base_tags.clone();
@@ -132,22 +132,52 @@ where
                 .collect::<Vec<_>>()
         };
 
-        for (conn_id, pubkey) in wg_conns {
-            if let Ok(pubkey) = pubkey {
-                if let Ok((uplink, downlink)) = wg_client.peer_stats(&pubkey) {
-                    let mut metric_tags = base_tags.clone();
-                    metric_tags.insert("user_id".to_string(), conn_id.to_string());
-                    metric_tags.insert("proto".to_string(), "wireguard".to_string());
+        let peers = match wg_client.peers_info() {
+            Ok(peers) => peers,
+            Err(e) => {
+                tracing::error!("Failed to read WireGuard peers: {}", e);
+                return;
+            }
+        };
+        let now = std::time::SystemTime::now();
 
-                    self.metrics.push(
-                        node_uuid,
-                        "user.traffic.downlink",
-                        downlink as f64,
-                        metric_tags.clone(),
-                    );
-                    self.metrics
-                        .push(node_uuid, "user.traffic.uplink", uplink as f64, metric_tags);
-                }
+        for (conn_id, pubkey) in wg_conns {
+            let Some(peer) = pubkey.ok().and_then(|key| peers.get(&key)) else {
+                continue;
+            };
+
+            let mut metric_tags = base_tags.clone();
+            metric_tags.insert("user_id".to_string(), conn_id.to_string());
+            metric_tags.insert("proto".to_string(), "wireguard".to_string());
+
+            self.metrics.push(
+                node_uuid,
+                "user.traffic.downlink",
+                peer.tx_bytes as f64,
+                metric_tags.clone(),
+            );
+            self.metrics.push(
+                node_uuid,
+                "user.traffic.uplink",
+                peer.rx_bytes as f64,
+                metric_tags.clone(),
+            );
+            self.metrics.push(
+                node_uuid,
+                "user.online",
+                if peer.is_online(now) { 1.0 } else { 0.0 },
+                metric_tags.clone(),
+            );
+
+            if let Some(ts) = peer.last_handshake_secs() {
+                self.metrics
+                    .push(node_uuid, "user.last_handshake", ts as f64, metric_tags);
+                tracing::debug!(
+                    "WG peer {} last handshake {} from {:?}",
+                    conn_id,
+                    ts,
+                    peer.endpoint
+                );
             }
         }
     }
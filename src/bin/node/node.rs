@@ -1,9 +1,13 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::signal;
 use tokio::sync::broadcast;
+use tokio::sync::mpsc;
 #[cfg(feature = "xray")]
 use tokio::sync::Mutex;
+use tokio::sync::Notify;
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
@@ -12,20 +16,22 @@ use tokio::time::Duration;
 use tracing::{debug, error, info, warn};
 
 #[cfg(feature = "xray")]
-use fcore::{XrayClient, XrayHandlerClient, XraySettings, XrayStatsClient};
+use fcore::{XrayClient, XrayHandlerClient, XrayStatsClient};
 
 #[cfg(feature = "wireguard")]
-use fcore::{WgApi, WireguardServerConfig, WireguardSettings};
+use fcore::WgApi;
 
 use fcore::{
-    utils::measure_time, BaseConnection as Connection, ConnectionBaseOperations, Connections,
-    MetricBuffer, Node as MemNode, Publisher, Result, SnapshotManager, Subscriber, Tag, Topic,
+    serve_prometheus, utils::measure_time, BaseConnection as Connection, Command,
+    ConnectionBaseOperations, Connections, Hysteria2Client, Inbound, Journal, MetricBuffer,
+    Node as MemNode, Publisher, Result, SnapshotManager, Subscriber, Tag, Topic,
 };
 
-use fcore::{H2Settings, Hysteria2Settings, MtprotoSettings, NodeConfig, Settings};
-
+use super::admin;
 use super::config::ServiceSettings;
 use super::http::ApiRequests;
+use super::journal::JournalReplay;
+use super::reload::{ClientSettings, NodeConfigs, Reload};
 #[cfg(any(feature = "xray", feature = "wireguard"))]
 use super::snapshot::SnapshotRestore;
 use super::tasks::Tasks;
@@ -36,67 +42,99 @@ where
 {
     pub memory: Arc<RwLock<Connections<C>>>,
     pub node: MemNode,
+    /// Current inbounds, replaced on reload; `node.inbounds` holds the startup set
+    pub inbounds: parking_lot::RwLock<HashMap<Tag, Inbound>>,
     pub metrics: Arc<MetricBuffer>,
     pub subscriber: Subscriber,
+    pub commands: mpsc::Sender<Command>,
+    pub snapshot_now: Notify,
+    /// New connections go to memory only until undrain
+    pub draining: AtomicBool,
+    pub journal: Option<Journal>,
+    pub started_at: i64,
+    /// Update batches received over ZMQ since start
+    pub updates_seq: AtomicU64,
+    /// Unix seconds, 0 until the first update/snapshot
+    pub last_update_at: AtomicI64,
+    pub last_snapshot_at: AtomicI64,
     #[cfg(feature = "xray")]
     pub stats_client: Option<Arc<Mutex<XrayStatsClient>>>,
     #[cfg(feature = "xray")]
     pub handler_client: Option<Arc<Mutex<XrayHandlerClient>>>,
     #[cfg(feature = "wireguard")]
     pub wg_client: Option<WgApi>,
+    pub clients: ClientSettings,
 }
 
 impl<C> Node<C>
 where
     C: ConnectionBaseOperations + Send + Sync + Clone + 'static,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         node: MemNode,
         subscriber: Subscriber,
         metrics: Arc<MetricBuffer>,
+        commands: mpsc::Sender<Command>,
+        journal: Option<Journal>,
         #[cfg(feature = "xray")] stats_client: Option<Arc<Mutex<XrayStatsClient>>>,
         #[cfg(feature = "xray")] handler_client: Option<Arc<Mutex<XrayHandlerClient>>>,
         #[cfg(feature = "wireguard")] wg_client: Option<WgApi>,
+        clients: ClientSettings,
     ) -> Self {
         let memory = Arc::new(RwLock::new(Connections::default()));
+        let inbounds = parking_lot::RwLock::new(node.inbounds.clone());
         Self {
             memory,
             node,
+            inbounds,
             metrics,
             subscriber,
+            commands,
+            snapshot_now: Notify::new(),
+            draining: AtomicBool::new(false),
+            journal,
+            started_at: chrono::Utc::now().timestamp(),
+            updates_seq: AtomicU64::new(0),
+            last_update_at: AtomicI64::new(0),
+            last_snapshot_at: AtomicI64::new(0),
             #[cfg(feature = "xray")]
             stats_client,
             #[cfg(feature = "xray")]
             handler_client,
             #[cfg(feature = "wireguard")]
             wg_client,
+            clients,
         }
     }
+
+    pub fn inbound_tags(&self) -> Vec<Tag> {
+        self.inbounds.read().keys().copied().collect()
+    }
+
+    pub fn sync_tags(&self) -> Vec<Tag> {
+        self.inbound_tags()
+            .into_iter()
+            .filter(|k| !matches!(k, Tag::Hysteria2)) // Hysteria2 uses external auth provider
+            .filter(|k| !matches!(k, Tag::Mtproto)) // Mtproto doesn't support auth provider
+            .collect()
+    }
 }
 
 pub async fn run(settings: ServiceSettings) -> Result<()> {
     let mut tasks: Vec<JoinHandle<()>> = vec![];
     let (shutdown_tx, _) = broadcast::channel::<()>(1);
 
+    // Proto configs are re-read on reload, see `Reload`
+    let configs = match NodeConfigs::load(&settings) {
+        Ok(configs) => configs,
+        Err(e) => panic!("Node configs: {}", e),
+    };
+
     // Init Xray
     #[cfg(feature = "xray")]
-    let (xray_config, stats_client, handler_client) = if settings.xray.enabled {
-        let config = match XraySettings::from_file(&settings.xray.path) {
-            Ok(config) => {
-                info!(
-                    "Xray Config: Successfully read Xray config file: {:?}",
-                    config
-                );
-
-                if let Err(e) = config.validate() {
-                    panic!("Xray Config:: Error reading JSON file: {}", e);
-                }
-                config
-            }
-            Err(e) => {
-                panic!("Xray Config:: Error reading JSON file: {}", e);
-            }
-        };
+    let (stats_client, handler_client) = if let Some(ref config) = configs.xray {
+        info!("Xray Config: Successfully read Xray config file");
 
         let xray_api_endpoint = format!("http://{}", config.api.listen.clone());
 
@@ -105,17 +143,14 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
             XrayHandlerClient::new(&xray_api_endpoint).await?,
         ));
 
-        (Some(config), Some(stats_client), Some(handler_client))
+        (Some(stats_client), Some(handler_client))
     } else {
-        (None, None, None)
+        (None, None)
     };
 
     // Init Wireguard
     #[cfg(feature = "wireguard")]
-    let (wg_client, wg_config) = if settings.wg.enabled {
-        let row_config = WireguardServerConfig::from_file(&settings.wg.path)?;
-        let wg: WireguardSettings = row_config.try_into()?;
-        debug!("{:?}", wg);
+    let wg_client = if let Some(ref wg) = configs.wg {
         let client = match WgApi::new(&wg.interface) {
             Ok(c) => c,
             Err(e) => panic!("Cannot create WG client: {}", e),
@@ -123,88 +158,66 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
         if let Err(e) = client.validate() {
             panic!("Cannot validate WG client: {}", e);
         }
-        (Some(client), Some(wg))
-    } else {
-        (None, None)
-    };
-
-    // Init Hysteria2
-    let h2_config = if settings.h2.enabled {
-        match Hysteria2Settings::from_file(&settings.h2.path) {
-            Ok(cfg) => {
-                if let Err(e) = cfg.validate() {
-                    error!("Hysteria2 config validation failed: {}", e);
-                    panic!("Hysteria2 config: {}", e);
-                } else {
-                    match H2Settings::try_from(cfg) {
-                        Ok(settings) => Some(settings),
-                        Err(e) => {
-                            error!("Hysteria2 validation error: {}", e);
-                            panic!("Hysteria2 config: {}", e);
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                error!("Failed to load Hysteria2 config: {}", e);
-                panic!("Hysteria2 config: {}", e);
-            }
-        }
+        Some(client)
     } else {
         None
     };
 
-    // Init Mtproto
-    let mtproto_config = if settings.mtproto.enabled {
-        Some(MtprotoSettings::from_file(&settings.mtproto.path))
-    } else {
-        None
-    };
-
-    let node_config = NodeConfig::from_raw(settings.node.clone());
-
-    let node = MemNode::new(
-        node_config?,
-        #[cfg(feature = "xray")]
-        xray_config,
-        #[cfg(feature = "wireguard")]
-        wg_config,
-        h2_config,
-        mtproto_config,
-    );
+    let clients = configs.client_settings();
+    let node = configs.into_node(&settings)?;
 
     let topic_init: Topic = settings.node.env.clone().into();
     let topic_updates: Topic = settings.node.uuid.into();
 
-    let topics = vec![topic_updates, topic_init];
+    let topics = vec![
+        topic_updates,
+        topic_init,
+        Topic::Command(settings.node.uuid),
+        Topic::EnvCommand(settings.node.env.clone()),
+    ];
 
     tracing::debug!("Topics to connect {:?}", topics);
     let subscriber = Subscriber::new(&settings.service.updates_endpoint_zmq, topics)?;
     let metric_publisher = Publisher::connect(&settings.metrics.publisher).await?;
 
-    let metrics = MetricBuffer {
-        batch: parking_lot::Mutex::new(Vec::new()),
-        publisher: metric_publisher,
-    };
+    let metrics = MetricBuffer::new(metric_publisher, &settings.metrics);
+
+    if let (Some(config), Some(registry)) = (&settings.metrics.prometheus, &metrics.prometheus) {
+        let registry = registry.clone();
+        tasks.push(tokio::spawn(serve_prometheus(config.clone(), move || {
+            registry.render()
+        })));
+    }
+
+    let (command_tx, mut command_rx) = mpsc::channel::<Command>(16);
 
     let node = Arc::new(Node::<Connection>::new(
         node.clone(),
         subscriber,
         Arc::new(metrics),
+        command_tx,
+        settings.service.journal_path.as_deref().map(Journal::new),
         #[cfg(feature = "xray")]
         stats_client.clone(),
         #[cfg(feature = "xray")]
         handler_client.clone(),
         #[cfg(feature = "wireguard")]
         wg_client.clone(),
+        clients,
     ));
 
     let snapshot_path = settings.service.snapshot_path.clone();
-    let snapshot_manager = SnapshotManager::new(snapshot_path, node.memory.clone());
+    let snapshot_manager = SnapshotManager::new(snapshot_path, node.node.uuid, node.memory.clone());
 
     let snapshot_timestamp = if Path::new(&snapshot_manager.snapshot_path).exists() {
         match snapshot_manager.load_snapshot().await {
             Ok(Some(timestamp)) => {
+                match node.replay_journal().await {
+                    Ok(0) => {}
+                    Ok(count) => info!("Replayed {} journaled updates", count),
+                    Err(e) => error!("Journal replay failed: {}", e),
+                }
+
                 #[cfg(feature = "wireguard")]
                 if let Err(e) = snapshot_manager.restore_wg_connections(wg_client).await {
                     error!("Couldn't restore connections from memory, {}", e);
@@ -223,10 +236,12 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
                     "Loaded {} connections from snapshot with ts  {}",
                     count, timestamp,
                 );
+                node.last_snapshot_at
+                    .store(timestamp as i64, Ordering::Relaxed);
                 Some(timestamp)
             }
             Ok(None) => {
-                warn!("Snapshot file exists but couldn't be loaded");
+                warn!("Snapshot was quarantined, starting fresh");
                 None
             }
             Err(e) => {
@@ -239,27 +254,90 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
         warn!("No snapshot found, starting fresh");
         None
     };
+
+    // The journal is only meaningful on top of the snapshot it follows
+    if snapshot_timestamp.is_none() {
+        if let Some(journal) = &node.journal {
+            if let Err(e) = journal.clear().await {
+                error!("Couldn't clear journal: {}", e);
+            }
+        }
+    }
+
+    // Up before registration so a node can be inspected while the API is down
+    if settings.admin.enabled {
+        let h2_client = match &settings.h2.stats_endpoint {
+            Some(endpoint) if settings.h2.enabled => {
+                match Hysteria2Client::new(endpoint, settings.h2.stats_secret.clone()) {
+                    Ok(client) => Some(client),
+                    Err(e) => {
+                        warn!("Hysteria2 stats client: {}", e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let admin_handle: JoinHandle<()> = tokio::spawn({
+            let node = node.clone();
+            let admin = settings.admin.clone();
+            let mut shutdown = shutdown_tx.subscribe();
+            async move {
+                tokio::select! {
+                    _ = admin::serve(node, admin, h2_client) => {},
+                    _ = shutdown.recv() => {
+                        info!("🛑 Admin API received shutdown");
+                    },
+                }
+            }
+        });
+        tasks.push(admin_handle);
+    }
+
     {
-        tokio::spawn(async move {
-            info!(
-                "Running snapshot task, interval {}",
-                settings.service.snapshot_interval
-            );
+        tokio::spawn({
+            let node = node.clone();
+            async move {
+                info!(
+                    "Running snapshot task, interval {}",
+                    settings.service.snapshot_interval
+                );
 
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs(
-                settings.service.snapshot_interval,
-            ));
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+                    settings.service.snapshot_interval,
+                ));
 
-            loop {
-                interval.tick().await;
-                if let Err(e) = measure_time(snapshot_manager.create_snapshot(), "Snapshot").await {
-                    error!("Failed to create snapshot: {}", e);
-                } else {
-                    let count = snapshot_manager.len().await;
-                    debug!(
-                        "Connections snapshot saved successfully; {} Connections",
-                        count
-                    );
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {},
+                        _ = node.snapshot_now.notified() => {
+                            info!("Snapshot requested by command");
+                        },
+                    }
+                    if let Some(journal) = &node.journal {
+                        if let Err(e) = journal.rotate().await {
+                            error!("Failed to rotate journal: {}", e);
+                        }
+                    }
+                    if let Err(e) =
+                        measure_time(snapshot_manager.create_snapshot(), "Snapshot").await
+                    {
+                        error!("Failed to create snapshot: {}", e);
+                    } else {
+                        if let Some(journal) = &node.journal {
+                            if let Err(e) = journal.compact().await {
+                                error!("Failed to compact journal: {}", e);
+                            }
+                        }
+                        node.last_snapshot_at
+                            .store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+                        let count = snapshot_manager.len().await;
+                        debug!(
+                            "Connections snapshot saved successfully; {} Connections",
+                            count
+                        );
+                    }
                 }
             }
         });
@@ -278,6 +356,62 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
             });
             tasks.push(zmq_task);
 
+            info!("Command handler starting...");
+            let command_task = tokio::spawn({
+                let node = node.clone();
+                let settings = settings.clone();
+                let mut shutdown = shutdown_tx.subscribe();
+                async move {
+                    loop {
+                        tokio::select! {
+                            Some(cmd) = command_rx.recv() => {
+                                info!("Command received: {}", cmd);
+                                if let Err(e) = node.handle_command(cmd.clone(), &settings).await {
+                                    error!("Command {} failed: {}", cmd.kind, e);
+                                }
+                            },
+                            _ = shutdown.recv() => {
+                                info!("🛑 Command task received shutdown");
+                                break;
+                            },
+                        }
+                    }
+                }
+            });
+            tasks.push(command_task);
+
+            info!("SIGHUP reload handler starting...");
+            let reload_task = tokio::spawn({
+                let node = node.clone();
+                let settings = settings.clone();
+                let mut shutdown = shutdown_tx.subscribe();
+                async move {
+                    let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup())
+                    {
+                        Ok(s) => s,
+                        Err(e) => {
+                            error!("Couldn't install SIGHUP handler: {}", e);
+                            return;
+                        }
+                    };
+                    loop {
+                        tokio::select! {
+                            Some(()) = hangup.recv() => {
+                                info!("SIGHUP received, reloading configs");
+                                if let Err(e) = node.reload(&settings).await {
+                                    error!("Reload failed: {}", e);
+                                }
+                            },
+                            _ = shutdown.recv() => {
+                                info!("🛑 Reload task received shutdown");
+                                break;
+                            },
+                        }
+                    }
+                }
+            });
+            tasks.push(reload_task);
+
             tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 
             {
@@ -290,22 +424,19 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
                         .await
                     {
                         Ok(_) => {
-                            let tags: Vec<_> = node
-                                .node
-                                .inbounds
-                                .keys()
-                                .filter(|k| !matches!(k, Tag::Hysteria2)) // Hysteria2 uses external auth provider
-                                .filter(|k| !matches!(k, Tag::Mtproto)) // Mtproto doesn't support auth provider
-                                .collect();
-
-                            for tag in tags {
-                                node.sync_connections(
-                                    settings.api.endpoint.clone(),
-                                    settings.api.token.clone(),
-                                    *tag,
-                                    snapshot_timestamp,
-                                )
-                                .await?
+                            for tag in node.sync_tags() {
+                                while let Err(e) = node
+                                    .sync_connections(
+                                        settings.api.endpoint.clone(),
+                                        settings.api.token.clone(),
+                                        tag,
+                                        snapshot_timestamp,
+                                    )
+                                    .await
+                                {
+                                    warn!("Sync of {} failed, {} retrying...", tag, e);
+                                    sleep(Duration::from_secs(10)).await;
+                                }
                             }
                             break;
                         }
@@ -362,6 +493,32 @@ pub async fn run(settings: ServiceSettings) -> Result<()> {
     tasks.push(metrics_handle);
     tasks.push(metrics_flush_handle);
 
+    if settings.reconcile.enabled {
+        info!(
+            "Running reconcile task, interval {}, dry_run {}",
+            settings.reconcile.interval, settings.reconcile.dry_run
+        );
+        let reconcile_handle: JoinHandle<()> = tokio::spawn({
+            let node = node.clone();
+            let reconcile = settings.reconcile.clone();
+            let mut shutdown = shutdown_tx.subscribe();
+            async move {
+                loop {
+                    tokio::select! {
+                        _ = sleep(Duration::from_secs(reconcile.interval)) => {
+                            node.reconcile(reconcile.dry_run).await;
+                        },
+                        _ = shutdown.recv() => {
+                            info!("🛑 Reconcile task received shutdown");
+                            break;
+                        },
+                    }
+                }
+            }
+        });
+        tasks.push(reconcile_handle);
+    }
+
     wait_all_tasks_or_ctrlc(tasks, shutdown_tx).await;
     Ok(())
 }
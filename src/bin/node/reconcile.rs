@@ -0,0 +1,195 @@
+#[cfg(feature = "wireguard")]
+use std::collections::HashMap;
+#[cfg(any(feature = "xray", feature = "wireguard"))]
+use std::collections::HashSet;
+
+#[cfg(feature = "wireguard")]
+use fcore::IpAddrMask;
+#[cfg(feature = "xray")]
+use fcore::{XrayConnOperation, XrayHandlerActions};
+
+use fcore::{ConnectionBaseOperations, Result};
+
+use super::node::Node;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Drift {
+    /// Present in memory, absent in the backend
+    pub missing: usize,
+    /// Present in the backend, absent in memory
+    pub orphaned: usize,
+    pub fixed: usize,
+    pub failed: usize,
+}
+
+impl Drift {
+    pub fn is_clean(&self) -> bool {
+        self.missing == 0 && self.orphaned == 0
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Reconcile {
+    #[cfg(feature = "xray")]
+    async fn reconcile_xray(&self, dry_run: bool) -> Result<Drift>;
+    #[cfg(feature = "wireguard")]
+    async fn reconcile_wg(&self, dry_run: bool) -> Result<Drift>;
+    fn push_drift(&self, backend: &str, drift: &Drift);
+}
+
+#[async_trait::async_trait]
+impl<C> Reconcile for Node<C>
+where
+    C: ConnectionBaseOperations + Send + Sync + Clone + 'static,
+{
+    #[cfg(feature = "xray")]
+    async fn reconcile_xray(&self, dry_run: bool) -> Result<Drift> {
+        let client = match &self.handler_client {
+            Some(c) => c.clone(),
+            None => return Ok(Drift::default()),
+        };
+
+        let mut drift = Drift::default();
+
+        for tag in self.inbound_tags() {
+            if !tag.is_xray() {
+                continue;
+            }
+
+            let live: HashSet<uuid::Uuid> = {
+                let mut locked = client.lock().await;
+                locked
+                    .conn_ids_op(tag)
+                    .await
+                    .map_err(|e| {
+                        fcore::Error::Custom(format!("Couldn't list Xray users of {}: {}", tag, e))
+                    })?
+                    .into_iter()
+                    .collect()
+            };
+
+            let expected: Vec<(uuid::Uuid, Option<String>)> = {
+                let mem = self.memory.read().await;
+                mem.iter()
+                    .filter(|(_, conn)| !conn.get_deleted() && conn.get_proto().proto() == tag)
+                    .map(|(id, conn)| (*id, conn.get_password()))
+                    .collect()
+            };
+            let expected_ids: HashSet<uuid::Uuid> = expected.iter().map(|(id, _)| *id).collect();
+
+            for (conn_id, password) in expected {
+                if live.contains(&conn_id) {
+                    continue;
+                }
+                drift.missing += 1;
+                tracing::warn!("Reconcile: {} {} is missing in Xray", tag, conn_id);
+                if dry_run {
+                    continue;
+                }
+                match client.create(&conn_id, tag, password).await {
+                    Ok(()) => drift.fixed += 1,
+                    Err(e) => {
+                        drift.failed += 1;
+                        tracing::error!("Reconcile: couldn't add {} to Xray: {}", conn_id, e);
+                    }
+                }
+            }
+
+            for conn_id in live.difference(&expected_ids) {
+                drift.orphaned += 1;
+                tracing::warn!("Reconcile: {} {} is orphaned in Xray", tag, conn_id);
+                if dry_run {
+                    continue;
+                }
+                let res = client.lock().await.remove_conn_op(tag, conn_id).await;
+                match res {
+                    Ok(()) => drift.fixed += 1,
+                    Err(e) => {
+                        drift.failed += 1;
+                        tracing::error!("Reconcile: couldn't remove {} from Xray: {}", conn_id, e);
+                    }
+                }
+            }
+        }
+
+        Ok(drift)
+    }
+
+    #[cfg(feature = "wireguard")]
+    async fn reconcile_wg(&self, dry_run: bool) -> Result<Drift> {
+        let wg_api = match &self.wg_client {
+            Some(c) => c,
+            None => return Ok(Drift::default()),
+        };
+
+        let mut drift = Drift::default();
+
+        let live: HashSet<String> = wg_api.peers()?.into_iter().collect();
+
+        let expected: HashMap<String, (uuid::Uuid, Vec<IpAddrMask>)> = {
+            let mem = self.memory.read().await;
+            mem.iter()
+                .filter(|(_, conn)| !conn.get_deleted())
+                .filter_map(|(id, conn)| {
+                    let wg = conn.get_wireguard()?;
+                    let pubkey = wg.keys.pubkey().ok()?;
+                    Some((pubkey, (*id, wg.addresses())))
+                })
+                .collect()
+        };
+
+        for (pubkey, (conn_id, addresses)) in &expected {
+            if live.contains(pubkey) {
+                continue;
+            }
+            drift.missing += 1;
+            tracing::warn!("Reconcile: WG peer of {} is missing", conn_id);
+            if dry_run {
+                continue;
+            }
+            match wg_api.create(pubkey, addresses) {
+                Ok(()) => drift.fixed += 1,
+                Err(e) => {
+                    drift.failed += 1;
+                    tracing::error!("Reconcile: couldn't add WG peer {}: {}", conn_id, e);
+                }
+            }
+        }
+
+        for pubkey in live.iter().filter(|k| !expected.contains_key(*k)) {
+            drift.orphaned += 1;
+            tracing::warn!("Reconcile: WG peer {} is orphaned", pubkey);
+            if dry_run {
+                continue;
+            }
+            match wg_api.delete(pubkey) {
+                Ok(()) => drift.fixed += 1,
+                Err(e) => {
+                    drift.failed += 1;
+                    tracing::error!("Reconcile: couldn't remove WG peer {}: {}", pubkey, e);
+                }
+            }
+        }
+
+        Ok(drift)
+    }
+
+    fn push_drift(&self, backend: &str, drift: &Drift) {
+        let node_uuid = self.node.uuid;
+        let tags = self.node.get_base_tags();
+
+        for (name, value) in [
+            ("missing", drift.missing),
+            ("orphaned", drift.orphaned),
+            ("fixed", drift.fixed),
+            ("failed", drift.failed),
+        ] {
+            self.metrics.push(
+                node_uuid,
+                &format!("reconcile.{}.{}", backend, name),
+                value as f64,
+                tags.clone(),
+            );
+        }
+    }
+}
@@ -0,0 +1,254 @@
+#[cfg(feature = "xray")]
+use std::collections::HashSet;
+
+use tracing::{error, info};
+
+#[cfg(feature = "xray")]
+use fcore::{XrayHandlerActions, XraySettings};
+
+#[cfg(feature = "wireguard")]
+use fcore::{WireguardServerConfig, WireguardSettings};
+
+use fcore::{
+    ConnectionBaseOperations, Error, H2Settings, Hysteria2Settings, Inbound, MtprotoSettings,
+    Node as MemNode, NodeConfig, Result, Settings, Tag,
+};
+
+use super::config::ServiceSettings;
+use super::http::ApiRequests;
+use super::node::Node;
+
+/// What the Xray and WireGuard clients were built from, a reload can't change it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientSettings {
+    #[cfg(feature = "xray")]
+    pub xray_api: Option<String>,
+    #[cfg(feature = "wireguard")]
+    pub wg_interface: Option<String>,
+}
+
+/// Parsed and validated proto configs of the node
+pub struct NodeConfigs {
+    #[cfg(feature = "xray")]
+    pub xray: Option<XraySettings>,
+    #[cfg(feature = "wireguard")]
+    pub wg: Option<WireguardSettings>,
+    pub h2: Option<H2Settings>,
+    pub mtproto: Option<MtprotoSettings>,
+}
+
+impl NodeConfigs {
+    pub fn load(settings: &ServiceSettings) -> Result<Self> {
+        #[cfg(feature = "xray")]
+        let xray = if settings.xray.enabled {
+            let config = XraySettings::from_file(&settings.xray.path)
+                .map_err(|e| Error::Custom(format!("Xray config: {}", e)))?;
+            config
+                .validate()
+                .map_err(|e| Error::Custom(format!("Xray config: {}", e)))?;
+            tracing::debug!("Xray Config: {:?}", config);
+            Some(config)
+        } else {
+            None
+        };
+
+        #[cfg(feature = "wireguard")]
+        let wg = if settings.wg.enabled {
+            let raw = WireguardServerConfig::from_file(&settings.wg.path)
+                .map_err(|e| Error::Custom(format!("WireGuard config: {}", e)))?;
+            let config: WireguardSettings = raw
+                .try_into()
+                .map_err(|e| Error::Custom(format!("WireGuard config: {}", e)))?;
+            tracing::debug!("WireGuard Config: {:?}", config);
+            Some(config)
+        } else {
+            None
+        };
+
+        let h2 = if settings.h2.enabled {
+            let config = Hysteria2Settings::from_file(&settings.h2.path)
+                .map_err(|e| Error::Custom(format!("Hysteria2 config: {}", e)))?;
+            config
+                .validate()
+                .map_err(|e| Error::Custom(format!("Hysteria2 config: {}", e)))?;
+            let config = H2Settings::try_from(config)
+                .map_err(|e| Error::Custom(format!("Hysteria2 config: {}", e)))?;
+            Some(config)
+        } else {
+            None
+        };
+
+        let mtproto = if settings.mtproto.enabled {
+            let config: MtprotoSettings = MtprotoSettings::read_config(&settings.mtproto.path)
+                .map_err(|e| Error::Custom(format!("Mtproto config: {}", e)))?;
+            config
+                .validate()
+                .map_err(|e| Error::Custom(format!("Mtproto config: {}", e)))?;
+            Some(config)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            #[cfg(feature = "xray")]
+            xray,
+            #[cfg(feature = "wireguard")]
+            wg,
+            h2,
+            mtproto,
+        })
+    }
+
+    pub fn client_settings(&self) -> ClientSettings {
+        ClientSettings {
+            #[cfg(feature = "xray")]
+            xray_api: self.xray.as_ref().map(|c| c.api.listen.clone()),
+            #[cfg(feature = "wireguard")]
+            wg_interface: self.wg.as_ref().map(|c| c.interface.clone()),
+        }
+    }
+
+    pub fn into_node(self, settings: &ServiceSettings) -> Result<MemNode> {
+        let node_config = NodeConfig::from_raw(settings.node.clone())?;
+
+        Ok(MemNode::new(
+            node_config,
+            #[cfg(feature = "xray")]
+            self.xray,
+            #[cfg(feature = "wireguard")]
+            self.wg,
+            self.h2,
+            self.mtproto,
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Reload {
+    async fn reload(&self, settings: &ServiceSettings) -> Result<()>;
+    #[cfg(feature = "xray")]
+    async fn readd_xray_users(&self, tags: &HashSet<Tag>);
+}
+
+/// Inbound config without the traffic counters
+fn same_inbound(a: &Inbound, b: &Inbound) -> bool {
+    a.port == b.port
+        && a.stream_settings == b.stream_settings
+        && a.wg == b.wg
+        && a.h2 == b.h2
+        && a.mtproto_secret == b.mtproto_secret
+}
+
+#[async_trait::async_trait]
+impl<C> Reload for Node<C>
+where
+    C: ConnectionBaseOperations + Send + Sync + Clone + 'static,
+{
+    async fn reload(&self, settings: &ServiceSettings) -> Result<()> {
+        // Nothing is applied until every config parses and validates
+        let configs = NodeConfigs::load(settings)
+            .map_err(|e| Error::Custom(format!("Reload aborted, keeping current config: {}", e)))?;
+
+        let clients = configs.client_settings();
+        if clients != self.clients {
+            return Err(Error::Custom(format!(
+                "Reload aborted, Xray API or WireGuard interface changed ({:?} -> {:?}), restart the node to apply",
+                self.clients, clients
+            )));
+        }
+
+        let new_node = configs.into_node(settings)?;
+
+        let (added, removed, changed) = {
+            let old = self.inbounds.read();
+            let new = &new_node.inbounds;
+            let added: Vec<Tag> = new
+                .keys()
+                .filter(|t| !old.contains_key(t))
+                .copied()
+                .collect();
+            let removed: Vec<Tag> = old
+                .keys()
+                .filter(|t| !new.contains_key(t))
+                .copied()
+                .collect();
+            let changed: Vec<Tag> = new
+                .iter()
+                .filter(|(t, inbound)| old.get(t).is_some_and(|o| !same_inbound(o, inbound)))
+                .map(|(t, _)| *t)
+                .collect();
+            (added, removed, changed)
+        };
+
+        let total = new_node.inbounds.len();
+        *self.inbounds.write() = new_node.inbounds;
+        info!(
+            "Config reloaded: {} inbounds, added {:?}, removed {:?}, changed {:?}",
+            total, added, removed, changed
+        );
+
+        // Xray drops users of the inbounds it re-creates from a new config
+        #[cfg(feature = "xray")]
+        {
+            let touched: HashSet<Tag> = added
+                .iter()
+                .chain(changed.iter())
+                .filter(|t| t.is_xray())
+                .copied()
+                .collect();
+            if !touched.is_empty() {
+                self.readd_xray_users(&touched).await;
+            }
+        }
+
+        self.register_node(settings.api.endpoint.clone(), settings.api.token.clone())
+            .await?;
+
+        let sync_tags = self.sync_tags();
+        for tag in added.into_iter().filter(|t| sync_tags.contains(t)) {
+            if let Err(e) = self
+                .sync_connections(
+                    settings.api.endpoint.clone(),
+                    settings.api.token.clone(),
+                    tag,
+                    None,
+                )
+                .await
+            {
+                error!("Couldn't sync connections for new inbound {}: {}", tag, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "xray")]
+    async fn readd_xray_users(&self, tags: &HashSet<Tag>) {
+        let client = match &self.handler_client {
+            Some(c) => c.clone(),
+            None => return,
+        };
+
+        let conns: Vec<(uuid::Uuid, Tag, Option<String>)> = {
+            let mem = self.memory.read().await;
+            mem.iter()
+                .filter(|(_, conn)| !conn.get_deleted())
+                .map(|(id, conn)| (*id, conn.get_proto().proto(), conn.get_password()))
+                .filter(|(_, tag, _)| tags.contains(tag))
+                .collect()
+        };
+
+        let mut added = 0;
+        for (conn_id, tag, password) in conns {
+            match client.create(&conn_id, tag, password).await {
+                Ok(()) => added += 1,
+                // Already present if Xray wasn't restarted
+                Err(e) => tracing::debug!("Couldn't re-add {} to {}: {}", conn_id, tag, e),
+            }
+        }
+
+        if added > 0 {
+            tracing::warn!("Re-added {} users to Xray after reload", added);
+        }
+    }
+}
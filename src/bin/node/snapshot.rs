@@ -58,7 +58,7 @@ where
                 if let Some(wg) = conn.get_wireguard() {
                     if let Some(api) = wg_client.as_ref() {
                         if let Ok(pubkey) = &wg.keys.pubkey() {
-                            if let Err(e) = api.create(pubkey, wg.address.clone()) {
+                            if let Err(e) = api.create(pubkey, &wg.addresses()) {
                                 tracing::error!(
                                     "Failed to restore WireGuard connection {}: {}",
                                     conn_id,
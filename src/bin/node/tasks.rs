@@ -1,11 +1,12 @@
 use async_trait::async_trait;
 use futures::future::try_join_all;
 use rkyv::{AlignedVec, Deserialize, Infallible};
-use tokio::time::Duration;
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc;
 #[cfg(feature = "xray")]
 use tonic::Status;
 
-use fcore::{Action, BaseConnection as Connection, Message, Metrics, Topic};
+use fcore::{Action, BaseConnection as Connection, Command, CommandKind, Message, Metrics, Topic};
 #[cfg(any(feature = "xray", feature = "wireguard"))]
 use fcore::{ConnectionStorageBaseOperations, Proto, Tag};
 use fcore::{Error, Result};
@@ -14,16 +15,23 @@ use fcore::{StatsOp, XrayHandlerActions};
 
 use fcore::ConnectionBaseOperations;
 
+use super::config::ServiceSettings;
+use super::http::ApiRequests;
 #[cfg(any(feature = "xray", feature = "wireguard"))]
 use super::metrics::BusinessMetrics;
 use super::node::Node;
+#[cfg(any(feature = "xray", feature = "wireguard"))]
+use super::reconcile::Reconcile;
+use super::reload::Reload;
 
 #[async_trait]
 pub trait Tasks {
     async fn run_subscriber(&self) -> Result<()>;
     async fn handle_messages_batch(&self, msg: Vec<Message>) -> Result<()>;
     async fn handle_message(&self, msg: Message) -> Result<()>;
+    async fn handle_command(&self, cmd: Command, settings: &ServiceSettings) -> Result<()>;
     async fn collect_metrics(&self);
+    async fn reconcile(&self, dry_run: bool);
 }
 
 #[async_trait]
@@ -71,6 +79,16 @@ where
                     continue;
                 }
 
+                Topic::Command(uuid) if uuid != &node_uuid => {
+                    tracing::trace!("SUB: Skipping command for another node: {}", uuid);
+                    continue;
+                }
+
+                Topic::EnvCommand(env) if env != node_env => {
+                    tracing::trace!("SUB: Skipping command for another env: {}", env);
+                    continue;
+                }
+
                 _ => {
                     tracing::debug!("SUB: Accepted for processing: {:?}", topic);
                 }
@@ -80,6 +98,33 @@ where
                 continue;
             }
 
+            if topic.is_command() {
+                let command: Option<Command> = {
+                    let mut aligned = AlignedVec::new();
+                    aligned.extend_from_slice(&payload_bytes);
+
+                    match rkyv::check_archived_root::<Command>(&aligned) {
+                        Ok(archived) => archived.deserialize(&mut Infallible).ok(),
+                        Err(e) => {
+                            tracing::error!("SUB: Invalid rkyv command root: {:?}", e);
+                            None
+                        }
+                    }
+                };
+
+                // Never wait on the queue here, a long Resync would stall all updates
+                if let Some(cmd) = command {
+                    match self.commands.try_send(cmd) {
+                        Ok(()) => {}
+                        Err(mpsc::error::TrySendError::Full(cmd)) => {
+                            tracing::warn!("SUB: Command queue is full, dropped {}", cmd);
+                        }
+                        Err(e) => tracing::error!("SUB: Failed to queue command: {}", e),
+                    }
+                }
+                continue;
+            }
+
             let messages: Option<Vec<Message>> = {
                 let mut aligned = AlignedVec::new();
                 aligned.extend_from_slice(&payload_bytes);
@@ -94,12 +139,13 @@ where
             };
 
             if let Some(msgs) = messages {
+                self.updates_seq.fetch_add(1, Ordering::Relaxed);
+                self.last_update_at
+                    .store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
                 if let Err(err) = self.handle_messages_batch(msgs).await {
                     tracing::error!("SUB: Failed to handle messages: {}", err);
                 }
             }
-
-            tokio::time::sleep(Duration::from_millis(1)).await;
         }
     }
 
@@ -108,12 +154,36 @@ where
 
         let handles: Vec<_> = messages
             .into_iter()
-            .map(|msg| self.handle_message(msg))
+            .map(|msg| async move {
+                let journaled = !matches!(msg.action, Action::ResetStat);
+                let entry = journaled.then(|| msg.clone());
+                let result = self.handle_message(msg).await;
+                if let (Some(journal), Some(msg)) = (&self.journal, entry) {
+                    // A backend failure can follow the memory change, journal what memory holds
+                    let applied = match &result {
+                        Ok(()) => true,
+                        Err(_) => {
+                            let present = self.memory.read().await.get(&msg.conn_id).is_some();
+                            matches!(msg.action, Action::Delete) != present
+                        }
+                    };
+                    if applied {
+                        journal.append(&msg)?;
+                    }
+                }
+                result
+            })
             .collect();
 
-        let _results = try_join_all(handles).await?;
+        let results = try_join_all(handles).await;
 
-        Ok(())
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.flush().await {
+                tracing::error!("Journal flush failed: {}", e);
+            }
+        }
+
+        results.map(|_| ())
     }
 
     async fn handle_message(&self, msg: Message) -> Result<()> {
@@ -121,6 +191,9 @@ where
             Action::Create | Action::Update => {
                 #[cfg(any(feature = "xray", feature = "wireguard"))]
                 let conn_id: uuid::Uuid = msg.conn_id;
+                // Memory keeps following the API, backends catch up on undrain
+                #[cfg(any(feature = "xray", feature = "wireguard"))]
+                let draining = self.draining.load(Ordering::Relaxed);
 
                 match msg.tag {
                     #[cfg(feature = "wireguard")]
@@ -149,6 +222,11 @@ where
                                 })?;
                         }
 
+                        if draining {
+                            tracing::debug!("Node is draining, WG peer of {} is deferred", conn_id);
+                            return Ok(());
+                        }
+
                         let wg_api = self
                             .wg_client
                             .as_ref()
@@ -161,7 +239,7 @@ where
                                 return Err(Error::Custom("WG User already exist".into()));
                             }
 
-                            wg_api.create(&pubkey, wg.address).map_err(|e| {
+                            wg_api.create(&pubkey, &wg.addresses()).map_err(|e| {
                                 Error::Custom(format!("Failed to create WireGuard peer: {}", e))
                             })?;
 
@@ -185,16 +263,20 @@ where
                             Error::Grpc(Box::new(Status::unavailable("Xray handler unavailable")))
                         })?;
 
-                        client
-                            .create(&conn_id.clone(), msg.tag, None)
-                            .await
-                            .map_err(|err| {
-                                Error::Custom(format!(
-                                    "Failed to create conn {}: {}",
-                                    conn_id.clone(),
-                                    err
-                                ))
-                            })?;
+                        if draining {
+                            tracing::debug!("Node is draining, Xray user {} is deferred", conn_id);
+                        } else {
+                            client
+                                .create(&conn_id.clone(), msg.tag, None)
+                                .await
+                                .map_err(|err| {
+                                    Error::Custom(format!(
+                                        "Failed to create conn {}: {}",
+                                        conn_id.clone(),
+                                        err
+                                    ))
+                                })?;
+                        }
 
                         let mut mem = self.memory.write().await;
                         mem.add(&conn_id.clone(), conn.into()).map_err(|err| {
@@ -219,15 +301,22 @@ where
                                 )))
                             })?;
 
-                            client
-                                .create(&conn_id.clone(), msg.tag, Some(password))
-                                .await
-                                .map_err(|err| {
-                                    Error::Custom(format!(
-                                        "Failed to create conn {}: {}",
-                                        conn_id, err
-                                    ))
-                                })?;
+                            if draining {
+                                tracing::debug!(
+                                    "Node is draining, Xray user {} is deferred",
+                                    conn_id
+                                );
+                            } else {
+                                client
+                                    .create(&conn_id.clone(), msg.tag, Some(password))
+                                    .await
+                                    .map_err(|err| {
+                                        Error::Custom(format!(
+                                            "Failed to create conn {}: {}",
+                                            conn_id, err
+                                        ))
+                                    })?;
+                            }
 
                             let mut mem = self.memory.write().await;
                             mem.add(&conn_id.clone(), conn.into()).map_err(|err| {
@@ -318,6 +407,56 @@ where
         }
     }
 
+    async fn handle_command(&self, cmd: Command, settings: &ServiceSettings) -> Result<()> {
+        match cmd.kind {
+            CommandKind::Resync => {
+                for tag in self.sync_tags() {
+                    self.sync_connections(
+                        settings.api.endpoint.clone(),
+                        settings.api.token.clone(),
+                        tag,
+                        None,
+                    )
+                    .await?;
+                }
+                tracing::info!("Resync requested by {} is done", cmd.id);
+            }
+            CommandKind::Snapshot => {
+                self.snapshot_now.notify_one();
+            }
+            CommandKind::Reload => {
+                self.reload(settings).await?;
+                tracing::info!("Node reloaded by {}", cmd.id);
+            }
+            CommandKind::ResetStat => {
+                #[cfg(feature = "xray")]
+                {
+                    let conn_ids: Vec<uuid::Uuid> =
+                        self.memory.read().await.keys().copied().collect();
+                    for conn_id in conn_ids {
+                        if let Err(e) = self.reset(&conn_id).await {
+                            tracing::warn!("Couldn't reset stat for {}: {}", conn_id, e);
+                        }
+                    }
+                }
+                #[cfg(not(feature = "xray"))]
+                return Err(Error::Custom(
+                    "Reset stat is not supported, built without xray".into(),
+                ));
+            }
+            CommandKind::Drain => {
+                self.draining.store(true, Ordering::Relaxed);
+                tracing::warn!("Node is draining, new connections are kept out of the backends");
+            }
+            CommandKind::Undrain => {
+                self.draining.store(false, Ordering::Relaxed);
+                tracing::info!("Node stopped draining, adding deferred connections");
+                self.reconcile(false).await;
+            }
+        }
+        Ok(())
+    }
+
     async fn collect_metrics(&self) {
         self.heartbeat().await;
         self.bandwidth().await;
@@ -325,6 +464,7 @@ where
         self.loadavg().await;
         self.memory().await;
         self.disk_usage().await;
+        self.bus(&self.subscriber).await;
         #[cfg(feature = "xray")]
         if self.stats_client.is_some() {
             self.collect_inbound_metrics().await;
@@ -334,5 +474,43 @@ where
         if self.wg_client.is_some() {
             self.collect_wg_metrics().await;
         }
+        if let Some(registry) = &self.metrics.prometheus {
+            registry.sweep();
+        }
+    }
+
+    async fn reconcile(&self, dry_run: bool) {
+        // Deferred connections would count as drift and be added
+        if self.draining.load(Ordering::Relaxed) {
+            tracing::debug!("Node is draining, reconcile skipped");
+            return;
+        }
+        #[cfg(feature = "xray")]
+        match self.reconcile_xray(dry_run).await {
+            Ok(drift) => {
+                if !drift.is_clean() {
+                    tracing::warn!("Reconcile Xray (dry_run={}): {:?}", dry_run, drift);
+                }
+                self.push_drift("xray", &drift);
+            }
+            Err(e) => tracing::error!("Reconcile Xray failed: {}", e),
+        }
+
+        #[cfg(feature = "wireguard")]
+        match self.reconcile_wg(dry_run).await {
+            Ok(drift) => {
+                if !drift.is_clean() {
+                    tracing::warn!("Reconcile WG (dry_run={}): {:?}", dry_run, drift);
+                }
+                self.push_drift("wg", &drift);
+            }
+            Err(e) => tracing::error!("Reconcile WG failed: {}", e),
+        }
+
+        #[cfg(not(any(feature = "xray", feature = "wireguard")))]
+        tracing::debug!(
+            "Nothing to reconcile (dry_run={}), built without xray and wireguard",
+            dry_run
+        );
     }
 }
@@ -1,10 +1,10 @@
 use crate::config::inbound::StreamSettings;
 use crate::get_uuid_last_octet_simple;
 use serde::Serialize;
-use std::net::Ipv4Addr;
 
 use super::inbound::Inbound;
 use super::inbound::Network;
+use crate::memory::node::Address as NodeAddress;
 use crate::memory::tag::ProtoTag as Tag;
 
 #[derive(Serialize)]
@@ -106,7 +106,7 @@ pub trait InboundClashConfig {
         &self,
         conn_id: &uuid::Uuid,
         _hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Option<ClashProxy>;
     fn clash(proxies: Vec<ClashProxy>) -> ClashConfig;
@@ -141,7 +141,7 @@ impl InboundClashConfig for Inbound {
         &self,
         conn_id: &uuid::Uuid,
         _hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Option<ClashProxy> {
         let port = self.port;
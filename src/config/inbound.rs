@@ -1,7 +1,10 @@
 use base64::Engine;
+use flate2::{write::ZlibEncoder, Compression};
 use serde::{Deserialize, Serialize};
-use std::net::Ipv4Addr;
-use std::{fs::File, io::Read};
+use std::{
+    fs::File,
+    io::{Read, Write},
+};
 use url::Url;
 
 use crate::error::{Error, Result};
@@ -12,7 +15,7 @@ use crate::utils::get_uuid_last_octet_simple;
 
 use crate::config::h2::H2Settings;
 use crate::config::wireguard::WireguardSettings;
-use crate::memory::node::Stat as InboundStat;
+use crate::memory::node::{Address as NodeAddress, Stat as InboundStat};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
@@ -168,28 +171,28 @@ pub trait InboundConnLink {
         conn_id: &uuid::Uuid,
         conn: &Connection,
         hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String>;
     fn vless_xtls(
         &self,
         conn_id: &uuid::Uuid,
         hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String>;
     fn vless_grpc(
         &self,
         conn_id: &uuid::Uuid,
         hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String>;
     fn vless_xhttp(
         &self,
         conn_id: &uuid::Uuid,
         hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String>;
     fn h2(&self, hostname: &str, label: &str, conn: &Connection) -> Result<String>;
@@ -197,27 +200,54 @@ pub trait InboundConnLink {
         &self,
         conn_id: &uuid::Uuid,
         hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String>;
-    fn mtproto(&self, hostname: &str, address: &Ipv4Addr, label: &str) -> Result<String>;
+    fn mtproto(&self, hostname: &str, address: &NodeAddress, label: &str) -> Result<String>;
     fn wireguard(
         &self,
         conn_id: &uuid::Uuid,
         conn: &Connection,
         hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
+        label: &str,
+    ) -> Result<String>;
+    fn amnezia(
+        &self,
+        conn_id: &uuid::Uuid,
+        conn: &Connection,
+        hostname: &str,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String>;
 }
 
+pub const WG_PRIVKEY_PLACEHOLDER: &str = "<YOUR_PRIVATE_KEY>";
+
+/// Amnezia `vpn://` key: base64url of Qt qCompress'ed JSON
+fn amnezia_key(value: &serde_json::Value) -> Result<String> {
+    let json = serde_json::to_vec(value)?;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(8));
+    encoder.write_all(&json)?;
+    let compressed = encoder.finish()?;
+
+    let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+    payload.extend(compressed);
+
+    Ok(format!(
+        "vpn://{}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(payload)
+    ))
+}
+
 impl InboundConnLink for Inbound {
     fn create_link(
         &self,
         conn_id: &uuid::Uuid,
         conn: &Connection,
         hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String> {
         match self.tag {
@@ -237,17 +267,27 @@ impl InboundConnLink for Inbound {
         conn_id: &uuid::Uuid,
         conn: &Connection,
         _hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String> {
         tracing::debug!("Trying to print WG conn");
         if let Some(wg_conn) = conn.get_wireguard() {
-            let private_key = wg_conn.keys.privkey.clone();
-            let client_ip = wg_conn.address.clone();
+            // Client generated keypair, the user puts the private key in
+            let private_key = wg_conn
+                .keys
+                .privkey
+                .clone()
+                .unwrap_or_else(|| WG_PRIVKEY_PLACEHOLDER.to_string());
+            let client_ip = wg_conn
+                .addresses()
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
 
             if let Some(wg) = &self.wg {
                 let server_pubkey = wg.keys.pubkey()?;
-                let host = address;
+                let host = address.host();
                 let port = wg.port;
 
                 let dns = wg
@@ -257,12 +297,19 @@ impl InboundConnLink for Inbound {
                     .collect::<Vec<_>>()
                     .join(",");
 
+                let awg: String = wg
+                    .awg
+                    .iter()
+                    .flat_map(|awg| awg.interface_lines())
+                    .map(|(key, value)| format!("\n    {:<10} = {}", key, value))
+                    .collect();
+
                 let config = format!(
                     r#"
     [Interface]
     PrivateKey = {private_key}
     Address    = {client_ip}
-    DNS        = {dns}
+    DNS        = {dns}{awg}
 
     [Peer]
     PublicKey           = {server_pubkey}
@@ -283,11 +330,89 @@ impl InboundConnLink for Inbound {
         }
     }
 
+    fn amnezia(
+        &self,
+        conn_id: &uuid::Uuid,
+        conn: &Connection,
+        hostname: &str,
+        address: &NodeAddress,
+        label: &str,
+    ) -> Result<String> {
+        let wg = self
+            .wg
+            .as_ref()
+            .ok_or(Error::Custom("WG Inbound is not configured".into()))?;
+        let awg = wg
+            .awg
+            .as_ref()
+            .ok_or(Error::Custom("AWG is not configured".into()))?;
+        let wg_conn = conn
+            .get_wireguard()
+            .ok_or(Error::Custom("WG Conn is not configured".into()))?;
+        let client_priv_key = wg_conn.keys.privkey.clone().ok_or(Error::Custom(
+            "Amnezia key requires a server generated keypair".into(),
+        ))?;
+
+        let config = self
+            .wireguard(conn_id, conn, hostname, address, label)?
+            .lines()
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string();
+
+        let mut awg_fields = serde_json::Map::new();
+        for (key, value) in awg.interface_lines() {
+            awg_fields.insert(key.to_string(), value.into());
+        }
+
+        let mut last_config = awg_fields.clone();
+        last_config.extend([
+            (
+                "allowed_ips".into(),
+                serde_json::json!(["0.0.0.0/0", "::/0"]),
+            ),
+            (
+                "client_ip".into(),
+                wg_conn.address.address.to_string().into(),
+            ),
+            ("client_priv_key".into(), client_priv_key.into()),
+            ("client_pub_key".into(), wg_conn.keys.pubkey()?.into()),
+            ("config".into(), config.into()),
+            ("hostName".into(), address.to_string().into()),
+            ("persistent_keep_alive".into(), "25".into()),
+            ("port".into(), wg.port.into()),
+            ("server_pub_key".into(), wg.keys.pubkey()?.into()),
+        ]);
+
+        let mut container = awg_fields;
+        container.extend([
+            (
+                "last_config".into(),
+                serde_json::Value::Object(last_config).to_string().into(),
+            ),
+            ("port".into(), wg.port.to_string().into()),
+            ("transport_proto".into(), "udp".into()),
+        ]);
+
+        let dns: Vec<String> = wg.dns.iter().map(|d| d.to_string()).collect();
+
+        amnezia_key(&serde_json::json!({
+            "containers": [{ "awg": container, "container": "amnezia-awg" }],
+            "defaultContainer": "amnezia-awg",
+            "description": label,
+            "dns1": dns.first(),
+            "dns2": dns.get(1),
+            "hostName": address.to_string(),
+        }))
+    }
+
     fn vmess(
         &self,
         conn_id: &uuid::Uuid,
         _hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String> {
         let port = self.port;
@@ -361,7 +486,7 @@ impl InboundConnLink for Inbound {
         &self,
         conn_id: &uuid::Uuid,
         _hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String> {
         let s = self
@@ -383,7 +508,11 @@ impl InboundConnLink for Inbound {
             .first()
             .ok_or(Error::Custom("Missing SNI".into()))?;
 
-        let mut url = Url::parse(&format!("vless://{conn_id}@{address}:{}", self.port))?;
+        let mut url = Url::parse(&format!(
+            "vless://{conn_id}@{}:{}",
+            address.host(),
+            self.port
+        ))?;
         url.query_pairs_mut()
             .append_pair("security", "reality")
             .append_pair("flow", "xtls-rprx-vision")
@@ -405,7 +534,7 @@ impl InboundConnLink for Inbound {
         &self,
         conn_id: &uuid::Uuid,
         _hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String> {
         let s = self
@@ -421,7 +550,11 @@ impl InboundConnLink for Inbound {
             .as_ref()
             .ok_or(Error::Custom("Missing gRPC settings".into()))?;
 
-        let mut url = Url::parse(&format!("vless://{conn_id}@{address}:{}", self.port))?;
+        let mut url = Url::parse(&format!(
+            "vless://{conn_id}@{}:{}",
+            address.host(),
+            self.port
+        ))?;
         url.query_pairs_mut()
             .append_pair("security", "reality")
             .append_pair("type", "grpc")
@@ -442,7 +575,7 @@ impl InboundConnLink for Inbound {
         &self,
         conn_id: &uuid::Uuid,
         _hostname: &str,
-        address: &Ipv4Addr,
+        address: &NodeAddress,
         label: &str,
     ) -> Result<String> {
         let s = self
@@ -458,7 +591,11 @@ impl InboundConnLink for Inbound {
             .as_ref()
             .ok_or(Error::Custom("Missing xHTTP settings".into()))?;
 
-        let mut url = Url::parse(&format!("vless://{conn_id}@{address}:{}", self.port))?;
+        let mut url = Url::parse(&format!(
+            "vless://{conn_id}@{}:{}",
+            address.host(),
+            self.port
+        ))?;
         url.query_pairs_mut()
             .append_pair("security", "reality")
             .append_pair("type", "xhttp")
@@ -496,7 +633,7 @@ impl InboundConnLink for Inbound {
         }
     }
 
-    fn mtproto(&self, _hostname: &str, address: &Ipv4Addr, label: &str) -> Result<String> {
+    fn mtproto(&self, _hostname: &str, address: &NodeAddress, label: &str) -> Result<String> {
         let port = self.port;
 
         let secret = self
@@ -513,3 +650,30 @@ impl InboundConnLink for Inbound {
         Ok(url.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+
+    #[test]
+    fn test_amnezia_key_roundtrip() {
+        let value = serde_json::json!({ "hostName": "1.2.3.4", "description": "node" });
+        let key = amnezia_key(&value).unwrap();
+
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(key.strip_prefix("vpn://").unwrap())
+            .unwrap();
+        let len = u32::from_be_bytes(payload[..4].try_into().unwrap()) as usize;
+
+        let mut json = vec![];
+        ZlibDecoder::new(&payload[4..])
+            .read_to_end(&mut json)
+            .unwrap();
+        assert_eq!(json.len(), len);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            value
+        );
+    }
+}
@@ -1,11 +1,13 @@
 use serde::{de::DeserializeOwned, Deserialize};
 use std::env;
 use std::fs;
-use std::net::Ipv4Addr;
 use uuid::Uuid;
 
 use crate::error::{Error, Result};
-use crate::memory::{env::Env, node::Type};
+use crate::memory::{
+    env::Env,
+    node::{Address as NodeAddress, Type},
+};
 
 #[derive(Clone, Debug, Deserialize, Default)]
 pub struct ApiAccessConfig {
@@ -13,10 +15,52 @@ pub struct ApiAccessConfig {
     pub token: String,
 }
 
-#[derive(Clone, Default, Debug, Deserialize)]
+fn default_retry_batches() -> usize {
+    100
+}
+
+fn default_spill_max_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_max_user_series() -> usize {
+    10_000
+}
+
+/// Optional Prometheus `/metrics` endpoint
+#[derive(Clone, Debug, Deserialize)]
+pub struct PrometheusConfig {
+    pub listen: std::net::SocketAddr,
+    /// Per-user series beyond this are dropped, not exported
+    #[serde(default = "default_max_user_series")]
+    pub max_user_series: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
 pub struct MetricsTxConfig {
     pub publisher: String,
     pub interval: u64,
+    #[serde(default = "default_retry_batches")]
+    pub retry_batches: usize,
+    #[serde(default)]
+    pub spill_path: Option<String>,
+    #[serde(default = "default_spill_max_bytes")]
+    pub spill_max_bytes: u64,
+    #[serde(default)]
+    pub prometheus: Option<PrometheusConfig>,
+}
+
+impl Default for MetricsTxConfig {
+    fn default() -> Self {
+        Self {
+            publisher: String::new(),
+            interval: 0,
+            retry_batches: default_retry_batches(),
+            spill_path: None,
+            spill_max_bytes: default_spill_max_bytes(),
+            prometheus: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -24,7 +68,7 @@ pub struct NodeConfig {
     pub env: Env,
     pub hostname: String,
     pub default_interface: String,
-    pub address: Ipv4Addr,
+    pub address: NodeAddress,
     pub uuid: Uuid,
     pub label: String,
     pub max_bandwidth_bps: i64,
@@ -38,7 +82,7 @@ pub struct NodeConfigRaw {
     pub env: Env,
     pub hostname: Option<String>,
     pub default_interface: String,
-    pub address: Ipv4Addr,
+    pub address: NodeAddress,
     pub uuid: Uuid,
     pub label: String,
     pub max_bandwidth_bps: i64,
@@ -49,15 +93,14 @@ pub struct NodeConfigRaw {
 impl NodeConfig {
     pub fn from_raw(raw: NodeConfigRaw) -> Result<NodeConfig> {
         let num_cpus = std::thread::available_parallelism()?.get();
-        let hostname = if raw.hostname.is_none() {
-            match env::var("HOSTNAME") {
+        let hostname = match raw.hostname {
+            Some(hostname) => hostname,
+            None => match env::var("HOSTNAME") {
                 Ok(hostname) => hostname,
                 Err(_) => {
                     return Err(Error::Custom("Validation error: missing hostname (set $HOSTNAME env or specify in config)".into()));
                 }
-            }
-        } else {
-            raw.hostname.unwrap()
+            },
         };
 
         Ok(NodeConfig {
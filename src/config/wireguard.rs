@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 
 use crate::memory::connection::wireguard::IpAddrMask;
 use crate::{error::Error, WgKeys};
@@ -11,6 +11,98 @@ pub struct WireguardServerConfig {
     pub port: u16,
     pub private_key: String,
     pub dns: Option<Vec<String>>,
+    pub awg: Option<AwgParams>,
+}
+
+/// AmneziaWG junk packets and header obfuscation, both sides must match
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct AwgParams {
+    pub jc: u16,
+    pub jmin: u16,
+    pub jmax: u16,
+    pub s1: u16,
+    pub s2: u16,
+    pub h1: u32,
+    pub h2: u32,
+    pub h3: u32,
+    pub h4: u32,
+}
+
+impl Default for AwgParams {
+    // Same as plain WireGuard
+    fn default() -> Self {
+        Self {
+            jc: 0,
+            jmin: 0,
+            jmax: 0,
+            s1: 0,
+            s2: 0,
+            h1: 1,
+            h2: 2,
+            h3: 3,
+            h4: 4,
+        }
+    }
+}
+
+impl AwgParams {
+    /// Sets a param from an `[Interface]` line, false if the key is not AWG
+    pub fn set(&mut self, key: &str, value: &str) -> Result<bool, Error> {
+        let invalid = |_| Error::Custom(format!("Invalid AWG {} value: {}", key, value));
+
+        match key {
+            "Jc" => self.jc = value.parse().map_err(invalid)?,
+            "Jmin" => self.jmin = value.parse().map_err(invalid)?,
+            "Jmax" => self.jmax = value.parse().map_err(invalid)?,
+            "S1" => self.s1 = value.parse().map_err(invalid)?,
+            "S2" => self.s2 = value.parse().map_err(invalid)?,
+            "H1" => self.h1 = value.parse().map_err(invalid)?,
+            "H2" => self.h2 = value.parse().map_err(invalid)?,
+            "H3" => self.h3 = value.parse().map_err(invalid)?,
+            "H4" => self.h4 = value.parse().map_err(invalid)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.jc > 128 {
+            return Err(Error::Custom("AWG Jc must be 0..=128".into()));
+        }
+        if self.jc > 0 && (self.jmin > self.jmax || self.jmax > 1280) {
+            return Err(Error::Custom("AWG requires Jmin <= Jmax <= 1280".into()));
+        }
+        if self.s1 > 1132 || self.s2 > 1132 {
+            return Err(Error::Custom("AWG S1 and S2 must be <= 1132".into()));
+        }
+        if self.s1 + 56 == self.s2 {
+            return Err(Error::Custom("AWG S1 + 56 must not equal S2".into()));
+        }
+
+        let headers = [self.h1, self.h2, self.h3, self.h4];
+        let unique: std::collections::HashSet<_> = headers.iter().collect();
+        if unique.len() != headers.len() {
+            return Err(Error::Custom("AWG H1-H4 must be distinct".into()));
+        }
+
+        Ok(())
+    }
+
+    /// `[Interface]` lines of a client config
+    pub fn interface_lines(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Jc", self.jc.to_string()),
+            ("Jmin", self.jmin.to_string()),
+            ("Jmax", self.jmax.to_string()),
+            ("S1", self.s1.to_string()),
+            ("S2", self.s2.to_string()),
+            ("H1", self.h1.to_string()),
+            ("H2", self.h2.to_string()),
+            ("H3", self.h3.to_string()),
+            ("H4", self.h4.to_string()),
+        ]
+    }
 }
 
 impl WireguardServerConfig {
@@ -21,6 +113,8 @@ impl WireguardServerConfig {
         let mut address = None;
         let mut dns = vec![];
         let mut port = None;
+        let mut awg = AwgParams::default();
+        let mut is_awg = false;
 
         let interface = path
             .split('/')
@@ -60,6 +154,10 @@ impl WireguardServerConfig {
                     .filter_map(|v| v.trim().parse().ok())
                     .collect();
             }
+
+            if let Some((key, value)) = line.split_once('=') {
+                is_awg |= awg.set(key.trim(), value.trim())?;
+            }
         }
 
         Ok(Self {
@@ -68,6 +166,7 @@ impl WireguardServerConfig {
             private_key: private_key.ok_or_else(|| Error::Custom("no PrivateKey".into()))?,
             address: address.ok_or_else(|| Error::Custom("no Address".into()))?,
             dns: Some(dns),
+            awg: is_awg.then_some(awg),
         })
     }
 }
@@ -76,19 +175,27 @@ impl WireguardServerConfig {
 pub struct WireguardSettings {
     pub interface: String,
     pub address: IpAddrMask,
+    #[serde(default)]
+    pub address_v6: Option<IpAddrMask>,
     pub port: u16,
     pub keys: WgKeys,
-    pub dns: Vec<Ipv4Addr>,
+    pub dns: Vec<IpAddr>,
+    #[serde(default)]
+    pub awg: Option<AwgParams>,
 }
 
 impl std::fmt::Display for WireguardSettings {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{}|{}|{}|{}|{}",
+            "{}|{}|{}|{}|{}|{}",
             self.interface,
             self.address,
-            self.keys.privkey,
+            self.address_v6
+                .as_ref()
+                .map(|a| a.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            self.keys.pubkey,
             self.port,
             self.dns
                 .iter()
@@ -103,29 +210,68 @@ impl TryFrom<WireguardServerConfig> for WireguardSettings {
     type Error = Error;
 
     fn try_from(cfg: WireguardServerConfig) -> Result<Self, Error> {
-        let keys = WgKeys {
-            privkey: cfg.private_key,
-        };
+        let keys = WgKeys::from_privkey(&cfg.private_key)?;
 
-        let address = cfg
+        // Address = 10.10.0.1/16, fd00:10::1/64
+        let addresses = cfg
             .address
-            .parse::<IpAddrMask>()
+            .split(',')
+            .map(|a| a.trim().parse::<IpAddrMask>())
+            .collect::<Result<Vec<_>, _>>()
             .map_err(|_| Error::Custom("Invalid WG address".into()))?;
 
+        let address = addresses
+            .iter()
+            .find(|a| a.address.is_ipv4())
+            .cloned()
+            .ok_or_else(|| Error::Custom("WG address must include IPv4".into()))?;
+        let address_v6 = addresses.iter().find(|a| a.address.is_ipv6()).cloned();
+
         let dns = cfg
             .dns
             .unwrap_or_default()
             .into_iter()
-            .map(|d| d.parse::<Ipv4Addr>())
+            .map(|d| d.parse::<IpAddr>())
             .collect::<Result<Vec<_>, _>>()
             .map_err(|_| Error::Custom("Invalid DNS".into()))?;
 
+        if let Some(awg) = &cfg.awg {
+            awg.validate()?;
+        }
+
         Ok(Self {
             interface: cfg.interface,
             keys,
             address,
+            address_v6,
             port: cfg.port,
             dns,
+            awg: cfg.awg,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_awg_params() {
+        let mut awg = AwgParams::default();
+        assert!(awg.set("Jc", "4").unwrap());
+        assert!(awg.set("Jmin", "40").unwrap());
+        assert!(awg.set("Jmax", "70").unwrap());
+        assert!(awg.set("H1", "1234567").unwrap());
+        assert!(!awg.set("ListenPort", "51820").unwrap());
+        assert!(awg.set("S1", "x").is_err());
+        assert!(awg.validate().is_ok());
+
+        awg.s1 = 10;
+        awg.s2 = 66;
+        assert!(awg.validate().is_err());
+
+        awg.s2 = 20;
+        awg.h2 = awg.h1;
+        assert!(awg.validate().is_err());
+    }
+}
@@ -44,6 +44,9 @@ pub struct SubscriptionResponse {
     pub locations: Vec<EnvInfo>,
     pub downlink: i64,
     pub limit_bytes: i64,
+    /// Any connection of the subscription is online on some node right now
+    #[serde(default)]
+    pub online: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -35,8 +35,10 @@ pub use config::{
     h2::{H2Settings, Hysteria2Settings},
     inbound::{Inbound, InboundConnLink, Settings as XraySettings},
     mtproto::MtprotoSettings,
-    settings::{ApiAccessConfig, MetricsTxConfig, NodeConfig, NodeConfigRaw, Settings},
-    wireguard::{WireguardServerConfig, WireguardSettings},
+    settings::{
+        ApiAccessConfig, MetricsTxConfig, NodeConfig, NodeConfigRaw, PrometheusConfig, Settings,
+    },
+    wireguard::{AwgParams, WireguardServerConfig, WireguardSettings},
 };
 
 pub use memory::{
@@ -53,12 +55,17 @@ pub use memory::{
         Connections,
     },
     env::Env,
+    ip_pool::IpPool,
+    journal::Journal,
     key::{Code, Distributor, Key},
     node::{
-        Node, NodeMetricInfo, NodeResponse, Stat as InboundStat, Status as NodeStatus,
-        Type as NodeType,
+        Address as NodeAddress, Node, NodeMetricInfo, NodeResponse, Stat as InboundStat,
+        Status as NodeStatus, Type as NodeType,
+    },
+    snapshot::{
+        quarantine as quarantine_snapshot, write_atomic as write_snapshot_file, SnapshotHeader,
+        SnapshotManager,
     },
-    snapshot::SnapshotManager,
     stat::{Kind as StatKind, Stat},
     storage::{
         connection::ApiOp as ConnectionStorageApiOperations,
@@ -71,9 +78,14 @@ pub use memory::{
 };
 
 pub use metrics::{
-    storage::{HasMetrics, MetricBuffer, MetricStorage},
+    prometheus::{serve as serve_prometheus, PromRegistry},
+    storage::{
+        append_spill_file, decode_spill_records, encode_spill_records, repair_spill_file,
+        HasMetrics, MetricBuffer, MetricStorage,
+    },
     MetricEnvelope, Metrics,
 };
+pub use proto::hysteria2::Hysteria2Client;
 #[cfg(feature = "wireguard")]
 pub use proto::wireguard::WgApi;
 #[cfg(feature = "xray")]
@@ -88,6 +100,7 @@ pub use proto::xray::{
 pub use utils::*;
 
 pub use zmq::{
+    command::{Command, Kind as CommandKind},
     message::{Action, Message},
     publisher::Publisher,
     subscriber::Subscriber,
@@ -12,6 +12,7 @@ use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 #[derive(
     Archive, Deserialize, Serialize, RkyvDeserialize, RkyvSerialize, Debug, Clone, PartialEq,
 )]
+#[archive(check_bytes)]
 pub struct Base {
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
@@ -1,8 +1,11 @@
 use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::ops::{Deref, DerefMut};
+use std::ops::Deref;
+
+use self::operation::base::Operations as ConnectionBaseOp;
+use super::tag::ProtoTag;
 
 pub(crate) mod base;
 pub(crate) mod conn;
@@ -11,19 +14,172 @@ pub(crate) mod proto;
 pub mod stat;
 pub mod wireguard;
 
-#[derive(Archive, Deserialize, Serialize, RkyvDeserialize, RkyvSerialize, Debug, Clone)]
+/// Connections by id. Mutations go through the methods below so the
+/// secondary indexes stay in step with the map
+#[derive(Archive, RkyvDeserialize, RkyvSerialize, Debug, Clone)]
 #[archive(check_bytes)]
-pub struct Connections<C>(pub HashMap<uuid::Uuid, C>);
+pub struct Connections<C> {
+    conns: HashMap<uuid::Uuid, C>,
+    /// Hysteria2 token => conn id
+    tokens: HashMap<uuid::Uuid, uuid::Uuid>,
+    /// Subscription id => conn ids
+    subscriptions: HashMap<uuid::Uuid, HashSet<uuid::Uuid>>,
+    protos: HashMap<ProtoTag, HashSet<uuid::Uuid>>,
+}
+
+/// Values a connection is indexed by
+#[derive(PartialEq)]
+struct IndexKeys {
+    token: Option<uuid::Uuid>,
+    subscription_id: Option<uuid::Uuid>,
+    proto: ProtoTag,
+}
+
+impl IndexKeys {
+    fn of<C: ConnectionBaseOp>(conn: &C) -> Self {
+        Self {
+            token: conn.get_token(),
+            subscription_id: conn.get_subscription_id(),
+            proto: conn.get_proto().proto(),
+        }
+    }
+}
 
 impl<C> Default for Connections<C> {
     fn default() -> Self {
-        Connections(HashMap::new())
+        Connections {
+            conns: HashMap::new(),
+            tokens: HashMap::new(),
+            subscriptions: HashMap::new(),
+            protos: HashMap::new(),
+        }
+    }
+}
+
+impl<C: ConnectionBaseOp> From<HashMap<uuid::Uuid, C>> for Connections<C> {
+    fn from(conns: HashMap<uuid::Uuid, C>) -> Self {
+        let mut connections = Connections::default();
+        for (conn_id, conn) in &conns {
+            connections.index(conn_id, IndexKeys::of(conn));
+        }
+        connections.conns = conns;
+        connections
+    }
+}
+
+impl<C: ConnectionBaseOp> Connections<C> {
+    /// Inserts or replaces the connection, returning the previous one
+    pub fn insert(&mut self, conn_id: uuid::Uuid, conn: C) -> Option<C> {
+        let keys = IndexKeys::of(&conn);
+        let prev = self.conns.insert(conn_id, conn);
+        if let Some(prev) = &prev {
+            self.unindex(&conn_id, IndexKeys::of(prev));
+        }
+        self.index(&conn_id, keys);
+        prev
+    }
+
+    pub fn take(&mut self, conn_id: &uuid::Uuid) -> Option<C> {
+        let conn = self.conns.remove(conn_id)?;
+        self.unindex(conn_id, IndexKeys::of(&conn));
+        Some(conn)
+    }
+
+    /// Mutates a connection in place and reindexes it
+    pub fn update<R>(&mut self, conn_id: &uuid::Uuid, f: impl FnOnce(&mut C) -> R) -> Option<R> {
+        let conn = self.conns.get_mut(conn_id)?;
+        let before = IndexKeys::of(conn);
+        let res = f(conn);
+        let after = IndexKeys::of(conn);
+
+        if before != after {
+            self.unindex(conn_id, before);
+            self.index(conn_id, after);
+        }
+        Some(res)
+    }
+
+    pub fn by_token(&self, token: &uuid::Uuid) -> Option<uuid::Uuid> {
+        self.tokens.get(token).copied()
+    }
+
+    pub fn by_subscription(
+        &self,
+        subscription_id: &uuid::Uuid,
+    ) -> impl Iterator<Item = (&uuid::Uuid, &C)> {
+        self.lookup(self.subscriptions.get(subscription_id))
+    }
+
+    pub fn by_proto(&self, proto: &ProtoTag) -> impl Iterator<Item = (&uuid::Uuid, &C)> {
+        self.lookup(self.protos.get(proto))
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        ids: Option<&'a HashSet<uuid::Uuid>>,
+    ) -> impl Iterator<Item = (&'a uuid::Uuid, &'a C)> {
+        ids.into_iter()
+            .flatten()
+            .filter_map(|id| self.conns.get_key_value(id))
+    }
+
+    fn index(&mut self, conn_id: &uuid::Uuid, keys: IndexKeys) {
+        if let Some(token) = keys.token {
+            self.tokens.insert(token, *conn_id);
+        }
+        if let Some(sub_id) = keys.subscription_id {
+            self.subscriptions
+                .entry(sub_id)
+                .or_default()
+                .insert(*conn_id);
+        }
+        self.protos.entry(keys.proto).or_default().insert(*conn_id);
+    }
+
+    fn unindex(&mut self, conn_id: &uuid::Uuid, keys: IndexKeys) {
+        // Another connection may have taken the token over since
+        if let Some(token) = keys.token {
+            if self.tokens.get(&token) == Some(conn_id) {
+                self.tokens.remove(&token);
+            }
+        }
+        if let Some(sub_id) = keys.subscription_id {
+            remove_from(&mut self.subscriptions, &sub_id, conn_id);
+        }
+        remove_from(&mut self.protos, &keys.proto, conn_id);
+    }
+}
+
+fn remove_from<K: Eq + std::hash::Hash>(
+    index: &mut HashMap<K, HashSet<uuid::Uuid>>,
+    key: &K,
+    conn_id: &uuid::Uuid,
+) {
+    if let Some(ids) = index.get_mut(key) {
+        ids.remove(conn_id);
+        if ids.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+/// Only the map is serialized, indexes are rebuilt when read back
+impl<C: Serialize> Serialize for Connections<C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.conns.serialize(serializer)
+    }
+}
+
+impl<'de, C: Deserialize<'de> + ConnectionBaseOp> Deserialize<'de> for Connections<C> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let conns: HashMap<uuid::Uuid, C> = Deserialize::deserialize(deserializer)?;
+        Ok(Connections::from(conns))
     }
 }
 
 impl<C: fmt::Display> fmt::Display for Connections<C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (uuid, conn) in &self.0 {
+        for (uuid, conn) in &self.conns {
             writeln!(f, "{} => {}", uuid, conn)?;
         }
         Ok(())
@@ -34,12 +190,63 @@ impl<C> Deref for Connections<C> {
     type Target = HashMap<uuid::Uuid, C>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.conns
     }
 }
 
-impl<C> DerefMut for Connections<C> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+#[cfg(test)]
+mod tests {
+    use super::base::Base;
+    use super::proto::Proto;
+    use super::*;
+
+    #[test]
+    fn test_token_index_follows_mutations() {
+        let mut conns: Connections<Base> = Connections::default();
+        let conn_id = uuid::Uuid::new_v4();
+        let (old, new) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+
+        conns.insert(conn_id, Base::new(Proto::new_hysteria2(&old), None, None));
+        assert_eq!(conns.by_token(&old), Some(conn_id));
+
+        // Replacing rotates the token
+        conns.insert(conn_id, Base::new(Proto::new_hysteria2(&new), None, None));
+        assert_eq!(conns.by_token(&old), None);
+        assert_eq!(conns.by_token(&new), Some(conn_id));
+
+        conns.update(&conn_id, |c| c.set_proto(Proto::new_hysteria2(&old)));
+        assert_eq!(conns.by_token(&old), Some(conn_id));
+        assert_eq!(conns.by_token(&new), None);
+
+        conns.take(&conn_id);
+        assert_eq!(conns.by_token(&old), None);
+        assert!(conns.tokens.is_empty());
+    }
+
+    #[test]
+    fn test_subscription_and_proto_index_follow_mutations() {
+        let mut conns: Connections<Base> = Connections::default();
+        let (sub_a, sub_b) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let (conn_1, conn_2) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let vmess = Proto::new_xray(&ProtoTag::Vmess);
+
+        conns.insert(conn_1, Base::new(vmess.clone(), None, Some(sub_a)));
+        conns.insert(conn_2, Base::new(vmess, None, Some(sub_a)));
+        assert_eq!(conns.by_subscription(&sub_a).count(), 2);
+        assert_eq!(conns.by_proto(&ProtoTag::Vmess).count(), 2);
+
+        conns.update(&conn_1, |c| c.subscription_id = Some(sub_b));
+        assert_eq!(conns.by_subscription(&sub_a).count(), 1);
+        assert_eq!(conns.by_subscription(&sub_b).count(), 1);
+
+        // Full reload through serde rebuilds what was not serialized
+        let json = serde_json::to_string(&conns).unwrap();
+        let mut reloaded: Connections<Base> = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded.by_subscription(&sub_b).count(), 1);
+        assert_eq!(reloaded.by_proto(&ProtoTag::Vmess).count(), 2);
+
+        reloaded.take(&conn_1);
+        reloaded.take(&conn_2);
+        assert!(reloaded.subscriptions.is_empty() && reloaded.protos.is_empty());
     }
 }
@@ -5,7 +5,6 @@ use crate::zmq::message::Action;
 use crate::zmq::message::Message;
 
 pub trait Operations {
-    fn get_subscription_id(&self) -> Option<uuid::Uuid>;
     fn set_subscription_id(&mut self, subscription_id: &uuid::Uuid);
 
     fn get_env(&self) -> Env;
@@ -21,9 +20,6 @@ impl Operations for Conn {
         self.env.clone()
     }
 
-    fn get_subscription_id(&self) -> Option<uuid::Uuid> {
-        self.subscription_id
-    }
     fn set_subscription_id(&mut self, subscription_id: &uuid::Uuid) {
         self.subscription_id = Some(*subscription_id);
     }
@@ -46,7 +42,7 @@ impl Operations for Conn {
         };
 
         let wg = match &self.proto {
-            Proto::Wireguard { param, .. } => Some(param.clone()),
+            Proto::Wireguard { param, .. } => Some(param.public()),
             _ => None,
         };
 
@@ -74,7 +70,7 @@ impl Operations for Conn {
         let expires_at = self.expires_at;
 
         let wg = match &self.proto {
-            Proto::Wireguard { param, .. } => Some(param.clone()),
+            Proto::Wireguard { param, .. } => Some(param.public()),
             _ => None,
         };
 
@@ -107,7 +103,7 @@ impl Operations for Conn {
         let expires_at = self.expires_at;
 
         let wg = match &self.proto {
-            Proto::Wireguard { param, .. } => Some(param.clone()),
+            Proto::Wireguard { param, .. } => Some(param.public()),
             _ => None,
         };
 
@@ -23,6 +23,7 @@ pub trait Operations {
     fn get_wireguard(&self) -> Option<&WgParam>;
     fn get_password(&self) -> Option<String>;
     fn get_token(&self) -> Option<uuid::Uuid>;
+    fn get_subscription_id(&self) -> Option<uuid::Uuid>;
     fn set_password(&mut self, password: Option<String>) -> Result<()>;
 }
 
@@ -70,6 +71,10 @@ impl Operations for Base {
         }
     }
 
+    fn get_subscription_id(&self) -> Option<uuid::Uuid> {
+        self.subscription_id
+    }
+
     fn get_wireguard(&self) -> Option<&WgParam> {
         match &self.proto {
             Proto::Wireguard { param, .. } => Some(param),
@@ -151,4 +156,8 @@ impl Operations for Conn {
             _ => None,
         }
     }
+
+    fn get_subscription_id(&self) -> Option<uuid::Uuid> {
+        self.subscription_id
+    }
 }
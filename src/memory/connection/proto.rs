@@ -1,7 +1,7 @@
 use serde::Deserialize;
 use serde::Serialize;
 
-use super::super::tag::ProtoTag as Tag;
+use super::super::tag::ProtoTag;
 use super::wireguard::Param as WgParam;
 
 use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
@@ -9,22 +9,23 @@ use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 #[derive(
     Archive, Deserialize, Serialize, RkyvDeserialize, RkyvSerialize, Debug, Clone, PartialEq,
 )]
+#[archive(check_bytes)]
 pub enum Proto {
     Wireguard { param: WgParam },
     Shadowsocks { password: String },
-    Xray(Tag),
+    Xray(ProtoTag),
     Hysteria2 { token: uuid::Uuid },
     Mtproto { secret: String },
 }
 
 impl Proto {
-    pub fn proto(&self) -> Tag {
+    pub fn proto(&self) -> ProtoTag {
         match self {
-            Proto::Wireguard { .. } => Tag::Wireguard,
-            Proto::Shadowsocks { .. } => Tag::Shadowsocks,
-            Proto::Hysteria2 { .. } => Tag::Hysteria2,
+            Proto::Wireguard { .. } => ProtoTag::Wireguard,
+            Proto::Shadowsocks { .. } => ProtoTag::Shadowsocks,
+            Proto::Hysteria2 { .. } => ProtoTag::Hysteria2,
             Proto::Xray(tag) => *tag,
-            Proto::Mtproto { .. } => Tag::Mtproto,
+            Proto::Mtproto { .. } => ProtoTag::Mtproto,
         }
     }
 
@@ -50,7 +51,7 @@ impl Proto {
         Proto::Hysteria2 { token: *token }
     }
 
-    pub fn new_xray(tag: &Tag) -> Self {
+    pub fn new_xray(tag: &ProtoTag) -> Self {
         Proto::Xray(*tag)
     }
 
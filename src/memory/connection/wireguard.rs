@@ -12,38 +12,77 @@ use x25519_dalek::{PublicKey, StaticSecret};
 
 use crate::error::Error;
 
-#[derive(
-    Archive, Clone, Debug, Serialize, Deserialize, PartialEq, RkyvDeserialize, RkyvSerialize,
-)]
+#[derive(Archive, Clone, Serialize, Deserialize, PartialEq, RkyvDeserialize, RkyvSerialize)]
 #[archive(check_bytes)]
 pub struct Keys {
-    pub privkey: String,
+    /// None when the client generated the keypair and kept the private key
+    pub privkey: Option<String>,
+    pub pubkey: String,
+}
+
+// Private key stays out of logs
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keys")
+            .field("privkey", &self.privkey.as_ref().map(|_| "<redacted>"))
+            .field("pubkey", &self.pubkey)
+            .finish()
+    }
 }
 
 impl Default for Keys {
     fn default() -> Self {
         let secret = StaticSecret::random_from_rng(OsRng);
-        let privkey_bytes = secret.as_bytes();
+        let public = PublicKey::from(&secret);
 
         Self {
-            privkey: general_purpose::STANDARD.encode(privkey_bytes),
+            privkey: Some(general_purpose::STANDARD.encode(secret.as_bytes())),
+            pubkey: general_purpose::STANDARD.encode(public.as_bytes()),
         }
     }
 }
 
 impl Keys {
+    pub fn from_privkey(privkey: &str) -> Result<Self, Error> {
+        Ok(Self {
+            pubkey: Self::derive_pubkey(privkey)?,
+            privkey: Some(privkey.to_string()),
+        })
+    }
+
+    /// Keys of a client that keeps its private key
+    pub fn from_pubkey(pubkey: &str) -> Result<Self, Error> {
+        Self::decode_key(pubkey)?;
+        Ok(Self {
+            privkey: None,
+            pubkey: pubkey.to_string(),
+        })
+    }
+
     pub fn pubkey(&self) -> Result<String, Error> {
-        Self::derive_pubkey(&self.privkey)
+        Ok(self.pubkey.clone())
     }
 
-    fn derive_pubkey(private_key_b64: &str) -> Result<String, Error> {
-        let private_vec = general_purpose::STANDARD
-            .decode(private_key_b64)
-            .map_err(|e| Error::Custom(format!("invalid base64 private key: {}", e)))?;
+    /// Same keys without the private part, for the wire and logs
+    pub fn public(&self) -> Self {
+        Self {
+            privkey: None,
+            pubkey: self.pubkey.clone(),
+        }
+    }
+
+    fn decode_key(key_b64: &str) -> Result<[u8; 32], Error> {
+        let bytes = general_purpose::STANDARD
+            .decode(key_b64)
+            .map_err(|e| Error::Custom(format!("invalid base64 key: {}", e)))?;
 
-        let private_bytes: [u8; 32] = private_vec
+        bytes
             .try_into()
-            .map_err(|_| Error::Custom("Private key must be exactly 32 bytes".to_string()))?;
+            .map_err(|_| Error::Custom("Key must be exactly 32 bytes".to_string()))
+    }
+
+    fn derive_pubkey(private_key_b64: &str) -> Result<String, Error> {
+        let private_bytes = Self::decode_key(private_key_b64)?;
 
         let secret = StaticSecret::from(private_bytes);
         let public = PublicKey::from(&secret);
@@ -241,6 +280,8 @@ impl FromStr for IpAddrMask {
 pub struct Param {
     pub keys: Keys,
     pub address: IpAddrMask,
+    #[serde(default)]
+    pub address_v6: Option<IpAddrMask>,
 }
 
 impl Param {
@@ -248,8 +289,25 @@ impl Param {
         Self {
             keys: Keys::default(),
             address: ip,
+            address_v6: None,
         }
     }
+
+    /// Param without the client private key, nodes need only the pubkey
+    pub fn public(&self) -> Self {
+        Self {
+            keys: self.keys.public(),
+            address: self.address.clone(),
+            address_v6: self.address_v6.clone(),
+        }
+    }
+
+    /// Peer addresses, IPv4 first
+    pub fn addresses(&self) -> Vec<IpAddrMask> {
+        std::iter::once(self.address.clone())
+            .chain(self.address_v6.clone())
+            .collect()
+    }
 }
 
 impl<'de> Deserialize<'de> for IpAddrMask {
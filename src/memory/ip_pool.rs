@@ -0,0 +1,331 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::connection::wireguard::IpAddrMask;
+use crate::error::{Error, Result};
+
+/// IPv4 host address allocator over a WireGuard network.
+///
+/// Network, server (first host) and broadcast addresses are reserved.
+/// Released addresses go to a free list and are handed out before
+/// never-used ones, lowest first. Claimed addresses don't move the scan
+/// position, so gaps below them are handed out without being materialized.
+/// Addresses other API instances hold are skipped until the pool is rebuilt.
+#[derive(Debug, Clone)]
+pub struct IpPool {
+    network: IpAddrMask,
+    first: u32,
+    last: u32,
+    next: u32,
+    reserved: HashSet<u32>,
+    allocated: HashMap<u32, uuid::Uuid>,
+    foreign: HashSet<u32>,
+    free: BTreeSet<u32>,
+}
+
+impl IpPool {
+    pub fn new(network: IpAddrMask) -> Result<Self> {
+        let base = network
+            .as_ipv4()
+            .ok_or_else(|| Error::Custom("Only IPv4 WireGuard networks are supported".into()))?;
+
+        if network.cidr > 30 {
+            return Err(Error::Custom(format!(
+                "WireGuard network {} is too small",
+                network
+            )));
+        }
+
+        let mask = if network.cidr == 0 {
+            0
+        } else {
+            u32::MAX << (32 - network.cidr)
+        };
+        let network_addr = u32::from(base) & mask;
+        let broadcast = network_addr | !mask;
+        let server = network_addr + 1;
+
+        let mut reserved = HashSet::new();
+        reserved.insert(network_addr);
+        reserved.insert(server);
+        reserved.insert(broadcast);
+
+        Ok(Self {
+            network,
+            first: server + 1,
+            last: broadcast - 1,
+            next: server + 1,
+            reserved,
+            allocated: HashMap::new(),
+            foreign: HashSet::new(),
+            free: BTreeSet::new(),
+        })
+    }
+
+    pub fn network(&self) -> &IpAddrMask {
+        &self.network
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        let ip = u32::from(ip);
+        ip >= self.first && ip <= self.last
+    }
+
+    /// Excludes an address from allocation, e.g. a manually configured peer
+    pub fn reserve(&mut self, ip: Ipv4Addr) {
+        let ip = u32::from(ip);
+        self.free.remove(&ip);
+        self.reserved.insert(ip);
+    }
+
+    /// Address found taken in the database by a connection this pool doesn't know,
+    /// it is skipped by `allocate` but can still be claimed
+    pub fn reserve_foreign(&mut self, ip: Ipv4Addr) {
+        let key = u32::from(ip);
+        if !self.contains(ip) || self.allocated.contains_key(&key) {
+            return;
+        }
+        self.free.remove(&key);
+        self.foreign.insert(key);
+    }
+
+    pub fn owner(&self, ip: Ipv4Addr) -> Option<uuid::Uuid> {
+        self.allocated.get(&u32::from(ip)).copied()
+    }
+
+    /// Marks an already assigned address as taken, fails on conflict
+    pub fn claim(&mut self, ip: Ipv4Addr, conn_id: uuid::Uuid) -> Result<()> {
+        if !self.contains(ip) {
+            return Err(Error::Custom(format!("{} is out of {}", ip, self.network)));
+        }
+
+        let key = u32::from(ip);
+        if self.reserved.contains(&key) {
+            return Err(Error::Custom(format!("{} is reserved", ip)));
+        }
+
+        match self.allocated.get(&key) {
+            Some(owner) if *owner == conn_id => return Ok(()),
+            Some(owner) => {
+                return Err(Error::Custom(format!(
+                    "{} is already allocated to {}",
+                    ip, owner
+                )))
+            }
+            None => {}
+        }
+
+        self.allocated.insert(key, conn_id);
+        self.foreign.remove(&key);
+        self.free.remove(&key);
+
+        Ok(())
+    }
+
+    pub fn allocate(&mut self, conn_id: uuid::Uuid) -> Result<IpAddrMask> {
+        let ip = self
+            .candidate()
+            .ok_or_else(|| Error::Custom(format!("No free addresses left in {}", self.network)))?;
+        self.claim(ip, conn_id)?;
+
+        let key = u32::from(ip);
+        if key >= self.next {
+            self.next = key + 1;
+        }
+        Ok(IpAddrMask::host(IpAddr::V4(ip)))
+    }
+
+    /// Next address `allocate` would hand out, without taking it
+    pub fn candidate(&self) -> Option<Ipv4Addr> {
+        if let Some(ip) = self.free.first() {
+            return Some(Ipv4Addr::from(*ip));
+        }
+
+        (self.next..=self.last)
+            .find(|ip| {
+                !self.reserved.contains(ip)
+                    && !self.allocated.contains_key(ip)
+                    && !self.foreign.contains(ip)
+            })
+            .map(Ipv4Addr::from)
+    }
+
+    /// Returns the address to the free list, only the owner can release it
+    pub fn release(&mut self, ip: Ipv4Addr, conn_id: &uuid::Uuid) -> bool {
+        let key = u32::from(ip);
+        match self.allocated.get(&key) {
+            Some(owner) if owner == conn_id => {
+                self.allocated.remove(&key);
+                // Addresses above `next` are found by the scan anyway
+                if key < self.next {
+                    self.free.insert(key);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks that an IPv6 prefix can hold every host of the pool
+    pub fn check_ipv6_prefix(prefix: &IpAddrMask) -> Result<()> {
+        match prefix.address {
+            IpAddr::V6(ip) if prefix.cidr <= 96 => {
+                if ip.segments()[0] & 0xfe00 != 0xfc00 {
+                    tracing::warn!("{} is not a unique local (fc00::/7) prefix", prefix);
+                }
+                Ok(())
+            }
+            _ => Err(Error::Custom(format!(
+                "{} must be an IPv6 prefix of /96 or shorter",
+                prefix
+            ))),
+        }
+    }
+
+    /// IPv6 /128 paired with an IPv4 host: same host offset within the prefix,
+    /// so dual-stack addresses need no allocation of their own
+    pub fn ipv6_for(&self, ip: Ipv4Addr, prefix: &IpAddrMask) -> Option<IpAddrMask> {
+        let IpAddr::V6(base) = prefix.address else {
+            return None;
+        };
+        if prefix.cidr > 96 {
+            return None;
+        }
+
+        let network_addr = self.first - 2;
+        let offset = u32::from(ip).checked_sub(network_addr)?;
+        let mask = if prefix.cidr == 0 {
+            0
+        } else {
+            u128::MAX << (128 - prefix.cidr)
+        };
+        let v6 = (u128::from(base) & mask) | offset as u128;
+
+        Some(IpAddrMask::host(IpAddr::V6(Ipv6Addr::from(v6))))
+    }
+
+    pub fn allocated(&self) -> usize {
+        self.allocated.len()
+    }
+
+    /// An address can be reserved after it was allocated, each is counted once
+    pub fn available(&self) -> usize {
+        let total = (self.last - self.first + 1) as usize;
+        let taken: HashSet<u32> = self
+            .reserved
+            .iter()
+            .chain(self.allocated.keys())
+            .chain(self.foreign.iter())
+            .filter(|ip| **ip >= self.first && **ip <= self.last)
+            .copied()
+            .collect();
+        total.saturating_sub(taken.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(net: &str) -> IpPool {
+        IpPool::new(net.parse().unwrap()).unwrap()
+    }
+
+    fn ip(s: &str) -> Ipv4Addr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_allocates_after_server_address() {
+        let mut pool = pool("10.1.0.0/16");
+        let addr = pool.allocate(uuid::Uuid::new_v4()).unwrap();
+        assert_eq!(addr.to_string(), "10.1.0.2/32");
+        let addr = pool.allocate(uuid::Uuid::new_v4()).unwrap();
+        assert_eq!(addr.to_string(), "10.1.0.3/32");
+    }
+
+    #[test]
+    fn test_reuses_released_address() {
+        let mut pool = pool("10.1.0.0/16");
+        let first = uuid::Uuid::new_v4();
+        pool.allocate(first).unwrap();
+        pool.allocate(uuid::Uuid::new_v4()).unwrap();
+
+        assert!(pool.release(ip("10.1.0.2"), &first));
+        let addr = pool.allocate(uuid::Uuid::new_v4()).unwrap();
+        assert_eq!(addr.as_ipv4(), Some(ip("10.1.0.2")));
+    }
+
+    #[test]
+    fn test_release_by_other_conn_is_ignored() {
+        let mut pool = pool("10.1.0.0/16");
+        pool.allocate(uuid::Uuid::new_v4()).unwrap();
+        assert!(!pool.release(ip("10.1.0.2"), &uuid::Uuid::new_v4()));
+        assert!(pool.owner(ip("10.1.0.2")).is_some());
+    }
+
+    #[test]
+    fn test_claim_conflict_and_gaps() {
+        let mut pool = pool("10.1.0.0/16");
+        let conn = uuid::Uuid::new_v4();
+        pool.claim(ip("10.1.0.5"), conn).unwrap();
+        assert!(pool.claim(ip("10.1.0.5"), conn).is_ok());
+        assert!(pool.claim(ip("10.1.0.5"), uuid::Uuid::new_v4()).is_err());
+        assert!(pool.claim(ip("10.1.0.1"), uuid::Uuid::new_v4()).is_err());
+        assert!(pool.claim(ip("10.2.0.1"), uuid::Uuid::new_v4()).is_err());
+
+        // Gap before the claimed address is filled first
+        let addr = pool.allocate(uuid::Uuid::new_v4()).unwrap();
+        assert_eq!(addr.as_ipv4(), Some(ip("10.1.0.2")));
+    }
+
+    #[test]
+    fn test_foreign_address_is_skipped_and_claimable() {
+        let mut pool = pool("10.1.0.0/29");
+        let conn = uuid::Uuid::new_v4();
+        pool.allocate(conn).unwrap();
+        pool.release(ip("10.1.0.2"), &conn);
+        pool.reserve_foreign(ip("10.1.0.2"));
+        pool.reserve_foreign(ip("10.1.0.3"));
+        assert_eq!(pool.available(), 3);
+
+        let addr = pool.allocate(uuid::Uuid::new_v4()).unwrap();
+        assert_eq!(addr.as_ipv4(), Some(ip("10.1.0.4")));
+
+        // The database has the final word, a claim takes it over
+        pool.claim(ip("10.1.0.2"), conn).unwrap();
+        assert!(pool.release(ip("10.1.0.2"), &conn));
+        assert_eq!(pool.available(), 3);
+    }
+
+    #[test]
+    fn test_exhaustion() {
+        let mut pool = pool("10.1.0.0/29");
+        // .0 network, .1 server, .7 broadcast
+        assert_eq!(pool.available(), 5);
+        for _ in 0..5 {
+            pool.allocate(uuid::Uuid::new_v4()).unwrap();
+        }
+        assert!(pool.allocate(uuid::Uuid::new_v4()).is_err());
+
+        pool.reserve(ip("10.1.0.2"));
+        assert_eq!(pool.available(), 0);
+    }
+
+    #[test]
+    fn test_ipv6_pairing() {
+        let pool = pool("10.1.0.0/16");
+        let prefix: IpAddrMask = "fd00:10::/64".parse().unwrap();
+        let v6 = pool.ipv6_for(ip("10.1.2.3"), &prefix).unwrap();
+        assert_eq!(v6.to_string(), "fd00:10::203/128");
+
+        assert!(IpPool::check_ipv6_prefix(&prefix).is_ok());
+        assert!(IpPool::check_ipv6_prefix(&"fd00::/112".parse().unwrap()).is_err());
+        assert!(IpPool::check_ipv6_prefix(&"10.0.0.0/8".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_ipv6() {
+        assert!(IpPool::new("fd00::/64".parse().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use rkyv::AlignedVec;
+use rkyv::{Deserialize, Infallible};
+use std::path::{Path, PathBuf};
+use tokio::fs as async_fs;
+
+use crate::error::Result;
+use crate::metrics::storage::{
+    append_spill_file, complete_spill_len, decode_spill_records, encode_spill_records,
+    repair_spill_file,
+};
+use crate::zmq::message::Message;
+
+/// Append-only log of applied messages since the last snapshot.
+///
+/// On snapshot the live file is rotated to `<path>.old`, which is dropped once the
+/// snapshot is on disk. Replay reads `.old` first, then the live file
+pub struct Journal {
+    path: PathBuf,
+    rotated: PathBuf,
+    pending: parking_lot::Mutex<Vec<Vec<u8>>>,
+    /// True once torn tails left by a crash are cut
+    io: tokio::sync::Mutex<bool>,
+}
+
+impl Journal {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            rotated: PathBuf::from(format!("{}.old", path)),
+            pending: parking_lot::Mutex::new(Vec::new()),
+            io: tokio::sync::Mutex::new(false),
+        }
+    }
+
+    /// Buffers the message, it is durable only after `flush`
+    pub fn append(&self, msg: &Message) -> Result<()> {
+        let bytes = rkyv::to_bytes::<_, 1024>(msg)?;
+        self.pending.lock().push(bytes.into_vec());
+        Ok(())
+    }
+
+    /// Writes buffered messages with a single fsync
+    pub async fn flush(&self) -> Result<usize> {
+        let mut repaired = self.io.lock().await;
+        self.flush_locked(&mut repaired).await
+    }
+
+    async fn flush_locked(&self, repaired: &mut bool) -> Result<usize> {
+        let records = std::mem::take(&mut *self.pending.lock());
+        if records.is_empty() {
+            return Ok(0);
+        }
+
+        if !*repaired {
+            for path in [&self.rotated, &self.path] {
+                repair_spill_file(path).await?;
+            }
+            *repaired = true;
+        }
+
+        append_spill_file(&self.path, &encode_spill_records(&records)).await?;
+        Ok(records.len())
+    }
+
+    /// Call right before taking a snapshot, everything journaled so far is in memory
+    pub async fn rotate(&self) -> Result<()> {
+        let mut repaired = self.io.lock().await;
+        self.flush_locked(&mut repaired).await?;
+
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        if self.rotated.exists() {
+            // Previous snapshot failed, keep both generations
+            let bytes = async_fs::read(&self.path).await?;
+            repair_spill_file(&self.rotated).await?;
+            append_spill_file(&self.rotated, &bytes[..complete_spill_len(&bytes)]).await?;
+            async_fs::remove_file(&self.path).await?;
+        } else {
+            async_fs::rename(&self.path, &self.rotated).await?;
+        }
+        Ok(())
+    }
+
+    /// Call once the snapshot is written
+    pub async fn compact(&self) -> Result<()> {
+        let _io = self.io.lock().await;
+        if self.rotated.exists() {
+            async_fs::remove_file(&self.rotated).await?;
+        }
+        Ok(())
+    }
+
+    /// Drops everything, used when there is no snapshot to replay on top of
+    pub async fn clear(&self) -> Result<()> {
+        let _io = self.io.lock().await;
+        self.pending.lock().clear();
+        for path in [&self.rotated, &self.path] {
+            if path.exists() {
+                async_fs::remove_file(path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Messages in the order they were applied, corrupt records are skipped
+    pub async fn replay(&self) -> Result<Vec<Message>> {
+        let _io = self.io.lock().await;
+        let mut messages = vec![];
+        for path in [&self.rotated, &self.path] {
+            messages.extend(Self::read(path).await?);
+        }
+        Ok(messages)
+    }
+
+    async fn read(path: &Path) -> Result<Vec<Message>> {
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut messages = vec![];
+        for record in decode_spill_records(&async_fs::read(path).await?) {
+            let mut aligned = AlignedVec::with_capacity(record.len());
+            aligned.extend_from_slice(&record);
+            match rkyv::check_archived_root::<Message>(&aligned) {
+                Ok(archived) => {
+                    let msg: Message = archived.deserialize(&mut Infallible)?;
+                    messages.push(msg);
+                }
+                Err(_) => tracing::warn!("Skipping corrupted journal record in {:?}", path),
+            }
+        }
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::tag::ProtoTag;
+    use crate::zmq::message::Action;
+
+    fn message(action: Action) -> Message {
+        Message {
+            conn_id: uuid::Uuid::new_v4(),
+            action,
+            tag: ProtoTag::Vmess,
+            wg: None,
+            password: None,
+            token: None,
+            expires_at: None,
+            subscription_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_journal_rotate_and_replay() {
+        let path = std::env::temp_dir().join(format!("journal-{}", uuid::Uuid::new_v4()));
+        let journal = Journal::new(&path.to_string_lossy());
+
+        let first = message(Action::Create);
+        let second = message(Action::Delete);
+
+        journal.append(&first).unwrap();
+        journal.flush().await.unwrap();
+        journal.rotate().await.unwrap();
+        journal.append(&second).unwrap();
+        journal.flush().await.unwrap();
+
+        let ids: Vec<_> = journal
+            .replay()
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.conn_id)
+            .collect();
+        assert_eq!(ids, vec![first.conn_id, second.conn_id]);
+
+        journal.compact().await.unwrap();
+        let ids: Vec<_> = journal
+            .replay()
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.conn_id)
+            .collect();
+        assert_eq!(ids, vec![second.conn_id]);
+
+        journal.clear().await.unwrap();
+        assert!(journal.replay().await.unwrap().is_empty());
+    }
+}
@@ -1,5 +1,7 @@
 pub(crate) mod connection;
 pub(crate) mod env;
+pub(crate) mod ip_pool;
+pub(crate) mod journal;
 pub(crate) mod key;
 pub(crate) mod node;
 pub(crate) mod snapshot;
@@ -2,13 +2,13 @@ use std::fmt;
 use std::str::FromStr;
 use std::{
     collections::{BTreeMap, HashMap},
-    net::Ipv4Addr,
+    net::IpAddr,
 };
 
 use chrono::DateTime;
 use chrono::Utc;
 use postgres_types::{FromSql, ToSql};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::env::Env;
 use super::tag::ProtoTag as Tag;
@@ -95,13 +95,98 @@ impl FromStr for Type {
     }
 }
 
+/// Public address of a node, IPv4, IPv6 or hostname
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Address {
+    Ip(IpAddr),
+    Hostname(String),
+}
+
+impl Address {
+    /// Host part of an URL or `host:port` endpoint, IPv6 goes in brackets
+    pub fn host(&self) -> String {
+        match self {
+            Address::Ip(IpAddr::V6(ip)) => format!("[{}]", ip),
+            Address::Ip(ip) => ip.to_string(),
+            Address::Hostname(name) => name.clone(),
+        }
+    }
+
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Address::Ip(ip) => Some(*ip),
+            Address::Hostname(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Ip(ip) => write!(f, "{}", ip),
+            Address::Hostname(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let unbracketed = s
+            .strip_prefix('[')
+            .and_then(|v| v.strip_suffix(']'))
+            .unwrap_or(s);
+
+        if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+            return Ok(Address::Ip(ip));
+        }
+
+        let valid = !s.is_empty()
+            && s.len() <= 253
+            && s.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+
+        if valid {
+            Ok(Address::Hostname(s.to_ascii_lowercase()))
+        } else {
+            Err(format!("Invalid node address: {}", s))
+        }
+    }
+}
+
+impl From<IpAddr> for Address {
+    fn from(ip: IpAddr) -> Self {
+        Address::Ip(ip)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NodeResponse {
     pub uuid: uuid::Uuid,
     pub env: String,
     pub hostname: String,
     pub interface: String,
-    pub address: Ipv4Addr,
+    pub address: Address,
     pub inbounds: Vec<Tag>,
     pub status: Status,
     pub label: String,
@@ -124,7 +209,7 @@ pub struct Node {
     pub uuid: uuid::Uuid,
     pub env: Env,
     pub hostname: String,
-    pub address: Ipv4Addr,
+    pub address: Address,
     pub status: Status,
     pub label: String,
     pub interface: String,
@@ -254,7 +339,7 @@ impl Node {
             env: self.env.to_string(),
             hostname: self.hostname.clone(),
             interface: self.interface.clone(),
-            address: self.address,
+            address: self.address.clone(),
             uuid: self.uuid,
             inbounds: tags,
             status: self.status,
@@ -309,3 +394,31 @@ pub struct Stat {
     pub uplink: i64,
     pub conn_count: i64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_parse() {
+        let v4: Address = "1.2.3.4".parse().unwrap();
+        assert_eq!(v4.host(), "1.2.3.4");
+
+        let v6: Address = "2001:db8::1".parse().unwrap();
+        assert_eq!(v6.host(), "[2001:db8::1]");
+        assert_eq!(v6.to_string(), "2001:db8::1");
+        assert_eq!("[2001:db8::1]".parse::<Address>().unwrap(), v6);
+
+        let host: Address = "Node-1.example.com".parse().unwrap();
+        assert_eq!(host, Address::Hostname("node-1.example.com".into()));
+
+        assert!("bad host".parse::<Address>().is_err());
+        assert!("-x.example.com".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn test_address_serde() {
+        let addr: Address = serde_json::from_str("\"fd00::2\"").unwrap();
+        assert_eq!(serde_json::to_string(&addr).unwrap(), "\"fd00::2\"");
+    }
+}
@@ -1,28 +1,238 @@
 use chrono::Utc;
-use rkyv::with::AsOwned;
-use rkyv::with::With;
+use rkyv::validation::validators::DefaultValidator;
 use rkyv::Infallible;
-use rkyv::{to_bytes, Archive, Deserialize, Serialize};
-use serde::{Deserialize as SerdeDeserialize, Serialize as SerdeSerialize};
+use rkyv::{to_bytes, AlignedVec, Archive, CheckBytes, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 use tokio::fs as async_fs;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::RwLock;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 
+use super::connection::base::Base;
 use super::connection::conn::Conn;
+use super::connection::operation::base::Operations as ConnectionBaseOp;
 use super::connection::Connections;
 
-#[derive(Archive, Deserialize, Serialize, SerdeDeserialize, SerdeSerialize, Debug, Clone)]
-#[archive(check_bytes)]
-pub struct SnapshotData<C>
-where
-    C: Archive + Send + Sync + Clone + 'static,
-{
-    pub timestamp: u64,
-    pub memory: Connections<C>,
+/// Bumped whenever the archived layout of connections changes, once per release
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Version of the headerless snapshots written by the first release, see `v1`
+const LEGACY_VERSION: u32 = 1;
+
+const MAGIC: &[u8; 4] = b"FSNP";
+
+/// magic | version u32 | node uuid | timestamp u64 | payload len u64 | sha256(payload)
+const HEADER_LEN: usize = 4 + 4 + 16 + 8 + 8 + 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotHeader {
     pub version: u32,
+    pub node_id: uuid::Uuid,
+    pub timestamp: u64,
+    pub len: u64,
+    pub checksum: [u8; 32],
+}
+
+impl SnapshotHeader {
+    pub fn new(version: u32, node_id: uuid::Uuid, timestamp: u64, payload: &[u8]) -> Self {
+        Self {
+            version,
+            node_id,
+            timestamp,
+            len: payload.len() as u64,
+            checksum: checksum(payload),
+        }
+    }
+
+    /// Header followed by the payload, ready to be written out
+    pub fn seal(&self, payload: &[u8]) -> Vec<u8> {
+        let mut buf = self.encode();
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    /// Validates node id, version, length and checksum and returns the payload.
+    /// Ok(None) means the bytes carry no header at all
+    pub fn open(
+        bytes: &[u8],
+        node_id: uuid::Uuid,
+        max_version: u32,
+    ) -> std::result::Result<Option<(Self, &[u8])>, String> {
+        let Some(header) = Self::decode(bytes) else {
+            return Ok(None);
+        };
+
+        if header.node_id != node_id {
+            return Err(format!("belongs to node {}", header.node_id));
+        }
+        if header.version > max_version {
+            return Err(format!(
+                "version {} is newer than supported",
+                header.version
+            ));
+        }
+
+        let payload = &bytes[HEADER_LEN..];
+        if payload.len() as u64 != header.len {
+            return Err(format!(
+                "payload is {} bytes, header says {}",
+                payload.len(),
+                header.len
+            ));
+        }
+        if checksum(payload) != header.checksum {
+            return Err("checksum mismatch".into());
+        }
+
+        Ok(Some((header, payload)))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(self.node_id.as_bytes());
+        buf.extend_from_slice(&self.timestamp.to_le_bytes());
+        buf.extend_from_slice(&self.len.to_le_bytes());
+        buf.extend_from_slice(&self.checksum);
+        buf
+    }
+
+    /// None if the bytes don't start with a complete header
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return None;
+        }
+        Some(Self {
+            version: u32::from_le_bytes(bytes[4..8].try_into().ok()?),
+            node_id: uuid::Uuid::from_slice(&bytes[8..24]).ok()?,
+            timestamp: u64::from_le_bytes(bytes[24..32].try_into().ok()?),
+            len: u64::from_le_bytes(bytes[32..40].try_into().ok()?),
+            checksum: bytes[40..72].try_into().ok()?,
+        })
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; 32] {
+    Sha256::digest(payload).into()
+}
+
+/// Writes through a temp file, fsyncs and renames over `path`
+pub async fn write_atomic(path: &str, bytes: &[u8]) -> Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    let mut file = async_fs::File::create(&temp_path).await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await?;
+    async_fs::rename(&temp_path, path).await?;
+    Ok(())
+}
+
+/// Moves a bad snapshot aside so it can be inspected, the caller starts fresh
+pub async fn quarantine(path: &str, reason: &str) -> Result<()> {
+    let target = format!("{}.corrupt-{}", path, Utc::now().timestamp());
+    tracing::error!(
+        "Snapshot {} is unusable ({}), moved to {}",
+        path,
+        reason,
+        target
+    );
+    async_fs::rename(path, &target)
+        .await
+        .map_err(|e| Error::Custom(format!("Couldn't quarantine snapshot: {}", e)))
+}
+
+/// Headerless layout of `LEGACY_VERSION`: WireGuard keys carried only the
+/// private key and peers had no IPv6 address. Mirrors the released types
+/// field for field, don't change it
+mod v1 {
+    use rkyv::{Archive, Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    use super::super::connection::base::Base as CurrentBase;
+    use super::super::connection::proto::Proto as CurrentProto;
+    use super::super::connection::wireguard::{
+        IpAddrMask, Keys as CurrentKeys, Param as CurrentParam,
+    };
+    use super::super::tag::ProtoTag;
+
+    #[derive(Archive, Deserialize, Serialize)]
+    #[archive(check_bytes)]
+    pub struct Keys {
+        pub privkey: String,
+    }
+
+    #[derive(Archive, Deserialize, Serialize)]
+    #[archive(check_bytes)]
+    pub struct Param {
+        pub keys: Keys,
+        pub address: IpAddrMask,
+    }
+
+    #[derive(Archive, Deserialize, Serialize)]
+    #[archive(check_bytes)]
+    pub enum Proto {
+        Wireguard { param: Param },
+        Shadowsocks { password: String },
+        Xray(ProtoTag),
+        Hysteria2 { token: uuid::Uuid },
+        Mtproto { secret: String },
+    }
+
+    #[derive(Archive, Deserialize, Serialize)]
+    #[archive(check_bytes)]
+    pub struct Base {
+        pub created_at: chrono::DateTime<chrono::Utc>,
+        pub modified_at: chrono::DateTime<chrono::Utc>,
+        pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+        pub proto: Proto,
+        pub subscription_id: Option<uuid::Uuid>,
+        pub is_deleted: bool,
+    }
+
+    #[derive(Archive, Deserialize, Serialize)]
+    #[archive(check_bytes)]
+    pub struct Connections(pub HashMap<uuid::Uuid, Base>);
+
+    #[derive(Archive, Deserialize, Serialize)]
+    #[archive(check_bytes)]
+    pub struct SnapshotData {
+        pub timestamp: u64,
+        pub memory: Connections,
+        pub version: u32,
+    }
+
+    impl Base {
+        /// Public keys were derived on use back then, now they are stored
+        pub fn upgrade(self) -> Result<CurrentBase, String> {
+            let proto = match self.proto {
+                Proto::Wireguard { param } => CurrentProto::Wireguard {
+                    param: CurrentParam {
+                        keys: CurrentKeys::from_privkey(&param.keys.privkey)
+                            .map_err(|e| format!("WireGuard key: {}", e))?,
+                        address: param.address,
+                        address_v6: None,
+                    },
+                },
+                Proto::Shadowsocks { password } => CurrentProto::Shadowsocks { password },
+                Proto::Xray(tag) => CurrentProto::Xray(tag),
+                Proto::Hysteria2 { token } => CurrentProto::Hysteria2 { token },
+                Proto::Mtproto { secret } => CurrentProto::Mtproto { secret },
+            };
+
+            Ok(CurrentBase {
+                created_at: self.created_at,
+                modified_at: self.modified_at,
+                expires_at: self.expires_at,
+                proto,
+                subscription_id: self.subscription_id,
+                is_deleted: self.is_deleted,
+            })
+        }
+    }
 }
 
 pub struct SnapshotManager<C>
@@ -30,6 +240,7 @@ where
     C: Archive + Send + Sync + Clone + 'static,
 {
     pub snapshot_path: String,
+    pub node_id: uuid::Uuid,
     pub memory: Arc<RwLock<C>>,
 }
 
@@ -54,6 +265,7 @@ where
     fn clone(&self) -> Self {
         SnapshotManager {
             snapshot_path: self.snapshot_path.clone(),
+            node_id: self.node_id,
             memory: self.memory.clone(),
         }
     }
@@ -67,6 +279,7 @@ where
         + Clone
         + 'static
         + std::convert::From<Conn>
+        + ConnectionBaseOp
         + rkyv::Serialize<
             rkyv::ser::serializers::CompositeSerializer<
                 rkyv::ser::serializers::AlignedSerializer<rkyv::AlignedVec>,
@@ -78,9 +291,14 @@ where
             >,
         >,
 {
-    pub fn new(snapshot_path: String, memory: Arc<RwLock<Connections<C>>>) -> Self {
+    pub fn new(
+        snapshot_path: String,
+        node_id: uuid::Uuid,
+        memory: Arc<RwLock<Connections<C>>>,
+    ) -> Self {
         Self {
             snapshot_path,
+            node_id,
             memory,
         }
     }
@@ -91,59 +309,132 @@ where
         let timestamp = Utc::now().timestamp() as u64;
         drop(memory_guard);
 
-        let snapshot = SnapshotData {
-            timestamp,
-            memory,
-            version: 1,
-        };
-
-        let bytes = to_bytes::<_, 256>(&snapshot)?;
-
-        let temp_path = format!("{}.tmp", self.snapshot_path);
-        async_fs::write(&temp_path, &bytes).await?;
-        async_fs::rename(&temp_path, &self.snapshot_path).await?;
+        let payload = to_bytes::<_, 256>(&memory)?;
+        let header = SnapshotHeader::new(SNAPSHOT_VERSION, self.node_id, timestamp, &payload);
 
-        Ok(())
+        write_atomic(&self.snapshot_path, &header.seal(&payload)).await
     }
 
+    /// Loads the snapshot into memory and returns its timestamp.
+    /// Files that fail validation are quarantined and None is returned
     pub async fn load_snapshot(&self) -> Result<Option<u64>>
     where
-        <C as Archive>::Archived: rkyv::Deserialize<C, rkyv::Infallible>,
+        <C as Archive>::Archived: Deserialize<C, Infallible>,
+        <Connections<C> as Archive>::Archived: for<'a> CheckBytes<DefaultValidator<'a>>,
+        C: From<Base>,
     {
         if !Path::new(&self.snapshot_path).exists() {
             return Ok(None);
         }
 
         let bytes = async_fs::read(&self.snapshot_path).await?;
-        let archived = unsafe { rkyv::archived_root::<SnapshotData<C>>(&bytes) };
-        let with: With<SnapshotData<C>, AsOwned> = archived.deserialize(&mut Infallible)?;
-        let snapshot: SnapshotData<C> = with.into_inner();
-
-        let mut memory_guard = self.memory.write().await;
-        memory_guard.0 = snapshot.memory.0.clone();
-
-        Ok(Some(snapshot.timestamp))
+        match self.decode(&bytes) {
+            Ok((timestamp, connections)) => {
+                let mut memory_guard = self.memory.write().await;
+                *memory_guard = connections;
+                Ok(Some(timestamp))
+            }
+            Err(reason) => {
+                quarantine(&self.snapshot_path, &reason).await?;
+                Ok(None)
+            }
+        }
     }
 
+    /// Timestamp from the header, None for missing, legacy or foreign snapshots
     pub async fn get_snapshot_timestamp(&self) -> Result<Option<u64>> {
         if !Path::new(&self.snapshot_path).exists() {
             return Ok(None);
         }
 
-        let bytes = async_fs::read(&self.snapshot_path).await?;
-        let archived = unsafe { rkyv::archived_root::<SnapshotData<C>>(&bytes) };
+        let mut header = vec![0u8; HEADER_LEN];
+        let mut file = async_fs::File::open(&self.snapshot_path).await?;
+        if tokio::io::AsyncReadExt::read_exact(&mut file, &mut header)
+            .await
+            .is_err()
+        {
+            return Ok(None);
+        }
 
-        Ok(Some(archived.timestamp))
+        Ok(SnapshotHeader::decode(&header)
+            .filter(|h| h.version == SNAPSHOT_VERSION && h.node_id == self.node_id)
+            .map(|h| h.timestamp))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> std::result::Result<(u64, Connections<C>), String>
+    where
+        <C as Archive>::Archived: Deserialize<C, Infallible>,
+        <Connections<C> as Archive>::Archived: for<'a> CheckBytes<DefaultValidator<'a>>,
+        C: From<Base>,
+    {
+        let Some((header, payload)) = SnapshotHeader::open(bytes, self.node_id, SNAPSHOT_VERSION)?
+        else {
+            return Self::migrate_legacy(bytes);
+        };
+
+        let connections = Self::migrate(header.version, payload)?;
+        Ok((header.timestamp, connections))
+    }
+
+    /// Decodes the payload of a headered snapshot written by `version`
+    fn migrate(version: u32, payload: &[u8]) -> std::result::Result<Connections<C>, String>
+    where
+        <C as Archive>::Archived: Deserialize<C, Infallible>,
+        <Connections<C> as Archive>::Archived: for<'a> CheckBytes<DefaultValidator<'a>>,
+    {
+        let mut aligned = AlignedVec::with_capacity(payload.len());
+        aligned.extend_from_slice(payload);
+
+        match version {
+            SNAPSHOT_VERSION => {
+                let archived = rkyv::check_archived_root::<Connections<C>>(&aligned)
+                    .map_err(|e| format!("invalid payload: {}", e))?;
+                archived
+                    .deserialize(&mut Infallible)
+                    .map_err(|e| format!("deserialize: {:?}", e))
+            }
+            // Add an arm converting the old archived layout when bumping SNAPSHOT_VERSION
+            v => Err(format!("no migration from version {}", v)),
+        }
+    }
+
+    /// Headerless files: only `LEGACY_VERSION` was ever released
+    fn migrate_legacy(bytes: &[u8]) -> std::result::Result<(u64, Connections<C>), String>
+    where
+        C: From<Base>,
+    {
+        let mut aligned = AlignedVec::with_capacity(bytes.len());
+        aligned.extend_from_slice(bytes);
+        let archived = rkyv::check_archived_root::<v1::SnapshotData>(&aligned)
+            .map_err(|_| "unrecognized legacy snapshot".to_string())?;
+        if archived.version != LEGACY_VERSION {
+            return Err(format!(
+                "legacy version {} can't be migrated",
+                archived.version
+            ));
+        }
+        let snapshot: v1::SnapshotData = archived
+            .deserialize(&mut Infallible)
+            .map_err(|e| format!("deserialize: {:?}", e))?;
+
+        let conns = snapshot
+            .memory
+            .0
+            .into_iter()
+            .map(|(conn_id, conn)| Ok((conn_id, C::from(conn.upgrade()?))))
+            .collect::<std::result::Result<HashMap<_, _>, String>>()?;
+        tracing::info!("Migrated legacy snapshot v{}", LEGACY_VERSION);
+        Ok((snapshot.timestamp, Connections::from(conns)))
     }
 
     pub async fn len(&self) -> usize {
         let mem = self.memory.read().await;
-        mem.0.len()
+        mem.len()
     }
 
     pub async fn is_empty(&self) -> bool {
         let mem = self.memory.read().await;
-        mem.0.len() == 0
+        mem.is_empty()
     }
 }
 
@@ -153,3 +444,121 @@ impl<C: Send + Sync + Clone + From<Conn>> Connections<C> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::connection::base::Base;
+    use crate::memory::connection::proto::Proto;
+    use crate::memory::connection::wireguard::Keys;
+    use crate::memory::tag::ProtoTag;
+
+    fn manager(node_id: uuid::Uuid, path: &str) -> SnapshotManager<Connections<Base>> {
+        SnapshotManager::new(
+            path.to_string(),
+            node_id,
+            Arc::new(RwLock::new(Connections::default())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_roundtrip_and_quarantine() {
+        let path = std::env::temp_dir()
+            .join(format!("snapshot-{}.bin", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        let node_id = uuid::Uuid::new_v4();
+
+        let writer = manager(node_id, &path);
+        writer.memory.write().await.insert(
+            uuid::Uuid::new_v4(),
+            Base::new(Proto::new_xray(&ProtoTag::Vmess), None, None),
+        );
+        writer.create_snapshot().await.unwrap();
+
+        let reader = manager(node_id, &path);
+        assert!(reader.load_snapshot().await.unwrap().is_some());
+        assert_eq!(reader.len().await, 1);
+
+        // Foreign node is refused and moved aside
+        assert_eq!(
+            manager(uuid::Uuid::new_v4(), &path)
+                .load_snapshot()
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(!Path::new(&path).exists());
+
+        // Truncated payload fails the length check
+        writer.create_snapshot().await.unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(reader.load_snapshot().await.unwrap(), None);
+        assert!(!Path::new(&path).exists());
+
+        let dir = Path::new(&path).parent().unwrap();
+        let name = Path::new(&path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        for entry in std::fs::read_dir(dir).unwrap().flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&name) {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_release_v1_snapshot_is_migrated() {
+        let path = std::env::temp_dir()
+            .join(format!("snapshot-{}.bin", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        let (wg_id, h2_id, token) = (
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+        );
+        let keys = Keys::default();
+        let v1_base = |proto| v1::Base {
+            created_at: Utc::now(),
+            modified_at: Utc::now(),
+            expires_at: None,
+            proto,
+            subscription_id: None,
+            is_deleted: false,
+        };
+
+        let old = v1::SnapshotData {
+            timestamp: 1,
+            memory: v1::Connections(HashMap::from([
+                (
+                    wg_id,
+                    v1_base(v1::Proto::Wireguard {
+                        param: v1::Param {
+                            keys: v1::Keys {
+                                privkey: keys.privkey.clone().unwrap(),
+                            },
+                            address: "10.0.0.2/32".parse().unwrap(),
+                        },
+                    }),
+                ),
+                (h2_id, v1_base(v1::Proto::Hysteria2 { token })),
+            ])),
+            version: LEGACY_VERSION,
+        };
+        std::fs::write(&path, to_bytes::<_, 256>(&old).unwrap()).unwrap();
+
+        let reader = manager(uuid::Uuid::new_v4(), &path);
+        assert_eq!(reader.load_snapshot().await.unwrap(), Some(1));
+        let memory = reader.memory.read().await;
+        let wg = memory.get(&wg_id).unwrap().get_wireguard().unwrap();
+        assert_eq!(wg.keys.pubkey, keys.pubkey);
+        assert_eq!(wg.address_v6, None);
+        assert_eq!(memory.by_token(&token), Some(h2_id));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,10 +1,7 @@
-use std::collections::hash_map::Entry;
-
 use super::super::connection::conn::Conn as Connection;
 use super::super::connection::conn::ConnPatch as ConnectionPatch;
 use super::super::connection::operation::api::Operations as ConnectionApiOp;
 use super::super::connection::operation::base::Operations as ConnectionBaseOp;
-use super::super::connection::proto::Proto;
 use super::super::connection::wireguard::IpAddrMask;
 use super::super::connection::Connections;
 use super::super::storage::Status as OperationStatus;
@@ -39,7 +36,7 @@ where
     C: ConnectionBaseOp + Clone + Send + Sync + 'static,
 {
     fn len(&self) -> usize {
-        self.0.len()
+        (**self).len()
     }
 
     fn is_empty(&self) -> bool {
@@ -47,35 +44,25 @@ where
     }
 
     fn validate_token(&self, token: &uuid::Uuid) -> Option<uuid::Uuid> {
-        self.iter()
-            .find(|(_, conn)| {
-                matches!(
-                    conn.get_proto(),
-                    Proto::Hysteria2 { token: t } if t == *token
-                )
-            })
-            .map(|(id, _)| *id)
+        self.by_token(token)
     }
 
     fn add(&mut self, conn_id: &uuid::Uuid, new_conn: C) -> Result<OperationStatus> {
-        match self.entry(*conn_id) {
-            Entry::Occupied(_) => return Ok(OperationStatus::AlreadyExist(*conn_id)),
-            Entry::Vacant(entry) => {
-                entry.insert(new_conn);
-            }
+        if self.contains_key(conn_id) {
+            return Ok(OperationStatus::AlreadyExist(*conn_id));
         }
+        self.insert(*conn_id, new_conn);
         Ok(OperationStatus::Ok(*conn_id))
     }
 
     fn remove(&mut self, conn_id: &uuid::Uuid) -> Result<()> {
-        self.0
-            .remove(conn_id)
+        self.take(conn_id)
             .map(|_| ())
             .ok_or(Error::Custom("Conn not found".into()))
     }
 
     fn get(&self, conn_id: &uuid::Uuid) -> Option<C> {
-        self.0.get(conn_id).cloned()
+        (**self).get(conn_id).cloned()
     }
 }
 
@@ -84,13 +71,11 @@ where
     C: ConnectionBaseOp + ConnectionApiOp + Clone + Send + Sync + PartialEq<C> + 'static,
 {
     fn add(&mut self, conn_id: &uuid::Uuid, new_conn: C) -> Result<OperationStatus> {
-        match self.entry(*conn_id) {
-            Entry::Occupied(_entry) => Ok(OperationStatus::AlreadyExist(*conn_id)),
-            Entry::Vacant(entry) => {
-                entry.insert(new_conn);
-                Ok(OperationStatus::Ok(*conn_id))
-            }
+        if self.contains_key(conn_id) {
+            return Ok(OperationStatus::AlreadyExist(*conn_id));
         }
+        self.insert(*conn_id, new_conn);
+        Ok(OperationStatus::Ok(*conn_id))
     }
 
     fn apply_update(conn: &mut Connection, patch: ConnectionPatch) -> Option<Connection> {
@@ -154,8 +139,7 @@ where
 
     fn get_by_subscription_id(&self, subscription_id: &uuid::Uuid) -> Option<Vec<(uuid::Uuid, C)>> {
         let conns: Vec<(uuid::Uuid, C)> = self
-            .iter()
-            .filter(|(_, conn)| conn.get_subscription_id() == Some(*subscription_id))
+            .by_subscription(subscription_id)
             .map(|(conn_id, conn)| (*conn_id, conn.clone()))
             .collect();
 
@@ -168,8 +152,7 @@ where
 
     fn get_by_proto(&self, proto: Tag) -> Option<Vec<(uuid::Uuid, C)>> {
         let conns: Vec<(uuid::Uuid, C)> = self
-            .iter()
-            .filter(|(_, conn)| conn.get_proto().proto() == proto)
+            .by_proto(&proto)
             .map(|(conn_id, conn)| (*conn_id, conn.clone()))
             .collect();
 
@@ -181,8 +164,7 @@ where
     }
 
     fn get_last_wg_addr(&self) -> Option<IpAddrMask> {
-        self.iter()
-            .filter(|(_, conn)| conn.get_proto().proto() == Tag::Wireguard)
+        self.by_proto(&Tag::Wireguard)
             .max_by_key(|(_, conn)| {
                 conn.get_wireguard()
                     .and_then(|wg| match wg.address.address {
@@ -7,7 +7,6 @@ where
     S: Send + Sync + Clone + 'static + PartialEq,
 {
     fn find_by_id(&self, id: &uuid::Uuid) -> Option<&S>;
-    fn find_by_id_mut(&mut self, id: &uuid::Uuid) -> Option<&mut S>;
     fn find_by_refer_code(&self, code: &str) -> Option<&S>;
     fn all(&self) -> Vec<S>;
     fn add(&mut self, new_subscription: S) -> OperationStatus;
@@ -22,24 +21,19 @@ where
     S: SubscriptionOp + Send + Sync + Clone + 'static + PartialEq + SubscriptionOp,
 {
     fn count_invited_by(&self, refer_code: &str) -> usize {
-        self.values()
-            .filter(|s| s.referred_by() == Some(refer_code))
-            .count()
+        self.referrals(refer_code)
     }
 
     fn find_by_id(&self, id: &uuid::Uuid) -> Option<&S> {
         self.get(id)
     }
-    fn find_by_id_mut(&mut self, id: &uuid::Uuid) -> Option<&mut S> {
-        self.get_mut(id)
-    }
 
     fn find_by_refer_code(&self, code: &str) -> Option<&S> {
-        self.values().find(|s| s.refer_code() == code)
+        self.by_refer_code(code)
     }
 
     fn exist_refer_code(&self, code: &str) -> bool {
-        self.values().any(|s| s.refer_code() == code)
+        self.has_refer_code(code)
     }
 
     fn all(&self) -> Vec<S> {
@@ -49,14 +43,11 @@ where
     fn add(&mut self, new_subscription: S) -> OperationStatus {
         let id = new_subscription.id();
 
-        match self.get_mut(&id) {
-            Some(existing) => {
-                if existing == &new_subscription {
-                    OperationStatus::AlreadyExist(id)
-                } else {
-                    *existing = new_subscription;
-                    OperationStatus::Updated(id)
-                }
+        match self.get(&id) {
+            Some(existing) if existing == &new_subscription => OperationStatus::AlreadyExist(id),
+            Some(_) => {
+                self.insert(id, new_subscription);
+                OperationStatus::Updated(id)
             }
             None => {
                 self.insert(id, new_subscription);
@@ -66,7 +57,7 @@ where
     }
 
     fn delete(&mut self, id: &uuid::Uuid) {
-        self.remove(id);
+        self.take(id);
     }
 
     fn update(&mut self, subscription: S) {
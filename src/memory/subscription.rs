@@ -1,12 +1,11 @@
 use chrono::{DateTime, Utc};
 use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 use std::ops::Deref;
-use std::ops::DerefMut;
 
 use crate::utils::get_uuid_last_octet_simple;
 
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -91,21 +90,126 @@ impl From<tokio_postgres::Row> for Subscription {
     }
 }
 
-#[derive(
-    Archive, PartialEq, Deserialize, Serialize, RkyvDeserialize, RkyvSerialize, Debug, Clone,
-)]
+/// Subscriptions by id, refer codes are indexed the same way as connections
+#[derive(Archive, PartialEq, RkyvDeserialize, RkyvSerialize, Debug, Clone)]
 #[archive(check_bytes)]
-pub struct Subscriptions<S>(pub HashMap<uuid::Uuid, S>);
+pub struct Subscriptions<S> {
+    subs: HashMap<uuid::Uuid, S>,
+    /// Own refer code => subscriptions holding it, more than one is a conflict
+    refer_codes: HashMap<String, HashSet<uuid::Uuid>>,
+    /// Refer code => subscriptions invited with it
+    referrals: HashMap<String, HashSet<uuid::Uuid>>,
+}
 
 impl<S> Default for Subscriptions<S> {
     fn default() -> Self {
-        Subscriptions(HashMap::new())
+        Subscriptions {
+            subs: HashMap::new(),
+            refer_codes: HashMap::new(),
+            referrals: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Operations> From<HashMap<uuid::Uuid, S>> for Subscriptions<S> {
+    fn from(subs: HashMap<uuid::Uuid, S>) -> Self {
+        let mut subscriptions = Subscriptions::default();
+        for (id, sub) in &subs {
+            subscriptions.index(id, sub);
+        }
+        subscriptions.subs = subs;
+        subscriptions
+    }
+}
+
+impl<S: Operations> Subscriptions<S> {
+    /// Inserts or replaces the subscription, returning the previous one
+    pub fn insert(&mut self, id: uuid::Uuid, sub: S) -> Option<S> {
+        let prev = self.subs.remove(&id);
+        if let Some(prev) = &prev {
+            self.unindex(&id, prev);
+        }
+        self.index(&id, &sub);
+        self.subs.insert(id, sub);
+        prev
+    }
+
+    pub fn take(&mut self, id: &uuid::Uuid) -> Option<S> {
+        let sub = self.subs.remove(id)?;
+        self.unindex(id, &sub);
+        Some(sub)
+    }
+
+    /// None for a code held by several subscriptions, the referrer can't be told
+    pub fn by_refer_code(&self, code: &str) -> Option<&S> {
+        match self.refer_codes.get(code) {
+            Some(ids) if ids.len() == 1 => ids.iter().next().and_then(|id| self.subs.get(id)),
+            _ => None,
+        }
+    }
+
+    pub fn has_refer_code(&self, code: &str) -> bool {
+        self.refer_codes.contains_key(code)
+    }
+
+    pub fn referrals(&self, refer_code: &str) -> usize {
+        self.referrals.get(refer_code).map_or(0, HashSet::len)
+    }
+
+    fn index(&mut self, id: &uuid::Uuid, sub: &S) {
+        let holders = self.refer_codes.entry(sub.refer_code()).or_default();
+        holders.insert(*id);
+        if holders.len() > 1 {
+            tracing::warn!(
+                "Refer code {} is held by {} subscriptions",
+                sub.refer_code(),
+                holders.len()
+            );
+        }
+        if let Some(code) = sub.referred_by() {
+            self.referrals
+                .entry(code.to_string())
+                .or_default()
+                .insert(*id);
+        }
+    }
+
+    fn unindex(&mut self, id: &uuid::Uuid, sub: &S) {
+        let code = sub.refer_code();
+        if let Some(ids) = self.refer_codes.get_mut(&code) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.refer_codes.remove(&code);
+            }
+        }
+        if let Some(code) = sub.referred_by() {
+            if let Some(ids) = self.referrals.get_mut(code) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.referrals.remove(code);
+                }
+            }
+        }
+    }
+}
+
+/// Only the map is serialized, indexes are rebuilt when read back
+impl<S: Serialize> Serialize for Subscriptions<S> {
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        self.subs.serialize(serializer)
+    }
+}
+
+impl<'de, S: Deserialize<'de> + Operations> Deserialize<'de> for Subscriptions<S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let subs: HashMap<uuid::Uuid, S> = Deserialize::deserialize(deserializer)?;
+        Ok(Subscriptions::from(subs))
     }
 }
 
 impl<S: fmt::Display> fmt::Display for Subscriptions<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (id, sub) in &self.0 {
+        for (id, sub) in &self.subs {
             writeln!(f, "{} => {}", id, sub)?;
         }
         Ok(())
@@ -116,13 +220,7 @@ impl<S> Deref for Subscriptions<S> {
     type Target = HashMap<uuid::Uuid, S>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl<S> DerefMut for Subscriptions<S> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &self.subs
     }
 }
 
@@ -240,3 +338,51 @@ impl Operations for Subscription {
         self.downlink_bytes = Some(bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refer_code_index_follows_updates() {
+        let mut subs: Subscriptions<Subscription> = Subscriptions::default();
+        let referrer = Subscription::default();
+        let code = referrer.refer_code();
+        subs.insert(referrer.id, referrer.clone());
+
+        let mut invited = Subscription::default();
+        invited.set_referred_by(code.clone());
+        subs.insert(invited.id, invited.clone());
+        assert_eq!(subs.by_refer_code(&code).map(|s| s.id), Some(referrer.id));
+        assert_eq!(subs.referrals(&code), 1);
+
+        invited.set_refer_code("renamed".into());
+        subs.insert(invited.id, invited.clone());
+        assert_eq!(
+            subs.by_refer_code("renamed").map(|s| s.id),
+            Some(invited.id)
+        );
+        assert_eq!(subs.referrals(&code), 1);
+
+        subs.take(&invited.id);
+        assert!(subs.by_refer_code("renamed").is_none());
+        assert_eq!(subs.referrals(&code), 0);
+    }
+
+    #[test]
+    fn test_shared_refer_code_is_not_overwritten() {
+        let mut subs: Subscriptions<Subscription> = Subscriptions::default();
+        let mut first = Subscription::default();
+        first.set_refer_code("shared".into());
+        let mut second = Subscription::default();
+        second.set_refer_code("shared".into());
+        subs.insert(first.id, first.clone());
+        subs.insert(second.id, second.clone());
+
+        assert!(subs.has_refer_code("shared"));
+        assert!(subs.by_refer_code("shared").is_none());
+
+        subs.take(&second.id);
+        assert_eq!(subs.by_refer_code("shared").map(|s| s.id), Some(first.id));
+    }
+}
@@ -20,7 +20,7 @@ use tokio_postgres::types::ToSql;
     ToSql,
     FromSql,
 )]
-#[archive_attr(derive(Clone, Debug))]
+#[archive_attr(derive(Clone, Debug, PartialEq, Eq, Hash))]
 #[archive(check_bytes)]
 #[postgres(name = "proto", rename_all = "snake_case")]
 pub enum ProtoTag {
@@ -71,6 +71,17 @@ impl ProtoTag {
     pub fn is_mtproto(&self) -> bool {
         *self == ProtoTag::Mtproto
     }
+    /// Served by Xray, Shadowsocks included
+    pub fn is_xray(&self) -> bool {
+        matches!(
+            self,
+            ProtoTag::VlessTcpReality
+                | ProtoTag::VlessGrpcReality
+                | ProtoTag::VlessXhttpReality
+                | ProtoTag::Vmess
+                | ProtoTag::Shadowsocks
+        )
+    }
 }
 
 impl std::str::FromStr for ProtoTag {
@@ -7,6 +7,7 @@ use sysinfo::{CpuRefreshKind, MemoryRefreshKind, RefreshKind, System};
 use super::storage::HasMetrics;
 use super::storage::MetricSink;
 use super::Metrics;
+use crate::zmq::subscriber::Subscriber;
 
 static BEAT_INDEX: OnceLock<AtomicUsize> = OnceLock::new();
 
@@ -189,4 +190,42 @@ where
             );
         }
     }
+
+    async fn bus(&self, subscriber: &Subscriber) {
+        let node = self.node_settings();
+        let tags = node.get_base_tags();
+        let node_uuid = node.uuid;
+
+        let sockets = [
+            ("sub", subscriber.stats()),
+            ("pub", self.metrics().publisher.stats()),
+        ];
+
+        let queue = [
+            ("pending_batches", self.metrics().pending_len() as u64),
+            (
+                "dropped_batches",
+                self.metrics().dropped.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, value) in queue {
+            self.metrics().write(
+                &node_uuid,
+                &format!("metrics.{}", name),
+                value as f64,
+                tags.clone(),
+            );
+        }
+
+        for (socket, stats) in sockets {
+            for (name, value) in stats.snapshot() {
+                self.metrics().write(
+                    &node_uuid,
+                    &format!("zmq.{}.{}", socket, name),
+                    value as f64,
+                    tags.clone(),
+                );
+            }
+        }
+    }
 }
@@ -2,7 +2,10 @@ use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
+use crate::zmq::subscriber::Subscriber;
+
 pub(crate) mod impls;
+pub(crate) mod prometheus;
 pub(crate) mod storage;
 
 #[derive(Archive, RkyvSerialize, RkyvDeserialize, Serialize, Deserialize, Clone, Debug)]
@@ -30,4 +33,5 @@ pub trait Metrics {
     fn cpu_usage(&self) -> impl std::future::Future<Output = ()> + Send;
     fn loadavg(&self) -> impl std::future::Future<Output = ()> + Send;
     fn disk_usage(&self) -> impl std::future::Future<Output = ()> + Send;
+    fn bus(&self, subscriber: &Subscriber) -> impl std::future::Future<Output = ()> + Send;
 }
@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use warp::Filter;
+
+use super::storage::MetricStorage;
+use crate::config::settings::PrometheusConfig;
+
+const NAMESPACE: &str = "fcore";
+
+/// Tags that make a series per user, these are counted against the cap
+const USER_TAGS: [&str; 2] = ["user_id", "conn_id"];
+
+/// Cumulative stats, exported as counters rather than gauges
+const COUNTER_SUFFIXES: [&str; 11] = [
+    ".downlink",
+    ".uplink",
+    ".sent",
+    ".sent_bytes",
+    ".send_errors",
+    ".received",
+    ".received_bytes",
+    ".recv_errors",
+    ".accepted",
+    ".rejected",
+    ".dropped_batches",
+];
+
+/// Series not updated within this many collections are evicted
+const STALE_COLLECTIONS: u64 = 3;
+
+type Labels = BTreeMap<String, String>;
+type SeriesKey = (String, Labels);
+
+struct Sample {
+    value: f64,
+    counter: bool,
+    collection: u64,
+}
+
+/// Latest value of every series, rendered in the Prometheus text format
+pub struct PromRegistry {
+    series: parking_lot::RwLock<HashMap<SeriesKey, Sample>>,
+    collection: AtomicU64,
+    user_series: AtomicU64,
+    max_user_series: u64,
+    dropped: AtomicU64,
+}
+
+impl PromRegistry {
+    pub fn new(max_user_series: usize) -> Self {
+        Self {
+            series: parking_lot::RwLock::new(HashMap::new()),
+            collection: AtomicU64::new(0),
+            user_series: AtomicU64::new(0),
+            max_user_series: max_user_series as u64,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, name: &str, value: f64, tags: &BTreeMap<String, String>) {
+        let counter = COUNTER_SUFFIXES.iter().any(|suffix| name.ends_with(suffix));
+        let collection = self.collection.load(Ordering::Relaxed);
+        let name = sanitize_name(name);
+        let labels: Labels = tags
+            .iter()
+            .map(|(k, v)| (sanitize_label(k), v.clone()))
+            .collect();
+
+        let mut series = self.series.write();
+        let key = (name, labels);
+        if let Some(current) = series.get_mut(&key) {
+            current.value = value;
+            current.collection = collection;
+            return;
+        }
+
+        if is_user_series(&key) {
+            if self.user_series.load(Ordering::Relaxed) >= self.max_user_series {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            self.user_series.fetch_add(1, Ordering::Relaxed);
+        }
+
+        series.insert(
+            key,
+            Sample {
+                value,
+                counter,
+                collection,
+            },
+        );
+    }
+
+    /// Closes a collection round, evicting series that stopped reporting
+    pub fn sweep(&self) {
+        let collection = self.collection.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut series = self.series.write();
+        series.retain(|key, sample| {
+            if collection - sample.collection <= STALE_COLLECTIONS {
+                return true;
+            }
+            if is_user_series(key) {
+                self.user_series.fetch_sub(1, Ordering::Relaxed);
+            }
+            false
+        });
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let series = self.series.read();
+        let mut by_name: BTreeMap<&str, Vec<(&Labels, &Sample)>> = BTreeMap::new();
+        for ((name, labels), sample) in series.iter() {
+            by_name.entry(name).or_default().push((labels, sample));
+        }
+
+        let mut out = String::new();
+        for (name, samples) in by_name {
+            let kind = if samples[0].1.counter {
+                "counter"
+            } else {
+                "gauge"
+            };
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, sample) in samples {
+                let _ = writeln!(out, "{}{} {}", name, render_labels(labels), sample.value);
+            }
+        }
+
+        let dropped = format!("{}_prometheus_dropped_series_total", NAMESPACE);
+        let _ = writeln!(out, "# TYPE {} counter", dropped);
+        let _ = writeln!(out, "{} {}", dropped, self.dropped());
+        out
+    }
+}
+
+impl MetricStorage {
+    /// Latest point of every stored series, labelled with the reporting node
+    pub fn render_prometheus(&self, max_user_series: usize) -> String {
+        let registry = PromRegistry::new(max_user_series);
+        for node_ref in self.inner.iter() {
+            let node_id = node_ref.key().to_string();
+            for series in node_ref.value().iter() {
+                let (Some(point), Some(meta)) = (series.back(), self.metadata.get(series.key()))
+                else {
+                    continue;
+                };
+                let mut tags = meta.1.clone();
+                tags.insert("node_id".to_string(), node_id.clone());
+                registry.record(&meta.0, point.value, &tags);
+            }
+        }
+        registry.render()
+    }
+}
+
+/// Serves `GET /metrics` with whatever `render` returns
+pub async fn serve<F>(config: PrometheusConfig, render: F)
+where
+    F: Fn() -> String + Clone + Send + Sync + 'static,
+{
+    let route = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .map(move || {
+            warp::reply::with_header(
+                render(),
+                "Content-Type",
+                "text/plain; version=0.0.4; charset=utf-8",
+            )
+        });
+
+    let addr: SocketAddr = config.listen;
+    tracing::info!("Prometheus exporter listening on {}", addr);
+    warp::serve(route).run(addr).await;
+}
+
+fn is_user_series(key: &SeriesKey) -> bool {
+    USER_TAGS.iter().any(|tag| key.1.contains_key(*tag))
+}
+
+fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}_{}", NAMESPACE, name)
+}
+
+fn sanitize_label(label: &str) -> String {
+    let label: String = label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if label.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", label)
+    } else {
+        label
+    }
+}
+
+fn render_labels(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_and_user_series_cap() {
+        let registry = PromRegistry::new(1);
+        let base = BTreeMap::from([("env".to_string(), "dev \"1\"".to_string())]);
+        registry.record("sys.mem_used", 42.0, &base);
+        registry.record("sys.mem_used", 43.0, &base);
+
+        let mut user = base.clone();
+        user.insert("user_id".to_string(), "a".to_string());
+        registry.record("user.online", 1.0, &user);
+        user.insert("user_id".to_string(), "b".to_string());
+        registry.record("user.online", 1.0, &user);
+
+        let out = registry.render();
+        assert!(out.contains("# TYPE fcore_sys_mem_used gauge"));
+        assert!(out.contains("fcore_sys_mem_used{env=\"dev \\\"1\\\"\"} 43"));
+        assert!(out.contains("user_id=\"a\""));
+        assert!(!out.contains("user_id=\"b\""));
+        assert_eq!(registry.dropped(), 1);
+    }
+
+    #[test]
+    fn test_sweep_evicts_stale_series_and_frees_cap() {
+        let registry = PromRegistry::new(1);
+        let a = BTreeMap::from([("conn_id".to_string(), "a".to_string())]);
+        let b = BTreeMap::from([("conn_id".to_string(), "b".to_string())]);
+        registry.record("user.traffic.downlink", 10.0, &a);
+        assert!(registry
+            .render()
+            .contains("# TYPE fcore_user_traffic_downlink counter"));
+
+        for _ in 0..=STALE_COLLECTIONS {
+            registry.sweep();
+        }
+        registry.record("user.traffic.downlink", 5.0, &b);
+
+        let out = registry.render();
+        assert!(!out.contains("conn_id=\"a\""));
+        assert!(out.contains("conn_id=\"b\""));
+        assert_eq!(registry.dropped(), 0);
+    }
+}
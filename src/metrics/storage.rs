@@ -1,10 +1,24 @@
 use dashmap::DashMap;
+use rkyv::AlignedVec;
 use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::fs as async_fs;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{sleep, Duration};
 
+use super::prometheus::PromRegistry;
 use super::{MetricEnvelope, MetricPoint};
+use crate::config::settings::MetricsTxConfig;
+use crate::error::Result;
 use crate::memory::node::Node;
 use crate::zmq::{publisher::Publisher, topic::Topic};
 
+/// PUB drops silently at the high-water mark (1000), so batches are sent in
+/// chunks well below it with a pause for the socket to drain in between
+const SEND_CHUNK: usize = 100;
+const SEND_PAUSE: Duration = Duration::from_millis(100);
+
 pub trait HasMetrics {
     fn metrics(&self) -> &MetricBuffer;
     fn node_settings(&self) -> &Node;
@@ -22,6 +36,9 @@ impl MetricSink for MetricBuffer {
         value: f64,
         tags: BTreeMap<String, String>,
     ) {
+        if let Some(registry) = &self.prometheus {
+            registry.record(metric, value, &tags);
+        }
         let mut b = self.batch.lock();
         b.push(MetricEnvelope {
             node_id: *node_id,
@@ -36,9 +53,33 @@ impl MetricSink for MetricBuffer {
 pub struct MetricBuffer {
     pub batch: parking_lot::Mutex<Vec<MetricEnvelope>>,
     pub publisher: Publisher,
+    pub pending: parking_lot::Mutex<VecDeque<Vec<u8>>>,
+    pub retry_batches: usize,
+    pub spill_path: Option<PathBuf>,
+    pub spill_max_bytes: u64,
+    pub dropped: AtomicU64,
+    pub prometheus: Option<std::sync::Arc<PromRegistry>>,
+    spill_repaired: AtomicBool,
 }
 
 impl MetricBuffer {
+    pub fn new(publisher: Publisher, config: &MetricsTxConfig) -> Self {
+        Self {
+            batch: parking_lot::Mutex::new(Vec::new()),
+            publisher,
+            pending: parking_lot::Mutex::new(VecDeque::new()),
+            retry_batches: config.retry_batches,
+            spill_path: config.spill_path.as_ref().map(PathBuf::from),
+            spill_max_bytes: config.spill_max_bytes,
+            dropped: AtomicU64::new(0),
+            spill_repaired: AtomicBool::new(false),
+            prometheus: config
+                .prometheus
+                .as_ref()
+                .map(|p| std::sync::Arc::new(PromRegistry::new(p.max_user_series))),
+        }
+    }
+
     pub fn push(
         &self,
         node_id: uuid::Uuid,
@@ -46,6 +87,9 @@ impl MetricBuffer {
         value: f64,
         tags: BTreeMap<String, String>,
     ) {
+        if let Some(registry) = &self.prometheus {
+            registry.record(name, value, &tags);
+        }
         let mut b = self.batch.lock();
         b.push(MetricEnvelope {
             node_id,
@@ -56,25 +100,255 @@ impl MetricBuffer {
         });
     }
 
+    pub fn pending_len(&self) -> usize {
+        self.pending.lock().len()
+    }
+
     pub async fn flush_to_zmq(&self) {
-        let metrics = {
-            let mut batch = self.batch.lock();
-            if batch.is_empty() {
-                return;
+        let metrics = std::mem::take(&mut *self.batch.lock());
+
+        if !metrics.is_empty() {
+            match rkyv::to_bytes::<_, 65536>(&metrics) {
+                Ok(bytes) => self.pending.lock().push_back(bytes.into_vec()),
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to serialize batch, {} metrics dropped: {}",
+                        metrics.len(),
+                        e
+                    );
+                }
             }
-            std::mem::take(&mut *batch)
+        }
+
+        if self.publisher.is_connected() {
+            match self.replay_spill().await {
+                Ok(true) => self.send_pending().await,
+                Ok(false) => {}
+                Err(e) => tracing::error!("Metrics spill replay failed: {}", e),
+            }
+        } else {
+            tracing::debug!(
+                "Metrics collector is unreachable, {} batches pending",
+                self.pending_len()
+            );
+        }
+
+        if let Err(e) = self.spill_overflow().await {
+            tracing::error!("Metrics spill failed: {}", e);
+        }
+    }
+
+    async fn send_pending(&self) {
+        let mut sent = 0;
+        loop {
+            if sent > 0 && sent % SEND_CHUNK == 0 && !self.pause_between_chunks().await {
+                break;
+            }
+
+            let next = self.pending.lock().pop_front();
+            let Some(bytes) = next else {
+                break;
+            };
+
+            if let Err(e) = self
+                .publisher
+                .send_binary(&Topic::Metrics, bytes.as_slice())
+                .await
+            {
+                tracing::error!("Batch publish failed, will retry: {}", e);
+                self.pending.lock().push_front(bytes);
+                break;
+            }
+            sent += 1;
+        }
+    }
+
+    /// False when the collector went away and sending should stop
+    async fn pause_between_chunks(&self) -> bool {
+        sleep(SEND_PAUSE).await;
+        self.publisher.is_connected()
+    }
+
+    async fn spill_overflow(&self) -> Result<()> {
+        let overflow: Vec<Vec<u8>> = {
+            let mut pending = self.pending.lock();
+            let n = pending.len().saturating_sub(self.retry_batches);
+            pending.drain(..n).collect()
         };
 
-        let bytes = rkyv::to_bytes::<_, 65536>(&metrics).expect("Failed to serialize batch");
+        if overflow.is_empty() {
+            return Ok(());
+        }
 
-        if let Err(e) = &self
-            .publisher
-            .send_binary(&Topic::Metrics, bytes.as_slice())
-            .await
-        {
-            tracing::error!("Batch publish failed: {}", e);
+        let Some(path) = &self.spill_path else {
+            self.dropped
+                .fetch_add(overflow.len() as u64, Ordering::Relaxed);
+            tracing::warn!(
+                "Metrics retry queue is full, {} batches dropped",
+                overflow.len()
+            );
+            return Ok(());
+        };
+
+        let size = match async_fs::metadata(path).await {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        };
+        let records = encode_spill_records(&overflow);
+
+        if size + records.len() as u64 > self.spill_max_bytes {
+            self.dropped
+                .fetch_add(overflow.len() as u64, Ordering::Relaxed);
+            tracing::warn!(
+                "Metrics spill file {:?} is full, {} batches dropped",
+                path,
+                overflow.len()
+            );
+            return Ok(());
+        }
+
+        if !self.spill_repaired.swap(true, Ordering::Relaxed) {
+            repair_spill_file(path).await?;
+        }
+        append_spill_file(path, &records).await?;
+
+        tracing::info!("Spilled {} metric batches to {:?}", overflow.len(), path);
+        Ok(())
+    }
+
+    /// Returns true when nothing is left in the spill file
+    async fn replay_spill(&self) -> Result<bool> {
+        let Some(path) = &self.spill_path else {
+            return Ok(true);
+        };
+        if !path.exists() {
+            return Ok(true);
         }
+
+        let records = decode_spill_records(&async_fs::read(path).await?);
+        tracing::info!("Replaying {} spilled metric batches", records.len());
+
+        for (i, record) in records.iter().enumerate() {
+            // Records already handed to the socket are cut, the rest stays spilled
+            if i > 0 && i % SEND_CHUNK == 0 && !self.pause_between_chunks().await {
+                tracing::warn!(
+                    "Collector went away, {} spilled batches left",
+                    records.len() - i
+                );
+                rewrite_spill_file(path, &records[i..]).await?;
+                return Ok(false);
+            }
+
+            let mut aligned = AlignedVec::new();
+            aligned.extend_from_slice(record);
+            if rkyv::check_archived_root::<Vec<MetricEnvelope>>(&aligned).is_err() {
+                tracing::warn!("Skipping corrupted spilled metric batch");
+                continue;
+            }
+
+            if let Err(e) = self
+                .publisher
+                .send_binary(&Topic::Metrics, record.as_slice())
+                .await
+            {
+                tracing::error!("Spilled batch publish failed, will retry: {}", e);
+                rewrite_spill_file(path, &records[i..]).await?;
+                return Ok(false);
+            }
+        }
+
+        async_fs::remove_file(path).await?;
+        Ok(true)
+    }
+}
+
+/// Spill file is a sequence of `u32 LE length | rkyv batch` records
+pub fn encode_spill_records(batches: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(batches.iter().map(|b| b.len() + 4).sum());
+    for batch in batches {
+        buf.extend_from_slice(&(batch.len() as u32).to_le_bytes());
+        buf.extend_from_slice(batch);
     }
+    buf
+}
+
+/// Length of the prefix holding only complete records
+pub fn complete_spill_len(buf: &[u8]) -> usize {
+    let mut pos = 0;
+    while let Some(prefix) = buf.get(pos..pos + 4) {
+        let len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+        if buf.len() < pos + 4 + len {
+            break;
+        }
+        pos += 4 + len;
+    }
+    pos
+}
+
+/// Cuts a torn record left by a crash, otherwise appends would land after it
+/// and its length prefix would swallow them
+pub async fn repair_spill_file(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let bytes = async_fs::read(path).await?;
+    let valid = complete_spill_len(&bytes);
+    if valid < bytes.len() {
+        tracing::warn!(
+            "Cutting {} bytes of a torn record from {:?}",
+            bytes.len() - valid,
+            path
+        );
+        let file = async_fs::OpenOptions::new().write(true).open(path).await?;
+        file.set_len(valid as u64).await?;
+        file.sync_data().await?;
+    }
+    Ok(())
+}
+
+/// Appends records with one fsync, a failed write is rolled back to the old length
+pub async fn append_spill_file(path: &Path, records: &[u8]) -> Result<()> {
+    let mut file = async_fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let size = file.metadata().await?.len();
+
+    let written = async {
+        file.write_all(records).await?;
+        file.sync_data().await
+    }
+    .await;
+
+    if let Err(e) = written {
+        let _ = file.set_len(size).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+async fn rewrite_spill_file(path: &Path, records: &[Vec<u8>]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    async_fs::write(&tmp, encode_spill_records(records)).await?;
+    async_fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// Torn tail record (crash in the middle of append) is ignored
+pub fn decode_spill_records(mut buf: &[u8]) -> Vec<Vec<u8>> {
+    let mut records = vec![];
+    while buf.len() >= 4 {
+        let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let Some(record) = buf.get(4..4 + len) else {
+            tracing::warn!("Truncated record at the end of metrics spill file");
+            break;
+        };
+        records.push(record.to_vec());
+        buf = &buf[4 + len..];
+    }
+    records
 }
 
 pub struct MetricStorage {
@@ -134,6 +408,15 @@ impl MetricStorage {
             }
         }
     }
+    /// Timestamp of the newest point from the node in any series
+    pub fn last_seen(&self, node_id: &uuid::Uuid) -> Option<i64> {
+        self.inner
+            .get(node_id)?
+            .iter()
+            .filter_map(|series| series.back().map(|p| p.timestamp))
+            .max()
+    }
+
     fn make_series_key(name: &str, tags: &BTreeMap<String, String>) -> u64 {
         use std::collections::hash_map::DefaultHasher;
         use std::hash::{Hash, Hasher};
@@ -205,6 +488,40 @@ impl MetricStorage {
         result
     }
 
+    /// Latest point of `metric_name` per node among series tagged `tag_key=tag_value`
+    pub fn latest_by_tag(
+        &self,
+        tag_key: &str,
+        tag_value: &str,
+        metric_name: &str,
+    ) -> BTreeMap<uuid::Uuid, MetricPoint> {
+        let hashes: Vec<u64> = self
+            .find_series_by_tag(tag_key, tag_value)
+            .into_iter()
+            .filter(|hash| {
+                self.metadata
+                    .get(hash)
+                    .is_some_and(|meta| meta.0 == metric_name)
+            })
+            .collect();
+
+        let mut result: BTreeMap<uuid::Uuid, MetricPoint> = BTreeMap::new();
+        for node_ref in self.inner.iter() {
+            for hash in &hashes {
+                let Some(point) = node_ref.value().get(hash).and_then(|d| d.back().cloned()) else {
+                    continue;
+                };
+                let newer = result
+                    .get(node_ref.key())
+                    .is_none_or(|p| p.timestamp < point.timestamp);
+                if newer {
+                    result.insert(*node_ref.key(), point);
+                }
+            }
+        }
+        result
+    }
+
     pub fn perform_gc(&self) {
         let now_ms = chrono::Utc::now().timestamp_millis();
         let retention_ms = self.retention_seconds * 1000;
@@ -246,3 +563,64 @@ impl MetricStorage {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_records_roundtrip() {
+        let batches = vec![vec![1u8, 2, 3], vec![], vec![42u8; 300]];
+        let encoded = encode_spill_records(&batches);
+        assert_eq!(decode_spill_records(&encoded), batches);
+    }
+
+    #[test]
+    fn test_spill_records_torn_tail() {
+        let batches = vec![vec![1u8, 2, 3], vec![4u8, 5, 6]];
+        let mut encoded = encode_spill_records(&batches);
+        encoded.truncate(encoded.len() - 1);
+        assert_eq!(decode_spill_records(&encoded), vec![vec![1u8, 2, 3]]);
+    }
+
+    #[tokio::test]
+    async fn test_append_after_torn_tail() {
+        let path = std::env::temp_dir().join(format!("spill-{}", uuid::Uuid::new_v4()));
+        let mut torn = encode_spill_records(&[vec![1u8, 2, 3], vec![4u8; 10]]);
+        torn.truncate(torn.len() - 4);
+        std::fs::write(&path, &torn).unwrap();
+
+        repair_spill_file(&path).await.unwrap();
+        append_spill_file(&path, &encode_spill_records(&[vec![7u8, 8]]))
+            .await
+            .unwrap();
+
+        let records = decode_spill_records(&std::fs::read(&path).unwrap());
+        assert_eq!(records, vec![vec![1u8, 2, 3], vec![7u8, 8]]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_latest_by_tag() {
+        let storage = MetricStorage::new(100, 3600);
+        let node = uuid::Uuid::new_v4();
+        let now = chrono::Utc::now().timestamp_millis();
+        let tags = BTreeMap::from([("user_id".to_string(), "u1".to_string())]);
+
+        for (ts, value) in [(now - 2000, 1.0), (now - 1000, 0.0)] {
+            storage.insert_envelope(MetricEnvelope {
+                node_id: node,
+                name: "user.online".into(),
+                value,
+                timestamp: ts,
+                tags: tags.clone(),
+            });
+        }
+
+        let latest = storage.latest_by_tag("user_id", "u1", "user.online");
+        assert_eq!(latest.get(&node).map(|p| p.value), Some(0.0));
+        assert!(storage
+            .latest_by_tag("user_id", "u1", "user.last_handshake")
+            .is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use reqwest::{Client, Method, RequestBuilder, Url};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::error::{Error, Result};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes counted by Hysteria2 per user id, tx is server to client
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+pub struct TrafficStat {
+    pub tx: u64,
+    pub rx: u64,
+}
+
+/// Client of the Hysteria2 traffic stats API (`trafficStats` section of the server config)
+#[derive(Clone)]
+pub struct Hysteria2Client {
+    client: Client,
+    endpoint: Url,
+    secret: Option<String>,
+}
+
+impl Hysteria2Client {
+    pub fn new(endpoint: &str, secret: Option<String>) -> Result<Self> {
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+        Ok(Self {
+            client,
+            endpoint: Url::parse(endpoint)?,
+            secret,
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder> {
+        let url = self.endpoint.join(path)?;
+        let req = self.client.request(method, url);
+        Ok(match &self.secret {
+            Some(secret) => req.header("Authorization", secret),
+            None => req,
+        })
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<T> {
+        let res = self.request(Method::GET, path)?.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(Error::Custom(format!(
+                "Hysteria2 GET {}: status {}",
+                path, status
+            )));
+        }
+        Ok(res.json().await?)
+    }
+
+    /// Cumulative traffic per user id since the user was first seen
+    pub async fn traffic(&self) -> Result<HashMap<String, TrafficStat>> {
+        self.get("/traffic").await
+    }
+
+    /// Number of connected clients per user id, offline users are absent
+    pub async fn online(&self) -> Result<HashMap<String, u64>> {
+        self.get("/online").await
+    }
+
+    /// Drops all sessions of the given user ids
+    pub async fn kick(&self, ids: &[String]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let res = self
+            .request(Method::POST, "/kick")?
+            .json(ids)
+            .send()
+            .await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(Error::Custom(format!("Hysteria2 kick: status {}", status)));
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+pub(crate) mod hysteria2;
 #[cfg(feature = "wireguard")]
 pub(crate) mod wireguard;
 #[cfg(feature = "xray")]
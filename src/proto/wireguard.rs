@@ -1,9 +1,9 @@
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use std::collections::HashSet;
-use std::net::IpAddr;
-use std::net::Ipv4Addr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use defguard_wireguard_rs::host::Peer;
 use defguard_wireguard_rs::key::Key;
@@ -22,6 +22,47 @@ use crate::error::{Error, Result};
 
 const KEY_LEN: usize = 32;
 
+/// WireGuard rekeys every 2 minutes on an active session, so a handshake
+/// older than this means the peer is gone
+pub const WG_ONLINE_THRESHOLD: Duration = Duration::from_secs(180);
+
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub last_handshake: Option<SystemTime>,
+    pub endpoint: Option<SocketAddr>,
+}
+
+impl PeerInfo {
+    /// Unix seconds of the last handshake, None if the peer never connected
+    pub fn last_handshake_secs(&self) -> Option<u64> {
+        self.last_handshake
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .filter(|secs| *secs > 0)
+    }
+
+    pub fn is_online(&self, now: SystemTime) -> bool {
+        self.last_handshake_secs().is_some()
+            && self
+                .last_handshake
+                .and_then(|t| now.duration_since(t).ok())
+                .is_some_and(|age| age <= WG_ONLINE_THRESHOLD)
+    }
+}
+
+impl From<&Peer> for PeerInfo {
+    fn from(peer: &Peer) -> Self {
+        Self {
+            rx_bytes: peer.rx_bytes,
+            tx_bytes: peer.tx_bytes,
+            last_handshake: peer.last_handshake,
+            endpoint: peer.endpoint,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct WgApi {
     pub client: Arc<InnerWgApi>,
@@ -64,14 +105,17 @@ impl WgApi {
         Ok(Key::new(bytes.as_slice().try_into().unwrap()))
     }
 
-    pub fn create(&self, pubkey: &str, ip: IpAddrMask) -> Result<()> {
-        let ip = defguard_wireguard_rs::net::IpAddrMask {
-            ip: ip.address,
-            cidr: ip.cidr,
-        };
+    pub fn create(&self, pubkey: &str, ips: &[IpAddrMask]) -> Result<()> {
+        let ips = ips
+            .iter()
+            .map(|ip| defguard_wireguard_rs::net::IpAddrMask {
+                ip: ip.address,
+                cidr: ip.cidr,
+            })
+            .collect();
         let key = Self::decode_pubkey(pubkey)?;
         let mut peer = Peer::new(key);
-        peer.set_allowed_ips(vec![ip]);
+        peer.set_allowed_ips(ips);
         self.client.configure_peer(&peer)?;
         Ok(())
     }
@@ -102,52 +146,55 @@ impl WgApi {
         Ok(last_ip)
     }
 
+    /// First host of `network` not taken by a peer or the interface address
     pub fn next_available_ip_mask(
         &self,
         network: &IpAddrMask,
-        address: &Ipv4Addr,
+        address: &IpAddr,
     ) -> Result<IpAddrMask> {
-        let IpAddrMask {
-            address: IpAddr::V4(base_ip),
-            cidr,
-        } = network
-        else {
-            return Err(Error::Custom("Only IPv4 networks are supported".into()));
+        let (bits, base, own) = match (network.address, address) {
+            (IpAddr::V4(base), IpAddr::V4(own)) => {
+                (32, u32::from(base) as u128, u32::from(*own) as u128)
+            }
+            (IpAddr::V6(base), IpAddr::V6(own)) => (128, u128::from(base), u128::from(*own)),
+            _ => {
+                return Err(Error::Custom(
+                    "Network and interface address families differ".into(),
+                ))
+            }
         };
 
-        let interface_ip = u32::from_be_bytes(address.octets());
-        let base_ip_u32 = u32::from_be_bytes(base_ip.octets());
-        let netmask = !((1u32 << (32 - cidr)) - 1);
-        let network_addr = base_ip_u32 & netmask;
-        let broadcast_addr = network_addr | !netmask;
+        let host_bits = bits - network.cidr as u32;
+        let host_mask = if host_bits >= 128 {
+            u128::MAX
+        } else {
+            (1u128 << host_bits) - 1
+        };
+        let network_addr = base & !host_mask;
+        let last_addr = network_addr | host_mask;
+        let to_ip = |v: u128| match network.address {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(v as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(v)),
+        };
 
         let data = self.client.read_interface_data()?;
-        let mut used: HashSet<u32> = data
+        let mut used: HashSet<IpAddr> = data
             .peers
             .values()
             .flat_map(|peer| &peer.allowed_ips)
-            .filter_map(|ip_mask| match ip_mask {
-                defguard_wireguard_rs::net::IpAddrMask {
-                    ip: IpAddr::V4(addr),
-                    cidr: 32,
-                } => Some(u32::from_be_bytes(addr.octets())),
-                _ => None,
-            })
+            .filter(|ip_mask| ip_mask.cidr as u32 == bits)
+            .map(|ip_mask| ip_mask.ip)
             .collect();
+        used.insert(to_ip(own));
 
-        used.insert(interface_ip);
-
-        for ip_u32 in (network_addr + 1)..(broadcast_addr - 1) {
-            if !used.contains(&ip_u32) {
-                let next_ip = Ipv4Addr::from(ip_u32);
-                return Ok(IpAddrMask {
-                    address: IpAddr::V4(next_ip),
-                    cidr: 32,
-                });
-            }
-        }
+        // IPv4 broadcast is not a host, IPv6 has none
+        let last_host = if bits == 32 { last_addr - 1 } else { last_addr };
 
-        Err(Error::Custom("No available IPs in the subnet".into()))
+        (network_addr + 1..=last_host)
+            .map(to_ip)
+            .find(|ip| !used.contains(ip))
+            .map(IpAddrMask::host)
+            .ok_or_else(|| Error::Custom("No available IPs in the subnet".into()))
     }
 
     pub fn peer_stats(&self, pubkey: &str) -> Result<(i64, i64)> {
@@ -160,6 +207,22 @@ impl WgApi {
         Ok((peer.rx_bytes as i64, peer.tx_bytes as i64))
     }
 
+    /// Stats of all peers keyed by base64 pubkey, read in a single call
+    pub fn peers_info(&self) -> Result<HashMap<String, PeerInfo>> {
+        let data = self.client.read_interface_data()?;
+        Ok(data
+            .peers
+            .iter()
+            .map(|(key, peer)| (key.to_string(), PeerInfo::from(peer)))
+            .collect())
+    }
+
+    /// Base64 pubkeys of all peers configured on the interface
+    pub fn peers(&self) -> Result<Vec<String>> {
+        let data = self.client.read_interface_data()?;
+        Ok(data.peers.keys().map(|key| key.to_string()).collect())
+    }
+
     pub fn is_exist(&self, pubkey: String) -> bool {
         Self::decode_pubkey(&pubkey)
             .ok()
@@ -231,6 +231,8 @@ pub trait ConnOp {
         user_id: String,
     ) -> Result<GetInboundUserResponse, Status>;
     async fn conn_count_op(&mut self, tag: Tag) -> Result<i64, Status>;
+    async fn conn_ids_op(&mut self, tag: Tag) -> Result<Vec<uuid::Uuid>, Status>;
+    async fn remove_conn_op(&mut self, tag: Tag, conn_id: &uuid::Uuid) -> Result<(), Status>;
 }
 
 #[async_trait::async_trait]
@@ -273,4 +275,45 @@ impl ConnOp for HandlerClient {
             }
         }
     }
+
+    /// Conn ids of all users in the inbound, emails not in "<uuid>@pony" form are skipped
+    async fn conn_ids_op(&mut self, tag: Tag) -> Result<Vec<uuid::Uuid>, Status> {
+        let request = GetInboundUserRequest {
+            tag: tag.to_string(),
+            email: "".to_string(),
+        };
+
+        let res: GetInboundUserResponse = self
+            .client
+            .get_inbound_users(Request::new(request))
+            .await?
+            .into_inner();
+
+        Ok(res
+            .users
+            .iter()
+            .filter_map(|user| user.email.strip_suffix("@pony"))
+            .filter_map(|id| id.parse().ok())
+            .collect())
+    }
+
+    /// Removes user by email only, works for every proto including Shadowsocks
+    async fn remove_conn_op(&mut self, tag: Tag, conn_id: &uuid::Uuid) -> Result<(), Status> {
+        let operation = RemoveUserOperation {
+            email: format!("{}@pony", conn_id),
+        };
+
+        let request = AlterInboundRequest {
+            tag: tag.to_string(),
+            operation: Some(TypedMessage {
+                r#type: "xray.app.proxyman.command.RemoveUserOperation".to_string(),
+                value: prost::Message::encode_to_vec(&operation),
+            }),
+        };
+
+        self.client
+            .alter_inbound(Request::new(request))
+            .await
+            .map(|_| ())
+    }
 }
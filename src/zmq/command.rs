@@ -0,0 +1,61 @@
+use chrono::Utc;
+use rkyv::{Archive, Deserialize, Serialize};
+use serde::{Deserialize as SerdeDes, Serialize as SerdeSer};
+use std::fmt;
+
+use super::message::RkyvDateTime;
+
+#[derive(Archive, Serialize, Deserialize, SerdeSer, SerdeDes, Debug, Clone, Copy, PartialEq)]
+#[archive(check_bytes)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// Full resync of connections from API
+    Resync,
+    /// Write snapshot immediately
+    Snapshot,
+    /// Reload Xray/WG/H2 configs
+    Reload,
+    /// Reset user stats
+    ResetStat,
+    /// Keep new connections out of the backends, memory still follows the API
+    Drain,
+    /// Add the connections deferred by a drain and stop deferring
+    Undrain,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Resync => write!(f, "Resync"),
+            Kind::Snapshot => write!(f, "Snapshot"),
+            Kind::Reload => write!(f, "Reload"),
+            Kind::ResetStat => write!(f, "ResetStat"),
+            Kind::Drain => write!(f, "Drain"),
+            Kind::Undrain => write!(f, "Undrain"),
+        }
+    }
+}
+
+#[derive(Archive, Serialize, Deserialize, Clone, Debug, SerdeDes)]
+#[archive(check_bytes)]
+pub struct Command {
+    pub id: uuid::Uuid,
+    pub kind: Kind,
+    pub issued_at: RkyvDateTime,
+}
+
+impl Command {
+    pub fn new(kind: Kind) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            kind,
+            issued_at: Utc::now().into(),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} | {} | {:?}", self.id, self.kind, self.issued_at)
+    }
+}
@@ -5,6 +5,7 @@ use rkyv::{Archive, Deserialize, Serialize};
 use serde::{Deserialize as SerdeDes, Serialize as SerdeSer};
 use std::fmt;
 
+use crate::memory::connection::proto::Proto;
 use crate::memory::connection::wireguard::Param as WgParam;
 
 #[derive(Archive, Deserialize, Serialize, Debug, Clone, SerdeDes)]
@@ -72,7 +73,15 @@ impl fmt::Display for Message {
             self.action,
             self.tag,
             match &self.wg {
-                Some(wg) => format!("{} | {}", wg.address, wg.keys.privkey),
+                Some(wg) => format!(
+                    "{} {} | {}",
+                    wg.address,
+                    wg.address_v6
+                        .as_ref()
+                        .map(|a| a.to_string())
+                        .unwrap_or_default(),
+                    wg.keys.pubkey
+                ),
                 None => "-".to_string(),
             },
             match &self.password {
@@ -90,3 +99,16 @@ impl fmt::Display for Message {
         )
     }
 }
+
+impl Message {
+    /// Proto of the connection this message creates, None if required fields are missing
+    pub fn proto(&self) -> Option<Proto> {
+        match self.tag {
+            ProtoTag::Wireguard => self.wg.as_ref().map(Proto::new_wg),
+            ProtoTag::Shadowsocks => self.password.as_deref().map(Proto::new_ss),
+            ProtoTag::Hysteria2 => self.token.as_ref().map(Proto::new_hysteria2),
+            ProtoTag::Mtproto => None,
+            tag => Some(Proto::new_xray(&tag)),
+        }
+    }
+}
@@ -1,4 +1,6 @@
+pub(crate) mod command;
 pub(crate) mod message;
 pub(crate) mod publisher;
+pub(crate) mod socket;
 pub(crate) mod subscriber;
 pub(crate) mod topic;
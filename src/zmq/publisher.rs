@@ -1,14 +1,16 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration};
 use zmq;
-use zmq::Socket;
+use zmq::SocketEvent;
 
+use super::socket::{AsyncSocket, SocketStats};
 use crate::{Error, Topic};
 
 #[derive(Clone)]
 pub struct Publisher {
-    socket: Arc<Mutex<Socket>>,
+    socket: Arc<AsyncSocket>,
+    connected: Arc<AtomicBool>,
 }
 
 impl Publisher {
@@ -26,6 +28,14 @@ impl Publisher {
 
         publisher.set_sndhwm(1000)?;
 
+        // Bound publisher can't tell if anybody listens, connected one tracks the peer
+        let connected = Arc::new(AtomicBool::new(is_bind));
+        if !is_bind {
+            // Don't queue messages to a peer which is not connected yet
+            publisher.set_immediate(true)?;
+            Self::monitor(&context, &publisher, connected.clone())?;
+        }
+
         let mut i = 0;
         loop {
             let result = if is_bind {
@@ -57,21 +67,74 @@ impl Publisher {
         sleep(Duration::from_millis(1000)).await;
 
         Ok(Self {
-            socket: Arc::new(Mutex::new(publisher)),
+            socket: Arc::new(AsyncSocket::new(publisher)?),
+            connected,
         })
     }
 
+    fn monitor(
+        context: &zmq::Context,
+        publisher: &zmq::Socket,
+        connected: Arc<AtomicBool>,
+    ) -> Result<(), Error> {
+        let endpoint = format!("inproc://pub-monitor-{}", uuid::Uuid::new_v4());
+        let events = SocketEvent::CONNECTED.to_raw() | SocketEvent::DISCONNECTED.to_raw();
+        publisher.monitor(&endpoint, events as i32)?;
+
+        let monitor = context.socket(zmq::PAIR)?;
+        monitor.connect(&endpoint)?;
+        let monitor = AsyncSocket::new(monitor)?;
+
+        tokio::spawn(async move {
+            loop {
+                let parts = match monitor.recv_multipart().await {
+                    Ok(parts) => parts,
+                    Err(e) => {
+                        tracing::error!("PUB: Monitor recv failed: {}", e);
+                        break;
+                    }
+                };
+
+                let Some(event) = parts.first().filter(|p| p.len() >= 2) else {
+                    continue;
+                };
+
+                match SocketEvent::from_raw(u16::from_ne_bytes([event[0], event[1]])) {
+                    SocketEvent::CONNECTED => {
+                        tracing::info!("PUB: Peer connected");
+                        connected.store(true, Ordering::Relaxed);
+                    }
+                    SocketEvent::DISCONNECTED => {
+                        tracing::warn!("PUB: Peer disconnected");
+                        connected.store(false, Ordering::Relaxed);
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
     pub async fn new(endpoint: &str) -> Result<Self, Error> {
         Self::bind(endpoint).await
     }
 
     pub async fn send_binary(&self, topic: &Topic, payload: &[u8]) -> zmq::Result<()> {
-        let socket = self.socket.lock().await;
-
-        socket.send(topic.as_str().as_ref(), zmq::SNDMORE)?;
-        socket.send(payload, 0)?;
+        let topic_str = topic.as_str();
+        self.socket
+            .send_multipart(&[topic_str.as_bytes(), payload])
+            .await?;
 
         tracing::debug!("PUB: Message sent: {} | {} bytes", topic, payload.len());
         Ok(())
     }
+
+    pub fn stats(&self) -> &SocketStats {
+        &self.socket.stats
+    }
 }
@@ -0,0 +1,163 @@
+use parking_lot::Mutex;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::unix::AsyncFd;
+use zmq::Socket;
+
+struct ZmqFd(RawFd);
+
+impl AsRawFd for ZmqFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct SocketStats {
+    pub sent: AtomicU64,
+    pub sent_bytes: AtomicU64,
+    pub send_errors: AtomicU64,
+    pub received: AtomicU64,
+    pub received_bytes: AtomicU64,
+    pub recv_errors: AtomicU64,
+}
+
+impl SocketStats {
+    pub fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("sent", self.sent.load(Ordering::Relaxed)),
+            ("sent_bytes", self.sent_bytes.load(Ordering::Relaxed)),
+            ("send_errors", self.send_errors.load(Ordering::Relaxed)),
+            ("received", self.received.load(Ordering::Relaxed)),
+            (
+                "received_bytes",
+                self.received_bytes.load(Ordering::Relaxed),
+            ),
+            ("recv_errors", self.recv_errors.load(Ordering::Relaxed)),
+        ]
+    }
+}
+
+/// ZMQ socket driven by tokio readiness of ZMQ_FD instead of blocking calls.
+/// ZMQ_FD is edge-triggered and signals any state change, so every operation
+/// is attempted with DONTWAIT first and the fd is awaited only on EAGAIN.
+pub struct AsyncSocket {
+    // fd must be deregistered before the socket closes it
+    fd: AsyncFd<ZmqFd>,
+    socket: Mutex<Socket>,
+    pub stats: SocketStats,
+}
+
+impl AsyncSocket {
+    pub fn new(socket: Socket) -> zmq::Result<Self> {
+        let fd = socket.get_fd()?;
+        let fd = AsyncFd::new(ZmqFd(fd)).map_err(|e| {
+            tracing::error!("ZMQ: Failed to register fd in reactor: {}", e);
+            zmq::Error::EINVAL
+        })?;
+
+        Ok(Self {
+            fd,
+            socket: Mutex::new(socket),
+            stats: SocketStats::default(),
+        })
+    }
+
+    async fn wait(&self) -> zmq::Result<()> {
+        let mut guard = self.fd.readable().await.map_err(|e| {
+            tracing::error!("ZMQ: fd readiness failed: {}", e);
+            zmq::Error::EINVAL
+        })?;
+        guard.clear_ready();
+        Ok(())
+    }
+
+    pub async fn recv_multipart(&self) -> zmq::Result<Vec<Vec<u8>>> {
+        loop {
+            let result = self.socket.lock().recv_multipart(zmq::DONTWAIT);
+            match result {
+                Ok(parts) => {
+                    let bytes: usize = parts.iter().map(Vec::len).sum();
+                    self.stats.received.fetch_add(1, Ordering::Relaxed);
+                    self.stats
+                        .received_bytes
+                        .fetch_add(bytes as u64, Ordering::Relaxed);
+                    return Ok(parts);
+                }
+                Err(zmq::Error::EAGAIN) => self.wait().await?,
+                Err(e) => {
+                    self.stats.recv_errors.fetch_add(1, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    pub async fn send_multipart(&self, parts: &[&[u8]]) -> zmq::Result<()> {
+        loop {
+            let result = self
+                .socket
+                .lock()
+                .send_multipart(parts.iter().copied(), zmq::DONTWAIT);
+            match result {
+                Ok(()) => {
+                    let bytes: usize = parts.iter().map(|p| p.len()).sum();
+                    self.stats.sent.fetch_add(1, Ordering::Relaxed);
+                    self.stats
+                        .sent_bytes
+                        .fetch_add(bytes as u64, Ordering::Relaxed);
+                    return Ok(());
+                }
+                // Not reached by PUB, it drops at the high-water mark instead
+                Err(zmq::Error::EAGAIN) => self.wait().await?,
+                Err(e) => {
+                    self.stats.send_errors.fetch_add(1, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_pub_sub_roundtrip() {
+        let ctx = zmq::Context::new();
+        let publisher = ctx.socket(zmq::PUB).unwrap();
+        publisher.bind("inproc://async-socket-test").unwrap();
+        let subscriber = ctx.socket(zmq::SUB).unwrap();
+        subscriber.connect("inproc://async-socket-test").unwrap();
+        subscriber.set_subscribe(b"test").unwrap();
+
+        let publisher = AsyncSocket::new(publisher).unwrap();
+        let subscriber = AsyncSocket::new(subscriber).unwrap();
+
+        // slow joiner: wait until subscription reaches publisher
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let recv = tokio::spawn(async move {
+            let mut received = vec![];
+            for _ in 0..3 {
+                received.push(subscriber.recv_multipart().await.unwrap());
+            }
+            received
+        });
+
+        for i in 0..3u8 {
+            publisher.send_multipart(&[b"test", &[i]]).await.unwrap();
+        }
+
+        let received = tokio::time::timeout(Duration::from_secs(5), recv)
+            .await
+            .expect("recv timed out")
+            .unwrap();
+
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[2], vec![b"test".to_vec(), vec![2]]);
+        assert_eq!(publisher.stats.sent.load(Ordering::Relaxed), 3);
+    }
+}
@@ -1,12 +1,19 @@
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 use zmq::Error;
-use zmq::Socket as ZmqSocket;
 
+use super::socket::{AsyncSocket, SocketStats};
 use super::topic::Topic;
 
+/// First pause after a failed recv, doubled per failure in a row up to the max,
+/// so a broken socket doesn't spin its caller
+const RECV_BACKOFF: Duration = Duration::from_millis(100);
+const RECV_BACKOFF_MAX: Duration = Duration::from_secs(5);
+
 pub struct Subscriber {
-    socket: Arc<Mutex<ZmqSocket>>,
+    socket: Arc<AsyncSocket>,
+    failures: Arc<AtomicU32>,
     pub topics: Vec<Topic>,
 }
 
@@ -31,50 +38,45 @@ impl Subscriber {
         }
 
         Ok(Self {
-            socket: Arc::new(Mutex::new(socket)),
+            socket: Arc::new(AsyncSocket::new(socket)?),
+            failures: Arc::new(AtomicU32::new(0)),
             topics,
         })
     }
 
     pub async fn recv(&self) -> Option<(Vec<u8>, Vec<u8>)> {
-        let socket = self.socket.clone();
-
-        let result = tokio::task::spawn_blocking(move || {
-            let socket = socket.blocking_lock();
-
-            let topic = match socket.recv_bytes(0) {
-                Ok(t) => t,
-                Err(e) => {
-                    tracing::error!("ZMQ recv topic failed: {}", e);
-                    return None;
-                }
-            };
-
-            let payload = match socket.recv_bytes(0) {
-                Ok(p) => p,
-                Err(e) => {
-                    tracing::error!("ZMQ recv payload failed: {}", e);
-                    return None;
-                }
-            };
-
-            Some((topic, payload))
-        })
-        .await;
-
-        match result {
-            Ok(Some(pair)) => Some(pair),
-            Ok(None) => {
-                tracing::warn!("ZMQ multipart recv returned None");
+        match self.socket.recv_multipart().await {
+            Ok(mut parts) if parts.len() == 2 => {
+                self.failures.store(0, Ordering::Relaxed);
+                let payload = parts.pop()?;
+                let topic = parts.pop()?;
+                Some((topic, payload))
+            }
+            Ok(parts) => {
+                self.socket
+                    .stats
+                    .recv_errors
+                    .fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("ZMQ multipart recv got {} frames, expected 2", parts.len());
                 None
             }
             Err(e) => {
-                tracing::error!("ZMQ recv_multipart join error: {}", e);
+                // Counted in recv_errors by the socket
+                let failures = self.failures.fetch_add(1, Ordering::Relaxed);
+                let backoff = RECV_BACKOFF
+                    .saturating_mul(1 << failures.min(6))
+                    .min(RECV_BACKOFF_MAX);
+                tracing::error!("ZMQ recv failed: {}, retrying in {:?}", e, backoff);
+                tokio::time::sleep(backoff).await;
                 None
             }
         }
     }
 
+    pub fn stats(&self) -> &SocketStats {
+        &self.socket.stats
+    }
+
     pub fn new_bound(endpoint: &str, topics: Vec<Topic>) -> Result<Self, Error> {
         let context = zmq::Context::new();
         let socket = context
@@ -92,7 +94,8 @@ impl Subscriber {
         tracing::debug!("Subscribed to topics: {:?}", topics);
 
         Ok(Self {
-            socket: Arc::new(Mutex::new(socket)),
+            socket: Arc::new(AsyncSocket::new(socket)?),
+            failures: Arc::new(AtomicU32::new(0)),
             topics,
         })
     }
@@ -102,6 +105,7 @@ impl Clone for Subscriber {
     fn clone(&self) -> Self {
         Self {
             socket: Arc::clone(&self.socket),
+            failures: Arc::clone(&self.failures),
             topics: self.topics.clone(),
         }
     }
@@ -11,6 +11,8 @@ pub enum Topic {
     Metrics,
     Updates(Env),
     Init(uuid::Uuid),
+    Command(uuid::Uuid),
+    EnvCommand(Env),
 }
 
 impl fmt::Display for Topic {
@@ -20,6 +22,8 @@ impl fmt::Display for Topic {
             Self::Metrics => write!(f, "metrics",),
             Self::Updates(env) => write!(f, "updates-{}", env),
             Self::Init(uuid) => write!(f, "init-{}", uuid),
+            Self::Command(uuid) => write!(f, "cmd-{}", uuid),
+            Self::EnvCommand(env) => write!(f, "cmd-env-{}", env),
         }
     }
 }
@@ -50,6 +54,17 @@ impl FromStr for Topic {
             return Ok(Topic::Init(id));
         }
 
+        if let Some(env_str) = s.strip_prefix("cmd-env-") {
+            let env = Env::from_str(env_str)?;
+            return Ok(Topic::EnvCommand(env));
+        }
+
+        if let Some(uuid_str) = s.strip_prefix("cmd-") {
+            let id = uuid::Uuid::parse_str(uuid_str)
+                .map_err(|_| Error::Custom("Invalid UUID in topic".into()))?;
+            return Ok(Topic::Command(id));
+        }
+
         Err(Error::Custom(format!("Unknown topic string: {}", s)))
     }
 }
@@ -87,6 +102,8 @@ impl Topic {
             Topic::Metrics => "metrics".to_string(),
             Topic::Updates(s) => format!("updates-{}", s),
             Topic::Init(s) => format!("init-{}", s),
+            Topic::Command(s) => format!("cmd-{}", s),
+            Topic::EnvCommand(s) => format!("cmd-env-{}", s),
         }
     }
 
@@ -96,8 +113,14 @@ impl Topic {
             Topic::Metrics => Cow::Borrowed("metrics"),
             Topic::Updates(env) => format!("updates-{}", env).into(),
             Topic::Init(uuid) => Cow::Owned(format!("init-{}", uuid)),
+            Topic::Command(uuid) => Cow::Owned(format!("cmd-{}", uuid)),
+            Topic::EnvCommand(env) => Cow::Owned(format!("cmd-env-{}", env)),
         }
     }
+    pub fn is_command(&self) -> bool {
+        matches!(self, Topic::Command(_) | Topic::EnvCommand(_))
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         match self {
             Topic::Auth => b"auth".to_vec(),
@@ -106,3 +129,29 @@ impl Topic {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_topic_roundtrip() {
+        let id = uuid::Uuid::new_v4();
+        let topics = vec![
+            Topic::Command(id),
+            Topic::EnvCommand(Env::Dev),
+            Topic::Init(id),
+            Topic::Updates(Env::Production),
+        ];
+
+        for topic in topics {
+            assert_eq!(topic.to_string().parse::<Topic>().unwrap(), topic);
+            assert_eq!(topic.as_string(), topic.to_string());
+        }
+    }
+
+    #[test]
+    fn test_command_topic_invalid_uuid() {
+        assert!("cmd-not-a-uuid".parse::<Topic>().is_err());
+    }
+}